@@ -0,0 +1,18 @@
+//! Runtime API definition for the subdex XCMP pallet.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use sp_std::prelude::*;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexXCMPApi<AssetId, Balance> where
+        AssetId: Codec,
+        Balance: Codec,
+    {
+        /// Amount of the parachain asset, held by our sovereign account on the given parachain.
+        fn backed_amount(para_id: u32, para_asset_id: Option<AssetId>) -> Balance;
+
+        /// All (para id, parachain asset id, backed amount) ledger entries.
+        fn backing_ledger() -> Vec<(u32, Option<AssetId>, Balance)>;
+    }
+}
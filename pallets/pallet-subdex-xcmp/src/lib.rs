@@ -18,8 +18,16 @@
 //! downward messages.
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use frame_support::{decl_error, decl_event, decl_module, decl_storage, ensure, traits::Currency};
-use frame_system::ensure_signed;
+use frame_support::{
+    decl_error, decl_event, decl_module, decl_storage,
+    dispatch::{DispatchError, DispatchResult},
+    ensure,
+    storage::with_transaction,
+    traits::{Currency, Get},
+    weights::Weight,
+    IterableStorageMap,
+};
+use frame_system::{ensure_root, ensure_signed, RawOrigin};
 
 use codec::{Codec, Decode, Encode};
 use cumulus_primitives::{
@@ -29,13 +37,120 @@ use cumulus_primitives::{
 };
 use cumulus_upward_message::BalancesMessage;
 pub use pallet_subdex::Asset;
-pub use sp_arithmetic::traits::{One, Zero};
+pub use sp_arithmetic::traits::{
+    CheckedAdd, CheckedSub, One, Saturating, UniqueSaturatedFrom, UniqueSaturatedInto, Zero,
+};
+use sp_runtime::TransactionOutcome;
+use sp_std::prelude::*;
+
+mod message;
+mod xcm;
+pub use message::{
+    DepositInstruction, PriceQuote, QueryId, TransferDeadline, TransferId, VersionedXCMPMessage,
+    XCMPMessage, XCMPMessageV0, XCMPVersion,
+};
+pub use xcm::{Junction, MultiAsset, MultiLocation};
+
+#[cfg(test)]
+mod mock;
+
+#[cfg(test)]
+mod simulator;
+
+#[cfg(test)]
+mod tests;
+
+/// Id of the inbound message, parked after a failed processing attempt.
+pub type FailedMessageId = u64;
+
+/// Id of the inbound deposit, quarantined for exceeding deposit limits.
+pub type QuarantineId = u64;
+
+/// Reason, why an inbound message could not be processed.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FailureReason {
+    /// Receiver balance would overflow after the deposit.
+    BalanceOverflow,
+    /// No more internal asset ids can be allocated.
+    AssetIdOverflow,
+    /// Backed amount of the parachain asset would overflow after the deposit.
+    BackingOverflow,
+    /// XCM-style asset location is unknown or its reserve is not the sending parachain.
+    UnknownAssetLocation,
+}
+
+/// Inbound message, which can be reprocessed or refunded to its origin.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum InboundMessage<XAccountId, XBalance, XAssetIdOf> {
+    /// Main currency transfer from the relay chain.
+    Downward(
+        XAccountId,
+        XBalance,
+        DepositInstruction<XBalance, XAssetIdOf>,
+    ),
+    /// XCMP message from the given parachain.
+    XCMP(ParaId, XCMPMessage<XAccountId, XBalance, XAssetIdOf>),
+}
+
+impl<XAccountId: Clone, XBalance: Copy, XAssetIdOf: Copy>
+    InboundMessage<XAccountId, XBalance, XAssetIdOf>
+{
+    /// Account, the message funds are addressed to. None, if message carries no funds.
+    pub fn owner(&self) -> Option<&XAccountId> {
+        match self {
+            InboundMessage::Downward(dest, _, _) => Some(dest),
+            InboundMessage::XCMP(_, msg) => msg.owner(),
+        }
+    }
+}
+
+/// Inbound message, parked after a failed processing attempt.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct FailedMessage<XAccountId, XBalance, XAssetIdOf> {
+    pub message: InboundMessage<XAccountId, XBalance, XAssetIdOf>,
+    pub reason: FailureReason,
+}
+
+/// Governance-set caps on deposits of the parachain asset.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct DepositLimits<XBalance, XBlockNumber> {
+    /// Maximum amount, deposited by a single message
+    pub max_per_message: XBalance,
+    /// Maximum amount, deposited within a single window
+    pub max_per_window: XBalance,
+    /// Window length in blocks (1 limits deposits per block), can not be zero
+    pub window: XBlockNumber,
+}
+
+/// Inbound deposit message, held until released by root.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct QuarantinedDeposit<XAccountId, XBalance, XAssetIdOf> {
+    pub src: ParaId,
+    pub message: XCMPMessage<XAccountId, XBalance, XAssetIdOf>,
+}
 
-#[derive(Encode, Decode)]
-pub enum XCMPMessage<XAccountId, XBalance, XAssetIdOf> {
-    /// Transfer tokens to the given account from the Parachain account.
-    /// When XAssetIdOf is None, treat message as main currency transfer.
-    TransferToken(XAccountId, XBalance, Option<XAssetIdOf>),
+/// Outbound parachain transfer, awaiting acknowledgement from the destination parachain.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct PendingTransfer<T: Trait> {
+    /// Account, the transfer is refunded to
+    pub sender: T::AccountId,
+    pub para_id: ParaId,
+    /// Dex asset, slashed from the sender
+    pub asset: Asset<AssetIdOf<T>>,
+    /// Asset id on the destination parachain (None for main currency)
+    pub para_asset_id: Option<AssetIdOf<T>>,
+    pub amount: BalanceOf<T>,
+    /// Block, the transfer is refunded at, unless acknowledged before
+    pub deadline: T::BlockNumber,
+}
+
+/// Where the output of `swap_and_send` is delivered to.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum SwapOutputDestination<XAssetIdOf> {
+    /// Main currency is sent to the relay chain.
+    RelayChain,
+    /// Parachain asset is sent back to its origin parachain.
+    Parachain(u32, Option<XAssetIdOf>),
 }
 
 pub type BalanceOf<T> = <<T as pallet_subdex::Trait>::Currency as Currency<
@@ -44,6 +159,20 @@ pub type BalanceOf<T> = <<T as pallet_subdex::Trait>::Currency as Currency<
 
 pub type AssetIdOf<T> = <T as pallet_subdex::Trait>::AssetId;
 
+pub type VersionedXCMPMessageOf<T> =
+    VersionedXCMPMessage<<T as frame_system::Trait>::AccountId, BalanceOf<T>, AssetIdOf<T>>;
+
+pub type InboundMessageOf<T> =
+    InboundMessage<<T as frame_system::Trait>::AccountId, BalanceOf<T>, AssetIdOf<T>>;
+
+pub type FailedMessageOf<T> =
+    FailedMessage<<T as frame_system::Trait>::AccountId, BalanceOf<T>, AssetIdOf<T>>;
+
+pub type DepositLimitsOf<T> = DepositLimits<BalanceOf<T>, <T as frame_system::Trait>::BlockNumber>;
+
+pub type QuarantinedDepositOf<T> =
+    QuarantinedDeposit<<T as frame_system::Trait>::AccountId, BalanceOf<T>, AssetIdOf<T>>;
+
 /// Configuration trait of this pallet.
 pub trait Trait: frame_system::Trait + pallet_subdex::Trait {
     /// Event type used by the runtime.
@@ -57,8 +186,26 @@ pub trait Trait: frame_system::Trait + pallet_subdex::Trait {
 
     /// The sender of XCMP messages.
     type XCMPMessageSender: XCMPMessageSender<
-        XCMPMessage<Self::AccountId, BalanceOf<Self>, AssetIdOf<Self>>,
+        VersionedXCMPMessage<Self::AccountId, BalanceOf<Self>, AssetIdOf<Self>>,
     >;
+
+    /// Maximum number of price queries, answered to a single parachain per block.
+    type MaxPriceQueriesPerBlock: Get<u32>;
+
+    /// Number of blocks, after which unacknowledged outbound transfer is refunded.
+    type TransferTimeout: Get<Self::BlockNumber>;
+
+    /// Time, within which the destination parachain accepts tracked transfer.
+    /// Must be shorter than `TransferTimeout` blocks of the timestamp `MinimumPeriod`, which is checked
+    /// by the integrity test, and should be shorter by more than the clock drift between parachains,
+    /// so that the transfer can not be deposited after it is refunded.
+    type TransferDeliveryPeriod: Get<Self::IMoment>;
+
+    /// Maximum number of in-flight transfers, sent by a single account.
+    type MaxPendingTransfersPerAccount: Get<u32>;
+
+    /// Maximum number of tracked transfers, sent in a single block, and so refunded in a single block.
+    type MaxTrackedTransfersPerBlock: Get<u32>;
 }
 
 // This pallet's storage items.
@@ -71,6 +218,73 @@ decl_storage! {
 
         // Next dex parachain asset id
         pub NextAssetId get(fn next_asset_id) config(): AssetIdOf<T>;
+
+        // Amount of the parachain asset, held by our sovereign account on the given parachain
+        pub BackedAmounts get(fn backed_amounts):
+            map hasher(blake2_128_concat) (ParaId, Option<AssetIdOf<T>>) => BalanceOf<T>;
+
+        // Main currency amount, sent to the given parachain and not returned yet
+        pub MainCurrencyInTransit get(fn main_currency_in_transit): map hasher(twox_64_concat) ParaId => BalanceOf<T>;
+
+        // Inbound messages, which failed to be processed
+        pub FailedMessages get(fn failed_messages):
+            map hasher(twox_64_concat) FailedMessageId => Option<FailedMessageOf<T>>;
+
+        // Next failed message id
+        pub NextFailedMessageId get(fn next_failed_message_id): FailedMessageId;
+
+        // Block number and amount of price queries, answered to the given parachain in that block
+        pub PriceQueries get(fn price_queries): map hasher(twox_64_concat) ParaId => (T::BlockNumber, u32);
+
+        // Older and newer cumulative price observations with their timestamps, taken while answering price queries
+        pub PriceObservations get(fn price_observations):
+            map hasher(blake2_128_concat) (Asset<AssetIdOf<T>>, Asset<AssetIdOf<T>>)
+            => (Option<(BalanceOf<T>, BalanceOf<T>)>, Option<(BalanceOf<T>, BalanceOf<T>)>);
+
+        // XCMP message version, understood by the given parachain
+        pub XCMPVersions get(fn xcmp_version): map hasher(twox_64_concat) ParaId => XCMPVersion;
+
+        // Outbound transfers, awaiting acknowledgement from the destination parachain
+        pub PendingTransfers get(fn pending_transfers):
+            map hasher(twox_64_concat) TransferId => Option<PendingTransfer<T>>;
+
+        // Next outbound transfer id
+        pub NextTransferId get(fn next_transfer_id): TransferId;
+
+        // Ids of the in-flight transfers, sent by the given account
+        pub AccountPendingTransfers get(fn account_pending_transfers):
+            map hasher(blake2_128_concat) T::AccountId => Vec<TransferId>;
+
+        // Ids of the in-flight transfers, refunded at the given block
+        pub TransferDeadlines get(fn transfer_deadlines):
+            map hasher(twox_64_concat) T::BlockNumber => Vec<TransferId>;
+
+        // Deposit caps of the parachain asset. Deposits are not limited, if not set
+        pub DepositLimitsByAsset get(fn deposit_limits):
+            map hasher(blake2_128_concat) (ParaId, Option<AssetIdOf<T>>) => Option<DepositLimitsOf<T>>;
+
+        // Start block of the current deposit window and amount, deposited within it
+        pub DepositWindows get(fn deposit_windows):
+            map hasher(blake2_128_concat) (ParaId, Option<AssetIdOf<T>>) => (T::BlockNumber, BalanceOf<T>);
+
+        // Maximum backed amount of the parachain asset, i.e. issuance of its dex asset on our chain.
+        // Issuance is not limited, if not set
+        pub IssuanceCeilings get(fn issuance_ceiling):
+            map hasher(blake2_128_concat) (ParaId, Option<AssetIdOf<T>>) => Option<BalanceOf<T>>;
+
+        // Inbound deposits, exceeding deposit limits
+        pub QuarantinedDeposits get(fn quarantined_deposits):
+            map hasher(twox_64_concat) QuarantineId => Option<QuarantinedDepositOf<T>>;
+
+        // Next quarantined deposit id
+        pub NextQuarantineId get(fn next_quarantine_id): QuarantineId;
+
+        // Parachain asset, identified by the non-canonical XCM-style location
+        pub ParaAssetByLocation get(fn para_asset_by_location):
+            map hasher(blake2_128_concat) MultiLocation => Option<(ParaId, Option<AssetIdOf<T>>)>;
+
+        // Whether plain transfers to the given parachain are sent as XCM-style asset transfers
+        pub MultiAssetTransfers get(fn multi_asset_transfers): map hasher(twox_64_concat) ParaId => bool;
     }
 }
 
@@ -81,8 +295,9 @@ decl_event! {
         // None if main currency
         ParaChainAssetId = Option<AssetIdOf<T>>,
         // Our internal para asset id representation
-        DexAssetId = AssetIdOf<T>
-
+        DexAssetId = AssetIdOf<T>,
+        DexAsset = Asset<AssetIdOf<T>>,
+        PriceQuote = PriceQuote<BalanceOf<T>>,
     {
         /// Transferred main currency amount to the account on the relay chain.
         TransferredTokensToRelayChain(AccountId, Balance),
@@ -90,11 +305,86 @@ decl_event! {
         /// Transferred main currency amount  to the account on request from the relay chain.
         TransferredTokensFromRelayChain(AccountId, Balance),
 
+        /// Transferred main currency amount to the account on the given parachain.
+        TransferredTokensToParachain(ParaId, AccountId, Balance),
+
+        /// Main currency amount, sent to the given parachain earlier, was returned to the account.
+        TransferredTokensFromParachain(ParaId, AccountId, Balance),
+
         /// Transferred custom asset to the account from the given parachain account.
         DepositAssetViaXCMP(ParaId, ParaChainAssetId, AccountId, DexAssetId, Balance),
 
         /// Transferred custom asset to the account from the given parachain account.
         WithdrawAssetViaXCMP(ParaId, ParaChainAssetId, AccountId, DexAssetId, Balance),
+
+        /// Inbound message failed to be processed and was parked under the given id.
+        MessageParked(FailedMessageId, FailureReason),
+
+        /// Parked inbound message was successfully reprocessed.
+        ParkedMessageRetried(FailedMessageId),
+
+        /// Parked inbound message funds were sent back to its origin.
+        ParkedMessageRefunded(FailedMessageId),
+
+        /// XCMP message version, used for the given parachain, was updated.
+        XCMPVersionSet(ParaId, XCMPVersion),
+
+        /// Swap on arrival failed, deposited asset is kept by the account.
+        SwapOnArrivalFailed(ParaId, AccountId, DexAsset, Balance),
+
+        /// Instruction from the relay chain transfer remark failed, deposited main currency is kept by the account.
+        DepositInstructionFailed(AccountId, Balance),
+
+        /// Price query from the given parachain was answered.
+        PriceQueryAnswered(ParaId, QueryId),
+
+        /// Price query from the given parachain was dropped, as it exceeds per block queries limit.
+        PriceQueryRateLimited(ParaId, QueryId),
+
+        /// Response to the price query could not be sent to the given parachain.
+        PriceQueryResponseFailed(ParaId, QueryId),
+
+        /// Price data was received from the given parachain in response to the query.
+        PriceResponseReceived(ParaId, QueryId, Option<PriceQuote>),
+
+        /// Outbound transfer awaits acknowledgement from the given parachain.
+        TransferPending(ParaId, TransferId, AccountId),
+
+        /// Outbound transfer was acknowledged by the destination parachain.
+        TransferAcknowledged(ParaId, TransferId),
+
+        /// Outbound transfer was rejected by the destination parachain.
+        TransferRejected(ParaId, TransferId),
+
+        /// Outbound transfer was not acknowledged in time.
+        TransferTimedOut(TransferId),
+
+        /// Outbound transfer amount was returned to the sender.
+        TransferRefunded(TransferId, AccountId, Balance),
+
+        /// Outbound transfer could not be refunded, as sender balance would overflow. Transfer stays pending.
+        TransferRefundFailed(TransferId),
+
+        /// Acknowledgement of the inbound tracked transfer could not be sent to the given parachain.
+        TransferReplyFailed(ParaId, TransferId),
+
+        /// Deposit limits of the given parachain asset were updated.
+        DepositLimitsSet(ParaId, ParaChainAssetId),
+
+        /// Issuance ceiling of the given parachain asset was updated.
+        IssuanceCeilingSet(ParaId, ParaChainAssetId),
+
+        /// Inbound deposit exceeds deposit limits and was quarantined under the given id.
+        DepositQuarantined(QuarantineId, ParaId, ParaChainAssetId, Balance),
+
+        /// Quarantined deposit was released by root.
+        QuarantinedDepositReleased(QuarantineId),
+
+        /// Parachain asset, identified by the given XCM-style location, was updated.
+        AssetLocationSet(MultiLocation),
+
+        /// XCM-style asset transfers to the given parachain were enabled or disabled.
+        MultiAssetTransfersSet(ParaId, bool),
     }
 }
 
@@ -103,6 +393,37 @@ decl_module! {
 
         fn deposit_event() = default;
 
+        fn integrity_test() {
+            // Blocks are at least `MinimumPeriod` apart, so the timeout lasts at least that many periods
+            let timeout_blocks: u64 = T::TransferTimeout::get().unique_saturated_into();
+            let min_timeout_period =
+                T::IMoment::from(<T as pallet_timestamp::Trait>::MinimumPeriod::get())
+                    .saturating_mul(T::IMoment::unique_saturated_from(timeout_blocks));
+            assert!(
+                T::TransferDeliveryPeriod::get() < min_timeout_period,
+                "Tracked transfer must expire before it is refunded on timeout"
+            );
+        }
+
+        /// Refund outbound transfers, not acknowledged until this block.
+        /// Their number is bounded by `MaxTrackedTransfersPerBlock`.
+        fn on_initialize(now: T::BlockNumber) -> Weight {
+            let expired_transfers = <TransferDeadlines<T>>::take(now);
+
+            for transfer_id in &expired_transfers {
+                if let Some(pending_transfer) = Self::pending_transfers(transfer_id) {
+                    Self::deposit_event(Event::<T>::TransferTimedOut(*transfer_id));
+                    Self::refund_pending_transfer(*transfer_id, pending_transfer);
+                }
+            }
+
+            // Deadline entry is taken. Every refund reads the pending transfer, its deadline, sender balance,
+            // backing or main currency in transit and sender transfers, writing all of them but the deadline,
+            // and the event.
+            let refunds = expired_transfers.len() as Weight;
+            T::DbWeight::get().reads_writes(1 + 5 * refunds, 1 + 5 * refunds)
+        }
+
         /// Transfer `amount` of main currency on the relay chain from the Parachain account to
         /// the given `dest` account.
         #[weight = 10]
@@ -113,18 +434,54 @@ decl_module! {
 
             <pallet_subdex::Module<T>>::ensure_sufficient_balance(&sender, Asset::MainNetworkCurrency, amount)?;
 
+            // Message is sent before slashing, so nothing has to be rolled back if the broker rejects it.
+            Self::send_upward_transfer(dest.clone(), amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            <pallet_subdex::Module<T>>::slash_asset(&sender, Asset::MainNetworkCurrency, amount);
+
+            Self::deposit_event(Event::<T>::TransferredTokensToRelayChain(dest, amount));
+        }
+
+        /// Transfer `amount` of main currency to the given `dest` account on the `para_id` parachain.
+        #[weight = 10]
+        fn transfer_balance_to_parachain_chain(
+            origin,
+            para_id: u32,
+            dest: T::AccountId,
+            amount: BalanceOf<T>,
+        ) {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_non_zero_balance(amount)?;
+
+            let para_id: ParaId = para_id.into();
+
+            <pallet_subdex::Module<T>>::ensure_sufficient_balance(&sender, Asset::MainNetworkCurrency, amount)?;
+
+            let in_transit_amount = Self::main_currency_in_transit(para_id)
+                .checked_add(&amount)
+                .ok_or(Error::<T>::InTransitAmountOverflow)?;
+
+            // Message is sent before slashing, so nothing has to be rolled back if the broker rejects it.
+            let transfer_id = Self::send_transfer_to_parachain(&sender, para_id, dest.clone(), amount, None)?;
+
             //
             // == MUTATION SAFE ==
             //
 
             <pallet_subdex::Module<T>>::slash_asset(&sender, Asset::MainNetworkCurrency, amount);
 
+            <MainCurrencyInTransit<T>>::insert(para_id, in_transit_amount);
 
-            let msg = <T as Trait>::UpwardMessage::transfer(dest.clone(), amount);
-            <T as Trait>::UpwardMessageSender::send_upward_message(&msg, UpwardMessageOrigin::Signed)
-                .expect("Should not fail; qed");
+            if let Some(transfer_id) = transfer_id {
+                Self::add_pending_transfer(transfer_id, sender, para_id, Asset::MainNetworkCurrency, None, amount);
+            }
 
-            Self::deposit_event(Event::<T>::TransferredTokensToRelayChain(dest, amount));
+            Self::deposit_event(Event::<T>::TransferredTokensToParachain(para_id, dest, amount));
         }
 
         // Transfer an `amount` of another parachain asset.
@@ -136,8 +493,6 @@ decl_module! {
             para_asset_id: Option<AssetIdOf<T>>,
             amount: BalanceOf<T>,
         ) {
-
-            //TODO we don't make sure that the parachain has some tokens on the other parachain.
             let who = ensure_signed(origin)?;
 
             Self::ensure_non_zero_balance(amount)?;
@@ -149,137 +504,1156 @@ decl_module! {
 
             <pallet_subdex::Module<T>>::ensure_sufficient_balance(&who, Asset::ParachainAsset(asset_id), amount)?;
 
+            // Make sure our sovereign account holds enough tokens on the other parachain
+            let backed_amount = Self::ensure_sufficient_backing(para_id, para_asset_id, amount)?;
+
+            // Message is sent before slashing, so nothing has to be rolled back if the broker rejects it.
+            let transfer_id = Self::send_transfer_to_parachain(&who, para_id, dest.clone(), amount, para_asset_id)?;
+
             //
             // == MUTATION SAFE ==
             //
 
             <pallet_subdex::Module<T>>::slash_asset(&who, Asset::ParachainAsset(asset_id), amount);
 
-            T::XCMPMessageSender::send_xcmp_message(
-                para_id,
-                &XCMPMessage::TransferToken(dest.clone(), amount, para_asset_id),
-            ).expect("Should not fail; qed");
+            <BackedAmounts<T>>::insert((para_id, para_asset_id), backed_amount);
+
+            if let Some(transfer_id) = transfer_id {
+                Self::add_pending_transfer(
+                    transfer_id,
+                    who,
+                    para_id,
+                    Asset::ParachainAsset(asset_id),
+                    para_asset_id,
+                    amount,
+                );
+            }
 
             Self::deposit_event(Event::<T>::WithdrawAssetViaXCMP(para_id, para_asset_id, dest, asset_id, amount));
         }
 
-    }
-}
+        /// Swap `asset_in_amount` of `asset_in` and send the output to the `dest` account
+        /// on the relay chain (main currency) or on the asset origin parachain.
+        /// Nothing is swapped, if the output can not be sent.
+        #[weight = 10]
+        fn swap_and_send(
+            origin,
+            asset_in: Asset<AssetIdOf<T>>,
+            asset_in_amount: BalanceOf<T>,
+            destination: SwapOutputDestination<AssetIdOf<T>>,
+            min_asset_out_amount: BalanceOf<T>,
+            dest: T::AccountId,
+        ) {
+            let sender = ensure_signed(origin)?;
 
-/// This is a hack to convert from one generic type to another where we are sure that both are the
-/// same type/use the same encoding.
-fn convert_hack<O: Decode>(input: &impl Encode) -> O {
-    input.using_encoded(|e| Decode::decode(&mut &e[..]).expect("Must be compatible; qed"))
-}
+            Self::ensure_non_zero_balance(asset_in_amount)?;
 
-impl<T: Trait> DownwardMessageHandler for Module<T> {
-    /// Transfer main network asset into dex parachain from the relay chain (natively supported via Currency trait)
-    fn handle_downward_message(msg: &DownwardMessage) {
-        if let DownwardMessage::TransferInto(dest, amount, _) = msg {
-            let dest = convert_hack(&dest);
-            let amount: BalanceOf<T> = convert_hack(amount);
+            match destination {
+                SwapOutputDestination::RelayChain => {
+                    let prepared_swap = <pallet_subdex::Module<T>>::prepare_swap(
+                        &sender,
+                        asset_in,
+                        asset_in_amount,
+                        Asset::MainNetworkCurrency,
+                        min_asset_out_amount,
+                    )?;
+                    let amount = prepared_swap.asset_out_amount;
 
-            <pallet_subdex::Module<T>>::ensure_can_hold_balance(
-                &dest,
-                Asset::MainNetworkCurrency,
-                amount,
-            )
-            .expect("Should not fail!");
+                    Self::ensure_non_zero_balance(amount)?;
+
+                    // Message is sent before swapping, so nothing has to be rolled back if the broker rejects it.
+                    Self::send_upward_transfer(dest.clone(), amount)?;
+
+                    //
+                    // == MUTATION SAFE ==
+                    //
+
+                    <pallet_subdex::Module<T>>::apply_swap(&sender, prepared_swap);
+
+                    <pallet_subdex::Module<T>>::slash_asset(&sender, Asset::MainNetworkCurrency, amount);
+
+                    Self::deposit_event(Event::<T>::TransferredTokensToRelayChain(dest, amount));
+                }
+                SwapOutputDestination::Parachain(para_id, para_asset_id) => {
+                    let para_id: ParaId = para_id.into();
+
+                    // Retreive our internal para asset id representation
+                    let asset_id = Self::ensure_asset_id_exists(para_id, para_asset_id)?;
+
+                    let prepared_swap = <pallet_subdex::Module<T>>::prepare_swap(
+                        &sender,
+                        asset_in,
+                        asset_in_amount,
+                        Asset::ParachainAsset(asset_id),
+                        min_asset_out_amount,
+                    )?;
+                    let amount = prepared_swap.asset_out_amount;
+
+                    Self::ensure_non_zero_balance(amount)?;
+
+                    // Make sure our sovereign account holds enough tokens on the other parachain
+                    let backed_amount = Self::ensure_sufficient_backing(para_id, para_asset_id, amount)?;
+
+                    // Message is sent before swapping, so nothing has to be rolled back if the broker rejects it.
+                    let transfer_id = Self::send_transfer_to_parachain(&sender, para_id, dest.clone(), amount, para_asset_id)?;
+
+                    //
+                    // == MUTATION SAFE ==
+                    //
+
+                    <pallet_subdex::Module<T>>::apply_swap(&sender, prepared_swap);
+
+                    <pallet_subdex::Module<T>>::slash_asset(&sender, Asset::ParachainAsset(asset_id), amount);
+
+                    <BackedAmounts<T>>::insert((para_id, para_asset_id), backed_amount);
+
+                    if let Some(transfer_id) = transfer_id {
+                        Self::add_pending_transfer(
+                            transfer_id,
+                            sender,
+                            para_id,
+                            Asset::ParachainAsset(asset_id),
+                            para_asset_id,
+                            amount,
+                        );
+                    }
+
+                    Self::deposit_event(Event::<T>::WithdrawAssetViaXCMP(para_id, para_asset_id, dest, asset_id, amount));
+                }
+            }
+        }
+
+        /// Process parked inbound message once again. Can be called by root or message funds owner.
+        #[weight = 10]
+        fn retry_failed_message(origin, failed_message_id: FailedMessageId) {
+            let failed_message = Self::ensure_failed_message_exists(failed_message_id)?;
+
+            Self::ensure_root_or_owner(origin, failed_message.message.owner())?;
+
+            Self::process_inbound_message(&failed_message.message)
+                .map_err(|_| Error::<T>::FailedMessageProcessingFailed)?;
 
             //
             // == MUTATION SAFE ==
             //
 
-            <pallet_subdex::Module<T>>::mint_asset(&dest, Asset::MainNetworkCurrency, amount);
+            <FailedMessages<T>>::remove(failed_message_id);
 
-            Self::deposit_event(Event::<T>::TransferredTokensFromRelayChain(dest, amount));
+            Self::deposit_event(Event::<T>::ParkedMessageRetried(failed_message_id));
         }
-    }
-}
 
-impl<T: Trait> XCMPMessageHandler<XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>>
-    for Module<T>
-{
-    // Transfer main currency or custom asset from other parachain to our chain
-    fn handle_xcmp_message(
-        src: ParaId,
-        msg: &XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
-    ) {
-        let asset_id = match msg {
-            XCMPMessage::TransferToken(dest, amount, para_asset_id)
-                if <AssetIdByParaAssetId<T>>::contains_key(src, para_asset_id) =>
-            {
-                let asset_id = Self::asset_id_by_para_asset_id(src, para_asset_id);
+        /// Send parked inbound message funds back to its origin. Can be called by root or message funds owner.
+        #[weight = 10]
+        fn refund_failed_message(origin, failed_message_id: FailedMessageId) {
+            let failed_message = Self::ensure_failed_message_exists(failed_message_id)?;
 
-                <pallet_subdex::Module<T>>::ensure_can_hold_balance(
-                    &dest,
-                    Asset::ParachainAsset(asset_id),
-                    *amount,
-                )
-                .expect("Should not fail!");
-                Some(asset_id)
+            Self::ensure_root_or_owner(origin, failed_message.message.owner())?;
+
+            // Refund is sent before removing the message, so nothing has to be rolled back if the broker rejects it.
+            match failed_message.message {
+                InboundMessage::Downward(dest, amount, _) => {
+                    Self::send_upward_transfer(dest, amount)?;
+                }
+                InboundMessage::XCMP(src, msg) => {
+                    let refund_msg = Self::resolve_multi_asset_message(src, &msg)
+                        .unwrap_or(msg)
+                        .refund(src)
+                        .ok_or(Error::<T>::NothingToRefund)?;
+
+                    // XCM-style asset transfer could only arrive in V1 envelope, so it is sent back in V1 too
+                    let version = match refund_msg {
+                        XCMPMessage::TransferMultiAsset(..) => XCMPVersion::V1,
+                        _ => Self::xcmp_version(src),
+                    };
+                    Self::send_versioned_xcmp_message(src, refund_msg, version)?;
+                }
             }
-            _ => None,
-        };
 
-        //
-        // == MUTATION SAFE ==
-        //
+            //
+            // == MUTATION SAFE ==
+            //
 
-        match msg {
-            XCMPMessage::TransferToken(dest, amount, para_asset_id) => {
-                if let Some(asset_id) = asset_id {
-                    <pallet_subdex::Module<T>>::mint_asset(
-                        &dest,
-                        Asset::ParachainAsset(asset_id),
-                        *amount,
-                    );
-                    Self::deposit_event(Event::<T>::DepositAssetViaXCMP(
-                        src,
-                        // para asset_id
-                        *para_asset_id,
-                        dest.clone(),
-                        // internal asset id representation
-                        asset_id,
-                        *amount,
-                    ));
-                } else {
-                    let next_asset_id = Self::next_asset_id();
-                    <AssetIdByParaAssetId<T>>::insert(src, *para_asset_id, next_asset_id);
+            <FailedMessages<T>>::remove(failed_message_id);
 
-                    <pallet_subdex::Module<T>>::mint_asset(
-                        &dest,
-                        Asset::ParachainAsset(next_asset_id),
-                        *amount,
-                    );
+            Self::deposit_event(Event::<T>::ParkedMessageRefunded(failed_message_id));
+        }
 
-                    <NextAssetId<T>>::mutate(|asset_id| *asset_id += AssetIdOf::<T>::one());
+        /// Set XCMP message version, used to communicate with the given parachain.
+        #[weight = 10]
+        fn set_xcmp_version(origin, para_id: u32, version: XCMPVersion) {
+            ensure_root(origin)?;
 
-                    Self::deposit_event(Event::<T>::DepositAssetViaXCMP(
-                        src,
-                        // para asset_id
-                        *para_asset_id,
-                        dest.clone(),
-                        // internal asset id representation
-                        next_asset_id,
-                        *amount,
-                    ));
+            let para_id: ParaId = para_id.into();
+
+            <XCMPVersions>::insert(para_id, version);
+
+            Self::deposit_event(Event::<T>::XCMPVersionSet(para_id, version));
+        }
+
+        /// Set deposit limits of the given parachain asset. None removes all limits.
+        #[weight = 10]
+        fn set_deposit_limits(
+            origin,
+            para_id: u32,
+            para_asset_id: Option<AssetIdOf<T>>,
+            limits: Option<DepositLimitsOf<T>>,
+        ) {
+            ensure_root(origin)?;
+
+            let para_id: ParaId = para_id.into();
+
+            if let Some(limits) = &limits {
+                ensure!(!limits.window.is_zero(), Error::<T>::ZeroDepositWindow);
+            }
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            match limits {
+                Some(limits) => <DepositLimitsByAsset<T>>::insert((para_id, para_asset_id), limits),
+                None => <DepositLimitsByAsset<T>>::remove((para_id, para_asset_id)),
+            }
+
+            Self::deposit_event(Event::<T>::DepositLimitsSet(para_id, para_asset_id));
+        }
+
+        /// Map XCM-style location, which has no canonical parachain asset representation,
+        /// to the given parachain asset. None removes the mapping.
+        #[weight = 10]
+        fn set_asset_location(
+            origin,
+            location: MultiLocation,
+            para_asset: Option<(u32, Option<AssetIdOf<T>>)>,
+        ) {
+            ensure_root(origin)?;
+
+            match para_asset {
+                Some((para_id, para_asset_id)) => {
+                    <ParaAssetByLocation<T>>::insert(&location, (ParaId::from(para_id), para_asset_id))
                 }
+                None => <ParaAssetByLocation<T>>::remove(&location),
             }
+
+            Self::deposit_event(Event::<T>::AssetLocationSet(location));
         }
-    }
-}
 
-impl<T: Trait> Module<T> {
-    pub fn ensure_asset_id_exists(
-        para_id: ParaId,
-        para_asset_id: Option<AssetIdOf<T>>,
-    ) -> Result<AssetIdOf<T>, Error<T>> {
-        ensure!(
-            <AssetIdByParaAssetId<T>>::contains_key(para_id, para_asset_id),
-            Error::<T>::AssetIdDoesNotExist
-        );
-        Ok(Self::asset_id_by_para_asset_id(para_id, para_asset_id))
+        /// Process quarantined deposit, ignoring deposit limits. Can be called by root only.
+        #[weight = 10]
+        fn release_quarantined_deposit(origin, quarantine_id: QuarantineId) {
+            ensure_root(origin)?;
+
+            let quarantined_deposit = Self::quarantined_deposits(quarantine_id)
+                .ok_or(Error::<T>::QuarantinedDepositDoesNotExist)?;
+
+            Self::execute_xcmp_message(quarantined_deposit.src, &quarantined_deposit.message)
+                .map_err(|_| Error::<T>::QuarantinedDepositProcessingFailed)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            <QuarantinedDeposits<T>>::remove(quarantine_id);
+
+            Self::deposit_event(Event::<T>::QuarantinedDepositReleased(quarantine_id));
+        }
+
+        /// Send plain transfers to the given parachain as XCM-style asset transfers, or stop doing so.
+        #[weight = 10]
+        fn set_multi_asset_transfers(origin, para_id: u32, enabled: bool) {
+            ensure_root(origin)?;
+
+            let para_id: ParaId = para_id.into();
+
+            <MultiAssetTransfers>::insert(para_id, enabled);
+
+            Self::deposit_event(Event::<T>::MultiAssetTransfersSet(para_id, enabled));
+        }
+
+        /// Set issuance ceiling of the given parachain asset on the `para_id` parachain. None removes the ceiling.
+        #[weight = 10]
+        fn set_issuance_ceiling(
+            origin,
+            para_id: u32,
+            para_asset_id: Option<AssetIdOf<T>>,
+            ceiling: Option<BalanceOf<T>>,
+        ) {
+            ensure_root(origin)?;
+
+            let para_id: ParaId = para_id.into();
+
+            match ceiling {
+                Some(ceiling) => <IssuanceCeilings<T>>::insert((para_id, para_asset_id), ceiling),
+                None => <IssuanceCeilings<T>>::remove((para_id, para_asset_id)),
+            }
+
+            Self::deposit_event(Event::<T>::IssuanceCeilingSet(para_id, para_asset_id));
+        }
+    }
+}
+
+/// This is a hack to convert from one generic type to another where we are sure that both are the
+/// same type/use the same encoding.
+fn convert_hack<O: Decode>(input: &impl Encode) -> O {
+    input.using_encoded(|e| Decode::decode(&mut &e[..]).expect("Must be compatible; qed"))
+}
+
+impl<T: Trait> DownwardMessageHandler for Module<T> {
+    /// Transfer main network asset into dex parachain from the relay chain (natively supported via Currency trait)
+    fn handle_downward_message(msg: &DownwardMessage) {
+        if let DownwardMessage::TransferInto(dest, amount, remark) = msg {
+            let dest = convert_hack(&dest);
+            let amount: BalanceOf<T> = convert_hack(amount);
+
+            // Unknown instructions are treated as a plain deposit
+            let instruction = DepositInstruction::decode(&mut &remark[..]).unwrap_or_default();
+
+            Self::process_or_park(InboundMessage::Downward(dest, amount, instruction));
+        }
+    }
+}
+
+impl<T: Trait> XCMPMessageHandler<VersionedXCMPMessageOf<T>> for Module<T> {
+    // Transfer main currency or custom asset from other parachain to our chain
+    fn handle_xcmp_message(src: ParaId, msg: &VersionedXCMPMessageOf<T>) {
+        // Older message versions are upgraded to the latest one before processing
+        Self::process_or_park(InboundMessage::XCMP(src, msg.clone().into_latest()));
+    }
+}
+
+impl<T: Trait> Module<T> {
+    /// Process inbound message, parking it in case of failure
+    fn process_or_park(message: InboundMessageOf<T>) {
+        if let Err(reason) = Self::process_inbound_message(&message) {
+            let failed_message_id = Self::next_failed_message_id();
+
+            <FailedMessages<T>>::insert(failed_message_id, FailedMessage { message, reason });
+            <NextFailedMessageId>::mutate(|id| *id = id.wrapping_add(1));
+
+            Self::deposit_event(Event::<T>::MessageParked(failed_message_id, reason));
+        }
+    }
+
+    fn process_inbound_message(message: &InboundMessageOf<T>) -> Result<(), FailureReason> {
+        match message {
+            InboundMessage::Downward(dest, amount, instruction) => {
+                Self::deposit_from_relay_chain(dest, *amount)?;
+                Self::apply_deposit_instruction(dest, *amount, instruction);
+                Ok(())
+            }
+            InboundMessage::XCMP(src, msg) => Self::process_xcmp_message(*src, msg),
+        }
+    }
+
+    fn deposit_from_relay_chain(
+        dest: &T::AccountId,
+        amount: BalanceOf<T>,
+    ) -> Result<(), FailureReason> {
+        <pallet_subdex::Module<T>>::ensure_can_hold_balance(
+            dest,
+            Asset::MainNetworkCurrency,
+            amount,
+        )
+        .map_err(|_| FailureReason::BalanceOverflow)?;
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        <pallet_subdex::Module<T>>::mint_asset(dest, Asset::MainNetworkCurrency, amount);
+
+        Self::deposit_event(Event::<T>::TransferredTokensFromRelayChain(
+            dest.clone(),
+            amount,
+        ));
+        Ok(())
+    }
+
+    /// Apply instruction to the deposited main currency. Plain deposit is kept, if it fails.
+    fn apply_deposit_instruction(
+        dest: &T::AccountId,
+        amount: BalanceOf<T>,
+        instruction: &DepositInstruction<BalanceOf<T>, AssetIdOf<T>>,
+    ) {
+        let result = match instruction {
+            DepositInstruction::Deposit => return,
+            DepositInstruction::DepositAndSwap(asset_id, min_asset_amount) => {
+                <pallet_subdex::Module<T>>::swap(
+                    dest,
+                    Asset::MainNetworkCurrency,
+                    amount,
+                    Asset::ParachainAsset(*asset_id),
+                    *min_asset_amount,
+                )
+                .map(|_| ())
+            }
+            DepositInstruction::DepositAndInvest(asset_id, max_asset_amount) => {
+                Self::invest_deposit(dest, amount, *asset_id, *max_asset_amount)
+            }
+        };
+
+        if result.is_err() {
+            Self::deposit_event(Event::<T>::DepositInstructionFailed(dest.clone(), amount));
+        }
+    }
+
+    /// Invest deposited main currency only, other asset cost is bounded by the sender
+    fn invest_deposit(
+        dest: &T::AccountId,
+        amount: BalanceOf<T>,
+        asset_id: AssetIdOf<T>,
+        max_asset_amount: BalanceOf<T>,
+    ) -> DispatchResult {
+        let asset = Asset::ParachainAsset(asset_id);
+        let (shares, asset_cost) = <pallet_subdex::Module<T>>::shares_for_amount(
+            Asset::MainNetworkCurrency,
+            amount,
+            asset,
+        )?;
+        ensure!(
+            asset_cost <= max_asset_amount,
+            Error::<T>::InvestCostAboveLimit
+        );
+
+        <pallet_subdex::Module<T>>::invest(dest, Asset::MainNetworkCurrency, asset, shares)
+    }
+
+    /// Process XCMP message, quarantining deposits, which exceed deposit limits
+    fn process_xcmp_message(
+        src: ParaId,
+        msg: &XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) -> Result<(), FailureReason> {
+        let msg = Self::resolve_multi_asset_message(src, msg)?;
+
+        // Expired transfer is refunded by the sender on timeout, so it must not be deposited
+        if let XCMPMessage::TrackedTransferToken(transfer_id, _, _, _, deadline) = msg {
+            let now: TransferDeadline = <pallet_subdex::Module<T>>::now().unique_saturated_into();
+            if now > deadline {
+                Self::reply_to_tracked_transfer(
+                    src,
+                    transfer_id,
+                    XCMPMessage::TransferRejected(transfer_id),
+                );
+                return Ok(());
+            }
+        }
+
+        match msg.deposit() {
+            Some((para_asset_id, amount))
+                if !Self::within_deposit_limits(src, para_asset_id, amount) =>
+            {
+                Self::quarantine_deposit(src, &msg, para_asset_id, amount);
+                Ok(())
+            }
+            _ => Self::execute_xcmp_message(src, &msg),
+        }
+    }
+
+    /// Convert XCM-style asset transfer into the plain transfer of the resolved parachain asset
+    fn resolve_multi_asset_message(
+        src: ParaId,
+        msg: &XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) -> Result<XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>, FailureReason> {
+        match msg {
+            XCMPMessage::TransferMultiAsset(dest, asset)
+                if asset.id == MultiLocation::relay_currency() =>
+            {
+                Ok(XCMPMessage::TransferToken(dest.clone(), asset.amount, None))
+            }
+            XCMPMessage::TransferMultiAsset(dest, asset) => {
+                match Self::resolve_asset_location(&asset.id) {
+                    // Only the sending parachain can be the asset reserve
+                    Some((para_id, para_asset_id)) if para_id == src => Ok(
+                        XCMPMessage::TransferToken(dest.clone(), asset.amount, para_asset_id),
+                    ),
+                    _ => Err(FailureReason::UnknownAssetLocation),
+                }
+            }
+            msg => Ok(msg.clone()),
+        }
+    }
+
+    /// Convert plain transfer into the XCM-style asset transfer to the destination parachain.
+    /// Parachain asset is located within the destination, main currency is located at the relay chain.
+    fn to_multi_asset_message(
+        msg: XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) -> XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>> {
+        match msg {
+            XCMPMessage::TransferToken(dest, amount, para_asset_id) => {
+                let location = match para_asset_id {
+                    Some(para_asset_id) => MultiLocation::local_asset(para_asset_id),
+                    None => Some(MultiLocation::relay_currency()),
+                };
+                match location {
+                    Some(id) => XCMPMessage::TransferMultiAsset(dest, MultiAsset { id, amount }),
+                    // Asset id, which has no XCM-style representation, is sent as it is
+                    None => XCMPMessage::TransferToken(dest, amount, para_asset_id),
+                }
+            }
+            msg => msg,
+        }
+    }
+
+    /// Parachain and its asset id, identified by XCM-style location.
+    /// Registered locations take precedence over the canonical ones.
+    pub fn resolve_asset_location(
+        location: &MultiLocation,
+    ) -> Option<(ParaId, Option<AssetIdOf<T>>)> {
+        Self::para_asset_by_location(location).or_else(|| location.as_sibling_asset())
+    }
+
+    /// Our internal asset id representation of the asset, identified by XCM-style location.
+    pub fn asset_id_by_location(location: &MultiLocation) -> Option<AssetIdOf<T>> {
+        let (para_id, para_asset_id) = Self::resolve_asset_location(location)?;
+        Self::ensure_asset_id_exists(para_id, para_asset_id).ok()
+    }
+
+    fn execute_xcmp_message(
+        src: ParaId,
+        msg: &XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) -> Result<(), FailureReason> {
+        match msg {
+            XCMPMessage::TransferToken(dest, amount, para_asset_id) => {
+                Self::deposit_from_parachain(src, dest, *amount, *para_asset_id)?;
+            }
+            XCMPMessage::TransferAndSwap(
+                dest,
+                amount,
+                para_asset_id,
+                target_asset_id,
+                min_target_amount,
+            ) => {
+                let asset = Self::deposit_from_parachain(src, dest, *amount, *para_asset_id)?;
+
+                // Deposited asset is kept by the beneficiary, if swap fails
+                if <pallet_subdex::Module<T>>::swap(
+                    dest,
+                    asset,
+                    *amount,
+                    Self::dex_asset(*target_asset_id),
+                    *min_target_amount,
+                )
+                .is_err()
+                {
+                    Self::deposit_event(Event::<T>::SwapOnArrivalFailed(
+                        src,
+                        dest.clone(),
+                        asset,
+                        *amount,
+                    ));
+                }
+            }
+            XCMPMessage::QueryPrice(asset_a_id, asset_b_id, query_id) => {
+                Self::answer_price_query(src, *asset_a_id, *asset_b_id, *query_id);
+            }
+            XCMPMessage::PriceResponse(query_id, price_data) => {
+                Self::deposit_event(Event::<T>::PriceResponseReceived(
+                    src,
+                    *query_id,
+                    price_data.clone(),
+                ));
+            }
+            XCMPMessage::TrackedTransferToken(transfer_id, dest, amount, para_asset_id, _) => {
+                // Rejected transfer is refunded by the sender, so it is never parked
+                Self::accept_tracked_transfer(src, *transfer_id, || {
+                    Self::deposit_from_parachain(src, dest, *amount, *para_asset_id).is_ok()
+                });
+            }
+            XCMPMessage::TransferMultiAsset(..) => {
+                // Resolved into a plain transfer before execution
+                return Err(FailureReason::UnknownAssetLocation);
+            }
+            XCMPMessage::TransferAck(transfer_id) => {
+                // Acknowledged main currency stays in transit, until it is returned
+                if let Some(pending_transfer) = Self::pending_transfer_to(src, *transfer_id) {
+                    Self::remove_pending_transfer(*transfer_id, &pending_transfer);
+
+                    Self::deposit_event(Event::<T>::TransferAcknowledged(src, *transfer_id));
+                }
+            }
+            XCMPMessage::TransferRejected(transfer_id) => {
+                if let Some(pending_transfer) = Self::pending_transfer_to(src, *transfer_id) {
+                    Self::deposit_event(Event::<T>::TransferRejected(src, *transfer_id));
+                    Self::refund_pending_transfer(*transfer_id, pending_transfer);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the effects of `accept` only along with the tracked transfer acknowledgement, reject the transfer otherwise.
+    /// Unacknowledged transfer is refunded by the sender on timeout, so nothing is kept, if the acknowledgement
+    /// can not be sent.
+    fn accept_tracked_transfer(
+        src: ParaId,
+        transfer_id: TransferId,
+        accept: impl FnOnce() -> bool,
+    ) {
+        let acknowledged = with_transaction(|| {
+            if accept()
+                && Self::send_xcmp_message(src, XCMPMessage::TransferAck(transfer_id)).is_ok()
+            {
+                TransactionOutcome::Commit(true)
+            } else {
+                TransactionOutcome::Rollback(false)
+            }
+        });
+
+        if !acknowledged {
+            Self::reply_to_tracked_transfer(
+                src,
+                transfer_id,
+                XCMPMessage::TransferRejected(transfer_id),
+            );
+        }
+    }
+
+    /// Reply to the tracked transfer, reporting the reply, which could not be sent
+    fn reply_to_tracked_transfer(
+        src: ParaId,
+        transfer_id: TransferId,
+        reply: XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) {
+        if Self::send_xcmp_message(src, reply).is_err() {
+            Self::deposit_event(Event::<T>::TransferReplyFailed(src, transfer_id));
+        }
+    }
+
+    /// Check, whether deposit fits into limits and issuance ceiling of the parachain asset
+    fn within_deposit_limits(
+        src: ParaId,
+        para_asset_id: Option<AssetIdOf<T>>,
+        amount: BalanceOf<T>,
+    ) -> bool {
+        // Every parachain asset is wrapped into its own dex asset, so its backed amount is the issuance
+        let within_issuance =
+            Self::issuance_ceiling((src, para_asset_id)).map_or(true, |ceiling| {
+                Self::backed_amounts((src, para_asset_id))
+                    .checked_add(&amount)
+                    .map_or(false, |issuance| issuance <= ceiling)
+            });
+
+        let limits = match Self::deposit_limits((src, para_asset_id)) {
+            Some(limits) => limits,
+            None => return within_issuance,
+        };
+
+        let (_, window_deposited) = Self::current_deposit_window(src, para_asset_id, limits.window);
+
+        let within_window = window_deposited
+            .checked_add(&amount)
+            .map_or(false, |deposited| deposited <= limits.max_per_window);
+
+        amount <= limits.max_per_message && within_window && within_issuance
+    }
+
+    /// Start block of the current deposit window and amount, deposited within it
+    fn current_deposit_window(
+        src: ParaId,
+        para_asset_id: Option<AssetIdOf<T>>,
+        window: T::BlockNumber,
+    ) -> (T::BlockNumber, BalanceOf<T>) {
+        let now = <frame_system::Module<T>>::block_number();
+        let (window_start, window_deposited) = Self::deposit_windows((src, para_asset_id));
+
+        if now >= window_start.saturating_add(window) {
+            (now, BalanceOf::<T>::zero())
+        } else {
+            (window_start, window_deposited)
+        }
+    }
+
+    /// Hold deposit message until released by root
+    fn quarantine_deposit(
+        src: ParaId,
+        msg: &XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+        para_asset_id: Option<AssetIdOf<T>>,
+        amount: BalanceOf<T>,
+    ) {
+        match msg {
+            // Funds are already held by our sovereign account, so tracked transfer is acknowledged
+            // and kept as a plain transfer
+            XCMPMessage::TrackedTransferToken(transfer_id, dest, ..) => {
+                let message = XCMPMessage::TransferToken(dest.clone(), amount, para_asset_id);
+                Self::accept_tracked_transfer(src, *transfer_id, || {
+                    Self::insert_quarantined_deposit(src, message, para_asset_id, amount);
+                    true
+                });
+            }
+            msg => Self::insert_quarantined_deposit(src, msg.clone(), para_asset_id, amount),
+        }
+    }
+
+    /// Add deposit message to the quarantine queue
+    fn insert_quarantined_deposit(
+        src: ParaId,
+        message: XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+        para_asset_id: Option<AssetIdOf<T>>,
+        amount: BalanceOf<T>,
+    ) {
+        let quarantine_id = Self::next_quarantine_id();
+
+        <QuarantinedDeposits<T>>::insert(quarantine_id, QuarantinedDeposit { src, message });
+        <NextQuarantineId>::mutate(|id| *id = id.wrapping_add(1));
+
+        Self::deposit_event(Event::<T>::DepositQuarantined(
+            quarantine_id,
+            src,
+            para_asset_id,
+            amount,
+        ));
+    }
+
+    /// Send price data of the requested exchange back to the querying parachain
+    fn answer_price_query(
+        src: ParaId,
+        asset_a_id: Option<AssetIdOf<T>>,
+        asset_b_id: Option<AssetIdOf<T>>,
+        query_id: QueryId,
+    ) {
+        let now = <frame_system::Module<T>>::block_number();
+        let (block_number, queries) = Self::price_queries(src);
+        let queries = if block_number == now { queries } else { 0 };
+
+        if queries >= T::MaxPriceQueriesPerBlock::get() {
+            Self::deposit_event(Event::<T>::PriceQueryRateLimited(src, query_id));
+            return;
+        }
+
+        <PriceQueries<T>>::insert(src, (now, queries + 1));
+
+        let price_quote =
+            Self::price_quote(Self::dex_asset(asset_a_id), Self::dex_asset(asset_b_id)).ok();
+
+        // Price query could only arrive in V1 envelope, so it is answered in V1 too
+        if Self::send_versioned_xcmp_message(
+            src,
+            XCMPMessage::PriceResponse(query_id, price_quote),
+            XCMPVersion::V1,
+        )
+        .is_ok()
+        {
+            Self::deposit_event(Event::<T>::PriceQueryAnswered(src, query_id));
+        } else {
+            Self::deposit_event(Event::<T>::PriceQueryResponseFailed(src, query_id));
+        }
+    }
+
+    /// Spot and average price of `asset_a` in `asset_b`. Average price is taken since the latest observation,
+    /// which is at least the TWAP period old. Newer observation replaces it, once it becomes that old.
+    /// Observation, older than the maximum averaging window, is not averaged.
+    fn price_quote(
+        asset_a: Asset<AssetIdOf<T>>,
+        asset_b: Asset<AssetIdOf<T>>,
+    ) -> Result<PriceQuote<BalanceOf<T>>, pallet_subdex::Error<T>> {
+        let spot_price = <pallet_subdex::Module<T>>::spot_price(asset_a, asset_b)?;
+        let (price_cumulative, now) =
+            <pallet_subdex::Module<T>>::current_price_cumulative(asset_a, asset_b)?;
+        let twap_period: BalanceOf<T> = T::TwapPeriod::get().into();
+        let max_twap_window: BalanceOf<T> = T::MaxTwapWindow::get().into();
+
+        let observations = Self::price_observations((asset_a, asset_b));
+        let (older, newer) = match observations {
+            (_, Some((_, timestamp))) if now.saturating_sub(timestamp) < twap_period => {
+                observations
+            }
+            (_, newer) => (newer, Some((price_cumulative, now))),
+        };
+
+        // Cumulative price wraps around, so the longer window could be ambiguous
+        let average_price = match older {
+            Some((price_cumulative_last, timestamp))
+                if now.saturating_sub(timestamp) <= max_twap_window =>
+            {
+                Some(<pallet_subdex::Module<T>>::average_price(
+                    price_cumulative_last,
+                    price_cumulative,
+                    now.saturating_sub(timestamp),
+                )?)
+            }
+            _ => None,
+        };
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        <PriceObservations<T>>::insert((asset_a, asset_b), (older, newer));
+
+        Ok(PriceQuote {
+            spot_price,
+            average_price,
+        })
+    }
+
+    /// Dex asset, identified by the asset id in the XCMP message (None for main currency)
+    fn dex_asset(asset_id: Option<AssetIdOf<T>>) -> Asset<AssetIdOf<T>> {
+        match asset_id {
+            Some(asset_id) => Asset::ParachainAsset(asset_id),
+            None => Asset::MainNetworkCurrency,
+        }
+    }
+
+    /// Mint parachain asset to the given account, or return main currency, sent to the parachain earlier.
+    /// Returns the deposited dex asset.
+    fn deposit_from_parachain(
+        src: ParaId,
+        dest: &T::AccountId,
+        amount: BalanceOf<T>,
+        para_asset_id: Option<AssetIdOf<T>>,
+    ) -> Result<Asset<AssetIdOf<T>>, FailureReason> {
+        // Main currency beyond the amount in transit belongs to the parachain and is wrapped
+        if para_asset_id.is_none() && amount <= Self::main_currency_in_transit(src) {
+            Self::return_main_currency(src, dest, amount)?;
+            return Ok(Asset::MainNetworkCurrency);
+        }
+
+        let (asset_id, next_asset_id) =
+            if <AssetIdByParaAssetId<T>>::contains_key(src, para_asset_id) {
+                (Self::asset_id_by_para_asset_id(src, para_asset_id), None)
+            } else {
+                let asset_id = Self::next_asset_id();
+                let next_asset_id = asset_id
+                    .checked_add(&AssetIdOf::<T>::one())
+                    .ok_or(FailureReason::AssetIdOverflow)?;
+                (asset_id, Some(next_asset_id))
+            };
+
+        <pallet_subdex::Module<T>>::ensure_can_hold_balance(
+            dest,
+            Asset::ParachainAsset(asset_id),
+            amount,
+        )
+        .map_err(|_| FailureReason::BalanceOverflow)?;
+
+        let backed_amount = Self::backed_amounts((src, para_asset_id))
+            .checked_add(&amount)
+            .ok_or(FailureReason::BackingOverflow)?;
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        if let Some(next_asset_id) = next_asset_id {
+            <AssetIdByParaAssetId<T>>::insert(src, para_asset_id, asset_id);
+            <NextAssetId<T>>::put(next_asset_id);
+        }
+
+        <BackedAmounts<T>>::insert((src, para_asset_id), backed_amount);
+
+        if let Some(limits) = Self::deposit_limits((src, para_asset_id)) {
+            let (window_start, window_deposited) =
+                Self::current_deposit_window(src, para_asset_id, limits.window);
+            <DepositWindows<T>>::insert(
+                (src, para_asset_id),
+                (window_start, window_deposited.saturating_add(amount)),
+            );
+        }
+
+        <pallet_subdex::Module<T>>::mint_asset(dest, Asset::ParachainAsset(asset_id), amount);
+
+        Self::deposit_event(Event::<T>::DepositAssetViaXCMP(
+            src,
+            // para asset_id
+            para_asset_id,
+            dest.clone(),
+            // internal asset id representation
+            asset_id,
+            amount,
+        ));
+        Ok(Asset::ParachainAsset(asset_id))
+    }
+
+    /// Return main currency amount in transit to the given parachain to the account
+    fn return_main_currency(
+        src: ParaId,
+        dest: &T::AccountId,
+        amount: BalanceOf<T>,
+    ) -> Result<(), FailureReason> {
+        <pallet_subdex::Module<T>>::ensure_can_hold_balance(
+            dest,
+            Asset::MainNetworkCurrency,
+            amount,
+        )
+        .map_err(|_| FailureReason::BalanceOverflow)?;
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        <MainCurrencyInTransit<T>>::mutate(src, |in_transit_amount| {
+            *in_transit_amount = in_transit_amount.saturating_sub(amount)
+        });
+
+        <pallet_subdex::Module<T>>::mint_asset(dest, Asset::MainNetworkCurrency, amount);
+
+        Self::deposit_event(Event::<T>::TransferredTokensFromParachain(
+            src,
+            dest.clone(),
+            amount,
+        ));
+        Ok(())
+    }
+
+    /// Send transfer to the given parachain. Transfer is tracked by its id, if the destination
+    /// parachain understands acknowledgements, and sent as a plain `TransferToken` otherwise.
+    /// XCM-style asset transfers carry no id, so they are never tracked.
+    /// Returns the id, pending transfer should be recorded under.
+    fn send_transfer_to_parachain(
+        sender: &T::AccountId,
+        para_id: ParaId,
+        dest: T::AccountId,
+        amount: BalanceOf<T>,
+        para_asset_id: Option<AssetIdOf<T>>,
+    ) -> Result<Option<TransferId>, DispatchError> {
+        match (
+            Self::xcmp_version(para_id),
+            Self::multi_asset_transfers(para_id),
+        ) {
+            (XCMPVersion::V0, _) | (XCMPVersion::V1, true) => {
+                Self::send_xcmp_message(
+                    para_id,
+                    XCMPMessage::TransferToken(dest, amount, para_asset_id),
+                )?;
+                Ok(None)
+            }
+            (XCMPVersion::V1, false) => {
+                Self::ensure_can_track_transfer(sender)?;
+
+                let transfer_id = Self::next_transfer_id();
+                let deadline = <pallet_subdex::Module<T>>::now()
+                    .saturating_add(T::TransferDeliveryPeriod::get())
+                    .unique_saturated_into();
+                Self::send_xcmp_message(
+                    para_id,
+                    XCMPMessage::TrackedTransferToken(
+                        transfer_id,
+                        dest,
+                        amount,
+                        para_asset_id,
+                        deadline,
+                    ),
+                )?;
+                Ok(Some(transfer_id))
+            }
+        }
+    }
+
+    /// Make sure, that pending transfers of the sender and the refunds at the transfer deadline stay bounded
+    fn ensure_can_track_transfer(sender: &T::AccountId) -> DispatchResult {
+        ensure!(
+            Self::account_pending_transfers(sender).len()
+                < T::MaxPendingTransfersPerAccount::get() as usize,
+            Error::<T>::TooManyPendingTransfers
+        );
+
+        let deadline =
+            <frame_system::Module<T>>::block_number().saturating_add(T::TransferTimeout::get());
+        ensure!(
+            Self::transfer_deadlines(deadline).len()
+                < T::MaxTrackedTransfersPerBlock::get() as usize,
+            Error::<T>::TooManyTrackedTransfersInBlock
+        );
+        Ok(())
+    }
+
+    /// Record sent transfer as pending, until it is acknowledged, rejected or timed out
+    fn add_pending_transfer(
+        transfer_id: TransferId,
+        sender: T::AccountId,
+        para_id: ParaId,
+        asset: Asset<AssetIdOf<T>>,
+        para_asset_id: Option<AssetIdOf<T>>,
+        amount: BalanceOf<T>,
+    ) {
+        let deadline =
+            <frame_system::Module<T>>::block_number().saturating_add(T::TransferTimeout::get());
+
+        <AccountPendingTransfers<T>>::mutate(&sender, |transfer_ids| {
+            transfer_ids.push(transfer_id)
+        });
+        <TransferDeadlines<T>>::mutate(deadline, |transfer_ids| transfer_ids.push(transfer_id));
+        <NextTransferId>::mutate(|id| *id = id.wrapping_add(1));
+
+        <PendingTransfers<T>>::insert(
+            transfer_id,
+            PendingTransfer {
+                sender: sender.clone(),
+                para_id,
+                asset,
+                para_asset_id,
+                amount,
+                deadline,
+            },
+        );
+
+        Self::deposit_event(Event::<T>::TransferPending(para_id, transfer_id, sender));
+    }
+
+    /// Pending transfer, sent to the given parachain
+    fn pending_transfer_to(para_id: ParaId, transfer_id: TransferId) -> Option<PendingTransfer<T>> {
+        Self::pending_transfers(transfer_id)
+            .filter(|pending_transfer| pending_transfer.para_id == para_id)
+    }
+
+    fn remove_pending_transfer(transfer_id: TransferId, pending_transfer: &PendingTransfer<T>) {
+        <PendingTransfers<T>>::remove(transfer_id);
+
+        <AccountPendingTransfers<T>>::mutate(&pending_transfer.sender, |transfer_ids| {
+            transfer_ids.retain(|id| *id != transfer_id)
+        });
+
+        // Deadline entry is already taken, when transfer is refunded on timeout
+        if <TransferDeadlines<T>>::contains_key(pending_transfer.deadline) {
+            <TransferDeadlines<T>>::mutate(pending_transfer.deadline, |transfer_ids| {
+                transfer_ids.retain(|id| *id != transfer_id)
+            });
+        }
+    }
+
+    /// Return pending transfer amount to the sender, restoring backing or main currency in transit
+    fn refund_pending_transfer(transfer_id: TransferId, pending_transfer: PendingTransfer<T>) {
+        if <pallet_subdex::Module<T>>::ensure_can_hold_balance(
+            &pending_transfer.sender,
+            pending_transfer.asset,
+            pending_transfer.amount,
+        )
+        .is_err()
+        {
+            Self::deposit_event(Event::<T>::TransferRefundFailed(transfer_id));
+            return;
+        }
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        match pending_transfer.asset {
+            Asset::MainNetworkCurrency => {
+                <MainCurrencyInTransit<T>>::mutate(pending_transfer.para_id, |in_transit_amount| {
+                    *in_transit_amount = in_transit_amount.saturating_sub(pending_transfer.amount)
+                });
+            }
+            Asset::ParachainAsset(_) => {
+                <BackedAmounts<T>>::mutate(
+                    (pending_transfer.para_id, pending_transfer.para_asset_id),
+                    |backed_amount| {
+                        *backed_amount = backed_amount.saturating_add(pending_transfer.amount)
+                    },
+                );
+            }
+        }
+
+        <pallet_subdex::Module<T>>::mint_asset(
+            &pending_transfer.sender,
+            pending_transfer.asset,
+            pending_transfer.amount,
+        );
+
+        Self::remove_pending_transfer(transfer_id, &pending_transfer);
+
+        Self::deposit_event(Event::<T>::TransferRefunded(
+            transfer_id,
+            pending_transfer.sender,
+            pending_transfer.amount,
+        ));
+    }
+
+    /// In-flight outbound transfers, sent by the given account.
+    pub fn in_flight_transfers(who: &T::AccountId) -> Vec<(TransferId, PendingTransfer<T>)> {
+        Self::account_pending_transfers(who)
+            .into_iter()
+            .filter_map(|transfer_id| {
+                Self::pending_transfers(transfer_id)
+                    .map(|pending_transfer| (transfer_id, pending_transfer))
+            })
+            .collect()
+    }
+
+    /// Send main currency transfer message to the relay chain
+    pub fn send_upward_transfer(dest: T::AccountId, amount: BalanceOf<T>) -> DispatchResult {
+        let msg = <T as Trait>::UpwardMessage::transfer(dest, amount);
+        <T as Trait>::UpwardMessageSender::send_upward_message(&msg, UpwardMessageOrigin::Signed)
+            .map_err(|_| Error::<T>::MessageSendFailed)?;
+        Ok(())
+    }
+
+    /// Send XCMP message, wrapped into the envelope version, understood by the destination parachain
+    pub fn send_xcmp_message(
+        dest: ParaId,
+        msg: XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+    ) -> DispatchResult {
+        Self::send_versioned_xcmp_message(dest, msg, Self::xcmp_version(dest))
+    }
+
+    /// Send message to the given parachain in the given envelope version
+    pub fn send_versioned_xcmp_message(
+        dest: ParaId,
+        msg: XCMPMessage<T::AccountId, BalanceOf<T>, AssetIdOf<T>>,
+        version: XCMPVersion,
+    ) -> DispatchResult {
+        let msg = if Self::multi_asset_transfers(dest) {
+            Self::to_multi_asset_message(msg)
+        } else {
+            msg
+        };
+
+        let versioned_msg = VersionedXCMPMessage::from_latest(msg, version)
+            .ok_or(Error::<T>::UnsupportedXCMPVersion)?;
+
+        T::XCMPMessageSender::send_xcmp_message(dest, &versioned_msg)
+            .map_err(|_| Error::<T>::MessageSendFailed)?;
+        Ok(())
+    }
+
+    pub fn ensure_failed_message_exists(
+        failed_message_id: FailedMessageId,
+    ) -> Result<FailedMessageOf<T>, Error<T>> {
+        Self::failed_messages(failed_message_id).ok_or(Error::<T>::FailedMessageDoesNotExist)
+    }
+
+    pub fn ensure_root_or_owner(origin: T::Origin, owner: Option<&T::AccountId>) -> DispatchResult {
+        let origin: Result<RawOrigin<T::AccountId>, T::Origin> = origin.into();
+        match origin {
+            Ok(RawOrigin::Root) => Ok(()),
+            Ok(RawOrigin::Signed(ref who)) if Some(who) == owner => Ok(()),
+            _ => Err(Error::<T>::NotFailedMessageOwner.into()),
+        }
+    }
+
+    pub fn ensure_asset_id_exists(
+        para_id: ParaId,
+        para_asset_id: Option<AssetIdOf<T>>,
+    ) -> Result<AssetIdOf<T>, Error<T>> {
+        ensure!(
+            <AssetIdByParaAssetId<T>>::contains_key(para_id, para_asset_id),
+            Error::<T>::AssetIdDoesNotExist
+        );
+        Ok(Self::asset_id_by_para_asset_id(para_id, para_asset_id))
+    }
+
+    /// Ensure our sovereign account holds at least `amount` of the parachain asset on the given parachain.
+    /// Returns backed amount, left after the withdrawal.
+    pub fn ensure_sufficient_backing(
+        para_id: ParaId,
+        para_asset_id: Option<AssetIdOf<T>>,
+        amount: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, Error<T>> {
+        Self::backed_amounts((para_id, para_asset_id))
+            .checked_sub(&amount)
+            .ok_or(Error::<T>::InsufficientBacking)
+    }
+
+    /// Backed amount of the given parachain asset, used by the runtime api.
+    pub fn backed_amount(para_id: u32, para_asset_id: Option<AssetIdOf<T>>) -> BalanceOf<T> {
+        Self::backed_amounts((ParaId::from(para_id), para_asset_id))
+    }
+
+    /// Full backing ledger, used by the runtime api.
+    pub fn backing_ledger() -> Vec<(u32, Option<AssetIdOf<T>>, BalanceOf<T>)> {
+        <BackedAmounts<T>>::iter()
+            .map(|((para_id, para_asset_id), amount)| (para_id.into(), para_asset_id, amount))
+            .collect()
     }
 
     pub fn ensure_non_zero_balance(amount: BalanceOf<T>) -> Result<(), Error<T>> {
@@ -298,5 +1672,33 @@ decl_error! {
         // Given parachain asset id entry does not exist
         AssetIdDoesNotExist,
         ZeroBalanceAmount,
+        // Given failed message entry does not exist
+        FailedMessageDoesNotExist,
+        // Only root or failed message funds owner can manage it
+        NotFailedMessageOwner,
+        // Parked message processing failed once again
+        FailedMessageProcessingFailed,
+        // Message broker rejected outgoing message
+        MessageSendFailed,
+        // Our sovereign account does not hold enough tokens on the other parachain
+        InsufficientBacking,
+        // Message can not be represented in the XCMP version, used by the destination parachain
+        UnsupportedXCMPVersion,
+        // Parked message carries no funds to be refunded
+        NothingToRefund,
+        // Main currency amount, sent to the parachain, would overflow
+        InTransitAmountOverflow,
+        // Given quarantined deposit entry does not exist
+        QuarantinedDepositDoesNotExist,
+        // Quarantined deposit processing failed
+        QuarantinedDepositProcessingFailed,
+        // Deposit instruction would invest more of the other asset, than allowed by the sender
+        InvestCostAboveLimit,
+        // Sender has too many in-flight transfers
+        TooManyPendingTransfers,
+        // Too many tracked transfers were sent in this block
+        TooManyTrackedTransfersInBlock,
+        // Deposit window should be at least one block long
+        ZeroDepositWindow,
     }
 }
@@ -0,0 +1,239 @@
+use super::*;
+use codec::{Error as CodecError, Input, Output};
+
+/// Id, used to match price query with its response.
+pub type QueryId = u64;
+
+/// Price of the queried asset A in the asset B, scaled by `pallet_subdex::PRICE_ONE`.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct PriceQuote<XBalance> {
+    /// Price, implied by the current exchange pools.
+    pub spot_price: XBalance,
+    /// Time weighted average price over at least the TWAP period. None, until such period of observations is available.
+    pub average_price: Option<XBalance>,
+}
+
+/// Id of the outbound transfer, awaiting acknowledgement from the destination parachain.
+pub type TransferId = u64;
+
+/// Timestamp in milliseconds, after which the tracked transfer is not accepted by the destination parachain.
+pub type TransferDeadline = u64;
+
+/// Latest XCMP message format.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum XCMPMessage<XAccountId, XBalance, XAssetIdOf> {
+    /// Transfer tokens to the given account from the Parachain account.
+    /// When XAssetIdOf is None, treat message as main currency transfer.
+    TransferToken(XAccountId, XBalance, Option<XAssetIdOf>),
+    /// Transfer tokens to the given beneficiary and swap them on arrival to the target dex asset
+    /// (None for main currency), expecting at least the given target asset amount.
+    /// Beneficiary keeps transferred tokens, if swap fails.
+    TransferAndSwap(
+        XAccountId,
+        XBalance,
+        Option<XAssetIdOf>,
+        Option<XAssetIdOf>,
+        XBalance,
+    ),
+    /// Request price of the first asset in the second one on the dex (None for main currency).
+    QueryPrice(Option<XAssetIdOf>, Option<XAssetIdOf>, QueryId),
+    /// Response to the price query. None, if requested exchange does not exist.
+    PriceResponse(QueryId, Option<PriceQuote<XBalance>>),
+    /// Transfer tokens to the given account, like `TransferToken`, and reply with
+    /// `TransferAck` or `TransferRejected`, carrying the given transfer id.
+    /// Transfer, delivered after the deadline timestamp, is rejected, as the sender refunds it on timeout.
+    TrackedTransferToken(
+        TransferId,
+        XAccountId,
+        XBalance,
+        Option<XAssetIdOf>,
+        TransferDeadline,
+    ),
+    /// Tracked transfer was deposited to the beneficiary.
+    TransferAck(TransferId),
+    /// Tracked transfer could not be deposited, sender should refund it.
+    TransferRejected(TransferId),
+    /// Transfer XCM-style asset to the given account. Asset location is relative to the receiving chain.
+    /// Its reserve must be the sending parachain, unless it is the relay chain currency.
+    TransferMultiAsset(XAccountId, MultiAsset<XBalance>),
+}
+
+impl<XAccountId: Clone, XBalance: Copy, XAssetIdOf: Copy>
+    XCMPMessage<XAccountId, XBalance, XAssetIdOf>
+{
+    /// Account, the message funds are addressed to. None, if message carries no funds.
+    pub fn owner(&self) -> Option<&XAccountId> {
+        match self {
+            XCMPMessage::TransferToken(dest, _, _) => Some(dest),
+            XCMPMessage::TransferAndSwap(dest, _, _, _, _) => Some(dest),
+            XCMPMessage::TrackedTransferToken(_, dest, _, _, _) => Some(dest),
+            XCMPMessage::TransferMultiAsset(dest, _) => Some(dest),
+            XCMPMessage::QueryPrice(..)
+            | XCMPMessage::PriceResponse(..)
+            | XCMPMessage::TransferAck(..)
+            | XCMPMessage::TransferRejected(..) => None,
+        }
+    }
+
+    /// Parachain asset id and amount, deposited by the message. None, if message carries no funds.
+    pub fn deposit(&self) -> Option<(Option<XAssetIdOf>, XBalance)> {
+        match self {
+            XCMPMessage::TransferToken(_, amount, para_asset_id)
+            | XCMPMessage::TransferAndSwap(_, amount, para_asset_id, _, _)
+            | XCMPMessage::TrackedTransferToken(_, _, amount, para_asset_id, _) => {
+                Some((*para_asset_id, *amount))
+            }
+            // Asset location has to be resolved first, see `Module::resolve_multi_asset_message`
+            XCMPMessage::TransferMultiAsset(..)
+            | XCMPMessage::QueryPrice(..)
+            | XCMPMessage::PriceResponse(..)
+            | XCMPMessage::TransferAck(..)
+            | XCMPMessage::TransferRejected(..) => None,
+        }
+    }
+
+    /// Message, returning carried funds back to the given origin parachain.
+    pub fn refund(&self, origin: ParaId) -> Option<Self> {
+        match self {
+            XCMPMessage::TransferToken(dest, amount, para_asset_id)
+            | XCMPMessage::TransferAndSwap(dest, amount, para_asset_id, _, _) => Some(
+                XCMPMessage::TransferToken(dest.clone(), *amount, *para_asset_id),
+            ),
+            // Asset of the unresolved location is sent back as it is, located relative to the origin
+            XCMPMessage::TransferMultiAsset(dest, asset) => Some(XCMPMessage::TransferMultiAsset(
+                dest.clone(),
+                MultiAsset {
+                    id: asset.id.reanchored_to_sibling(origin)?,
+                    amount: asset.amount,
+                },
+            )),
+            // Tracked transfers are refunded by the sender on rejection
+            XCMPMessage::TrackedTransferToken(..)
+            | XCMPMessage::QueryPrice(..)
+            | XCMPMessage::PriceResponse(..)
+            | XCMPMessage::TransferAck(..)
+            | XCMPMessage::TransferRejected(..) => None,
+        }
+    }
+}
+
+/// Instruction, SCALE-encoded into the remark of the relay chain `TransferInto` message.
+/// Empty (zeroed) remark decodes as a plain deposit.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum DepositInstruction<XBalance, XAssetIdOf> {
+    /// Keep deposited main currency.
+    Deposit,
+    /// Swap deposited main currency to the given dex asset, expecting at least the given amount.
+    DepositAndSwap(XAssetIdOf, XBalance),
+    /// Invest deposited main currency into its exchange with the given dex asset,
+    /// paying at most the given amount of the dex asset.
+    DepositAndInvest(XAssetIdOf, XBalance),
+}
+
+impl<XBalance, XAssetIdOf> Default for DepositInstruction<XBalance, XAssetIdOf> {
+    fn default() -> Self {
+        Self::Deposit
+    }
+}
+
+/// Legacy XCMP message format, sent without a version byte.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum XCMPMessageV0<XAccountId, XBalance, XAssetIdOf> {
+    /// Transfer tokens to the given account from the Parachain account.
+    /// When XAssetIdOf is None, treat message as main currency transfer.
+    TransferToken(XAccountId, XBalance, Option<XAssetIdOf>),
+}
+
+impl<XAccountId, XBalance, XAssetIdOf> From<XCMPMessageV0<XAccountId, XBalance, XAssetIdOf>>
+    for XCMPMessage<XAccountId, XBalance, XAssetIdOf>
+{
+    fn from(msg: XCMPMessageV0<XAccountId, XBalance, XAssetIdOf>) -> Self {
+        match msg {
+            XCMPMessageV0::TransferToken(dest, amount, para_asset_id) => {
+                XCMPMessage::TransferToken(dest, amount, para_asset_id)
+            }
+        }
+    }
+}
+
+/// XCMP message version, understood by the counterparty parachain.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum XCMPVersion {
+    V0,
+    V1,
+}
+
+impl Default for XCMPVersion {
+    // Parachains we know nothing about are expected to speak the legacy format
+    fn default() -> Self {
+        Self::V0
+    }
+}
+
+/// Versioned XCMP message envelope.
+///
+/// `V0` is encoded exactly like the legacy unversioned message, which always starts
+/// with the `TransferToken` variant index (0). Every later version is prefixed with its own version byte.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VersionedXCMPMessage<XAccountId, XBalance, XAssetIdOf> {
+    V0(XCMPMessageV0<XAccountId, XBalance, XAssetIdOf>),
+    V1(XCMPMessage<XAccountId, XBalance, XAssetIdOf>),
+}
+
+const V1_VERSION_BYTE: u8 = 1;
+
+impl<XAccountId, XBalance, XAssetIdOf> VersionedXCMPMessage<XAccountId, XBalance, XAssetIdOf> {
+    /// Upgrade message to the latest format.
+    pub fn into_latest(self) -> XCMPMessage<XAccountId, XBalance, XAssetIdOf> {
+        match self {
+            VersionedXCMPMessage::V0(msg) => msg.into(),
+            VersionedXCMPMessage::V1(msg) => msg,
+        }
+    }
+
+    /// Wrap message into the given envelope version.
+    /// Returns None, if message can not be represented in that version.
+    pub fn from_latest(
+        msg: XCMPMessage<XAccountId, XBalance, XAssetIdOf>,
+        version: XCMPVersion,
+    ) -> Option<Self> {
+        match (version, msg) {
+            (XCMPVersion::V0, XCMPMessage::TransferToken(dest, amount, para_asset_id)) => Some(
+                VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(dest, amount, para_asset_id)),
+            ),
+            (XCMPVersion::V0, _) => None,
+            (XCMPVersion::V1, msg) => Some(VersionedXCMPMessage::V1(msg)),
+        }
+    }
+}
+
+impl<XAccountId: Encode, XBalance: Encode, XAssetIdOf: Encode> Encode
+    for VersionedXCMPMessage<XAccountId, XBalance, XAssetIdOf>
+{
+    fn encode_to<W: Output>(&self, dest: &mut W) {
+        match self {
+            VersionedXCMPMessage::V0(msg) => msg.encode_to(dest),
+            VersionedXCMPMessage::V1(msg) => {
+                dest.push_byte(V1_VERSION_BYTE);
+                msg.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl<XAccountId: Decode, XBalance: Decode, XAssetIdOf: Decode> Decode
+    for VersionedXCMPMessage<XAccountId, XBalance, XAssetIdOf>
+{
+    fn decode<I: Input>(input: &mut I) -> Result<Self, CodecError> {
+        match input.read_byte()? {
+            // Legacy `XCMPMessageV0::TransferToken` variant index
+            0 => Ok(VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(
+                Decode::decode(input)?,
+                Decode::decode(input)?,
+                Decode::decode(input)?,
+            ))),
+            V1_VERSION_BYTE => Ok(VersionedXCMPMessage::V1(Decode::decode(input)?)),
+            _ => Err("Unknown XCMP message version".into()),
+        }
+    }
+}
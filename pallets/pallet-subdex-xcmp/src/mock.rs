@@ -0,0 +1,260 @@
+// Creating mock runtime here
+
+use crate::{GenesisConfig, Module, Trait, VersionedXCMPMessage};
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    xcmp::XCMPMessageSender, ParaId, UpwardMessageOrigin, UpwardMessageSender,
+};
+use cumulus_upward_message::BalancesMessage;
+use frame_support::{impl_outer_dispatch, impl_outer_origin, parameter_types, weights::Weight};
+use frame_system as system;
+use pallet_subdex::DexTreasury;
+use sp_core::H256;
+use sp_runtime::{
+    testing::Header,
+    traits::{BlakeTwo256, IdentityLookup},
+    Perbill,
+};
+use std::cell::RefCell;
+
+impl_outer_origin! {
+    pub enum Origin for Test {}
+}
+
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        pallet_subdex::DexPallet,
+    }
+}
+
+pub type AccountId = u64;
+pub type Balance = u128;
+pub type AssetId = u64;
+
+pub type TestXCMPMessage = VersionedXCMPMessage<AccountId, Balance, AssetId>;
+
+// For testing the pallet, we construct most of a mock runtime. This means
+// first constructing a configuration type (`Test`) which `impl`s each of the
+// configuration traits of pallets we want to use.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Test;
+parameter_types! {
+    pub const BlockHashCount: u64 = 250;
+    pub const MaximumBlockWeight: Weight = 1024;
+    pub const MaximumBlockLength: u32 = 2 * 1024;
+    pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+}
+impl system::Trait for Test {
+    type BaseCallFilter = ();
+    type Origin = Origin;
+    type Call = ();
+    type Index = u64;
+    type BlockNumber = u64;
+    type Hash = H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = AccountId;
+    type Lookup = IdentityLookup<Self::AccountId>;
+    type Header = Header;
+    type Event = ();
+    type BlockHashCount = BlockHashCount;
+    type MaximumBlockWeight = MaximumBlockWeight;
+    type DbWeight = ();
+    type BlockExecutionWeight = ();
+    type ExtrinsicBaseWeight = ();
+    type MaximumExtrinsicWeight = MaximumBlockWeight;
+    type MaximumBlockLength = MaximumBlockLength;
+    type AvailableBlockRatio = AvailableBlockRatio;
+    type Version = ();
+    type ModuleToIndex = ();
+    type AccountData = balances::AccountData<Balance>;
+    type OnNewAccount = ();
+    type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1;
+}
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 1;
+}
+impl balances::Trait for Test {
+    type Balance = Balance;
+    type Event = ();
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const FeeRateNominator: Balance = 3;
+    pub const FeeRateDenominator: Balance = 1000;
+    pub const MaxScheduledWeight: Weight = 100_000_000;
+    pub const ScheduledOrderDeposit: Balance = 1_000;
+    pub const TwapPeriod: u64 = 60;
+    pub const MaxTwapWindow: u64 = 600;
+    pub const MaxTicksCrossed: u32 = 16;
+}
+impl pallet_subdex::Trait for Test {
+    type Event = ();
+    type Currency = Balances;
+    type IMoment = u64;
+    type AssetId = AssetId;
+    type FeeRateNominator = FeeRateNominator;
+    type FeeRateDenominator = FeeRateDenominator;
+    type MaxScheduledWeight = MaxScheduledWeight;
+    type ScheduledOrderDeposit = ScheduledOrderDeposit;
+    type TwapPeriod = TwapPeriod;
+    type MaxTwapWindow = MaxTwapWindow;
+    type MaxTicksCrossed = MaxTicksCrossed;
+    type Call = Call;
+}
+
+/// Upward message, recorded by the test message sender
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum TestUpwardMessage {
+    Transfer(AccountId, Balance),
+}
+
+impl BalancesMessage<AccountId, Balance> for TestUpwardMessage {
+    fn transfer(dest: AccountId, amount: Balance) -> Self {
+        TestUpwardMessage::Transfer(dest, amount)
+    }
+}
+
+thread_local! {
+    static SENT_UPWARD_MESSAGES: RefCell<Vec<TestUpwardMessage>> = RefCell::new(vec![]);
+    static SENT_XCMP_MESSAGES: RefCell<Vec<(ParaId, TestXCMPMessage)>> = RefCell::new(vec![]);
+    static REJECT_MESSAGES: RefCell<bool> = RefCell::new(false);
+}
+
+/// Message broker stub, which records sent messages or rejects them on demand
+pub struct TestMessageSender;
+
+impl TestMessageSender {
+    pub fn reset() {
+        SENT_UPWARD_MESSAGES.with(|m| m.borrow_mut().clear());
+        SENT_XCMP_MESSAGES.with(|m| m.borrow_mut().clear());
+        Self::reject_messages(false);
+    }
+
+    pub fn reject_messages(reject: bool) {
+        REJECT_MESSAGES.with(|r| *r.borrow_mut() = reject);
+    }
+
+    pub fn sent_upward_messages() -> Vec<TestUpwardMessage> {
+        SENT_UPWARD_MESSAGES.with(|m| m.borrow().clone())
+    }
+
+    pub fn sent_xcmp_messages() -> Vec<(ParaId, TestXCMPMessage)> {
+        SENT_XCMP_MESSAGES.with(|m| m.borrow().clone())
+    }
+
+    /// Take messages, sent since the last reset
+    pub fn take_sent_messages() -> (Vec<TestUpwardMessage>, Vec<(ParaId, TestXCMPMessage)>) {
+        (
+            SENT_UPWARD_MESSAGES.with(|m| m.borrow_mut().drain(..).collect()),
+            SENT_XCMP_MESSAGES.with(|m| m.borrow_mut().drain(..).collect()),
+        )
+    }
+
+    fn is_rejecting() -> bool {
+        REJECT_MESSAGES.with(|r| *r.borrow())
+    }
+}
+
+impl UpwardMessageSender<TestUpwardMessage> for TestMessageSender {
+    fn send_upward_message(
+        msg: &TestUpwardMessage,
+        _origin: UpwardMessageOrigin,
+    ) -> Result<(), ()> {
+        if Self::is_rejecting() {
+            return Err(());
+        }
+        SENT_UPWARD_MESSAGES.with(|m| m.borrow_mut().push(msg.clone()));
+        Ok(())
+    }
+}
+
+impl XCMPMessageSender<TestXCMPMessage> for TestMessageSender {
+    fn send_xcmp_message(dest: ParaId, msg: &TestXCMPMessage) -> Result<(), ()> {
+        if Self::is_rejecting() {
+            return Err(());
+        }
+        SENT_XCMP_MESSAGES.with(|m| m.borrow_mut().push((dest, msg.clone())));
+        Ok(())
+    }
+}
+
+parameter_types! {
+    pub const MaxPriceQueriesPerBlock: u32 = 2;
+    pub const TransferTimeout: u64 = 10;
+    pub const TransferDeliveryPeriod: u64 = 5;
+    pub const MaxPendingTransfersPerAccount: u32 = 3;
+    pub const MaxTrackedTransfersPerBlock: u32 = 3;
+}
+impl Trait for Test {
+    type Event = ();
+    type UpwardMessageSender = TestMessageSender;
+    type UpwardMessage = TestUpwardMessage;
+    type XCMPMessageSender = TestMessageSender;
+    type MaxPriceQueriesPerBlock = MaxPriceQueriesPerBlock;
+    type TransferTimeout = TransferTimeout;
+    type TransferDeliveryPeriod = TransferDeliveryPeriod;
+    type MaxPendingTransfersPerAccount = MaxPendingTransfersPerAccount;
+    type MaxTrackedTransfersPerBlock = MaxTrackedTransfersPerBlock;
+}
+
+pub type System = system::Module<Test>;
+pub type Balances = balances::Module<Test>;
+pub type Timestamp = pallet_timestamp::Module<Test>;
+pub type DexPallet = pallet_subdex::Module<Test>;
+pub type DexXCMP = Module<Test>;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+pub const TREASURY: AccountId = 100;
+
+pub const INITIAL_BALANCE: Balance = 1_000_000;
+
+// This function basically just builds a genesis storage key/value store according to
+// our desired mockup.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    TestMessageSender::reset();
+
+    let mut t = system::GenesisConfig::default()
+        .build_storage::<Test>()
+        .unwrap();
+
+    balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, INITIAL_BALANCE), (BOB, INITIAL_BALANCE)],
+    }
+    .assimilate_storage(&mut t)
+    .unwrap();
+
+    pallet_subdex::GenesisConfig::<Test> {
+        dex_treasury: DexTreasury::new(TREASURY, 1, 2),
+        assets: vec![],
+        initial_balance: 0,
+        endowed_accounts: vec![],
+    }
+    .assimilate_storage(&mut t)
+    .unwrap();
+
+    GenesisConfig::<Test> {
+        // 0 id reserved for main currency
+        next_asset_id: 1,
+    }
+    .assimilate_storage(&mut t)
+    .unwrap();
+
+    t.into()
+}
@@ -0,0 +1,155 @@
+// Multi-parachain XCMP simulator.
+//
+// Every parachain runs the mock runtime in its own externalities. Messages, recorded by the stub
+// message sender, are routed between them, while the relay chain is modelled by plain balances
+// of relay accounts and parachain sovereign accounts.
+
+use crate::mock::*;
+use codec::Encode;
+use cumulus_primitives::{
+    relay_chain::DownwardMessage, xcmp::XCMPMessageHandler, DownwardMessageHandler, ParaId,
+};
+use frame_support::traits::OnInitialize;
+use sp_core::crypto::AccountId32;
+use std::collections::{BTreeMap, VecDeque};
+
+// Guards against endless message ping-pong between parachains
+const MAX_ROUTED_MESSAGES: usize = 100;
+
+pub struct Network {
+    parachains: BTreeMap<ParaId, sp_io::TestExternalities>,
+    relay_balances: BTreeMap<AccountId, Balance>,
+    sovereign_balances: BTreeMap<ParaId, Balance>,
+    xcmp_queue: VecDeque<(ParaId, ParaId, TestXCMPMessage)>,
+    dropped_messages: Vec<(ParaId, ParaId, TestXCMPMessage)>,
+}
+
+impl Network {
+    /// Network of the given parachains. Relay chain accounts are endowed like parachain ones.
+    pub fn new(para_ids: &[u32]) -> Self {
+        Self {
+            parachains: para_ids
+                .iter()
+                .map(|para_id| (ParaId::from(*para_id), new_test_ext()))
+                .collect(),
+            relay_balances: vec![(ALICE, INITIAL_BALANCE), (BOB, INITIAL_BALANCE)]
+                .into_iter()
+                .collect(),
+            sovereign_balances: BTreeMap::new(),
+            xcmp_queue: VecDeque::new(),
+            dropped_messages: vec![],
+        }
+    }
+
+    /// Execute closure on the given parachain and queue messages, it has sent.
+    /// Upward messages are applied to the relay chain immediately.
+    pub fn execute_with<R>(&mut self, para_id: u32, execute: impl FnOnce() -> R) -> R {
+        let para_id = ParaId::from(para_id);
+        let ext = self
+            .parachains
+            .get_mut(&para_id)
+            .expect("Parachain is not part of the network");
+
+        TestMessageSender::reset();
+        let result = ext.execute_with(execute);
+        let (upward_messages, xcmp_messages) = TestMessageSender::take_sent_messages();
+
+        for TestUpwardMessage::Transfer(dest, amount) in upward_messages {
+            let sovereign_balance = self.sovereign_balances.entry(para_id).or_default();
+            *sovereign_balance = sovereign_balance
+                .checked_sub(amount)
+                .expect("Parachain sovereign account holds transferred amount");
+            *self.relay_balances.entry(dest).or_default() += amount;
+        }
+
+        self.xcmp_queue.extend(
+            xcmp_messages
+                .into_iter()
+                .map(|(dest, msg)| (para_id, dest, msg)),
+        );
+
+        result
+    }
+
+    /// Queue message, as if it was sent by the given parachain.
+    pub fn send_xcmp_message(&mut self, src: u32, dest: u32, msg: TestXCMPMessage) {
+        self.xcmp_queue
+            .push_back((ParaId::from(src), ParaId::from(dest), msg));
+    }
+
+    /// Deliver queued XCMP messages, including replies, sent while handling them.
+    /// Messages to the parachains outside of the network are dropped.
+    pub fn process_messages(&mut self) {
+        let mut routed_messages = 0;
+
+        while let Some((src, dest, msg)) = self.xcmp_queue.pop_front() {
+            routed_messages += 1;
+            assert!(
+                routed_messages <= MAX_ROUTED_MESSAGES,
+                "Too many routed messages"
+            );
+
+            if self.parachains.contains_key(&dest) {
+                self.execute_with(dest.into(), || DexXCMP::handle_xcmp_message(src, &msg));
+            } else {
+                self.dropped_messages.push((src, dest, msg));
+            }
+        }
+    }
+
+    /// Transfer main currency from the relay chain account to the account on the given parachain.
+    pub fn transfer_from_relay_chain(
+        &mut self,
+        from: AccountId,
+        para_id: u32,
+        dest: AccountId,
+        amount: Balance,
+        remark: [u8; 32],
+    ) {
+        let relay_balance = self.relay_balances.entry(from).or_default();
+        *relay_balance = relay_balance
+            .checked_sub(amount)
+            .expect("Relay chain account holds transferred amount");
+        *self
+            .sovereign_balances
+            .entry(ParaId::from(para_id))
+            .or_default() += amount;
+
+        let mut relay_dest = [0u8; 32];
+        relay_dest[..8].copy_from_slice(&dest.encode());
+
+        self.execute_with(para_id, || {
+            DexXCMP::handle_downward_message(&DownwardMessage::TransferInto(
+                AccountId32::from(relay_dest),
+                amount,
+                remark,
+            ))
+        });
+    }
+
+    /// Initialize blocks on the given parachain up to the given block number.
+    pub fn run_to_block(&mut self, para_id: u32, block_number: u64) {
+        self.execute_with(para_id, || {
+            while System::block_number() < block_number {
+                let next_block_number = System::block_number() + 1;
+                System::set_block_number(next_block_number);
+                DexXCMP::on_initialize(next_block_number);
+            }
+        });
+    }
+
+    pub fn relay_balance(&self, who: AccountId) -> Balance {
+        self.relay_balances.get(&who).copied().unwrap_or_default()
+    }
+
+    pub fn sovereign_balance(&self, para_id: u32) -> Balance {
+        self.sovereign_balances
+            .get(&ParaId::from(para_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn dropped_messages(&self) -> &[(ParaId, ParaId, TestXCMPMessage)] {
+        &self.dropped_messages
+    }
+}
@@ -0,0 +1,1605 @@
+use crate::{
+    mock::*, simulator::Network, DepositInstruction, DepositLimits, Error, FailureReason, Junction,
+    MultiAsset, MultiLocation, NextAssetId, PriceQuote, QueryId, SwapOutputDestination,
+    VersionedXCMPMessage, XCMPMessage, XCMPMessageV0, XCMPVersion,
+};
+use codec::{Decode, Encode};
+use cumulus_primitives::{
+    relay_chain::DownwardMessage, xcmp::XCMPMessageHandler, DownwardMessageHandler, ParaId,
+};
+use frame_support::{
+    assert_noop, assert_ok,
+    traits::{Currency, Get, IntegrityTest, OnInitialize},
+};
+use pallet_subdex::{Asset, PRICE_ONE};
+use sp_core::crypto::AccountId32;
+
+const PARA_ID: u32 = 300;
+const PARA_ASSET_ID: AssetId = 7;
+
+fn deposit_parachain_asset(dest: AccountId, amount: Balance) {
+    DexXCMP::handle_xcmp_message(
+        ParaId::from(PARA_ID),
+        &VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(
+            dest,
+            amount,
+            Some(PARA_ASSET_ID),
+        )),
+    );
+}
+
+#[test]
+fn transfer_balance_to_relay_chain_sends_upward_message() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::transfer_balance_to_relay_chain(
+            Origin::signed(ALICE),
+            BOB,
+            1000
+        ));
+
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE - 1000);
+        assert_eq!(
+            TestMessageSender::sent_upward_messages(),
+            vec![TestUpwardMessage::Transfer(BOB, 1000)]
+        );
+    });
+}
+
+#[test]
+fn transfer_balance_to_relay_chain_keeps_funds_when_send_fails() {
+    new_test_ext().execute_with(|| {
+        TestMessageSender::reject_messages(true);
+
+        assert_noop!(
+            DexXCMP::transfer_balance_to_relay_chain(Origin::signed(ALICE), BOB, 1000),
+            Error::<Test>::MessageSendFailed
+        );
+
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE);
+        assert!(TestMessageSender::sent_upward_messages().is_empty());
+    });
+}
+
+#[test]
+fn transfer_asset_balance_to_parachain_sends_xcmp_message() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+        let asset_id =
+            DexXCMP::asset_id_by_para_asset_id(ParaId::from(PARA_ID), Some(PARA_ASSET_ID));
+
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+
+        assert_eq!(DexPallet::asset_balances(ALICE, asset_id), 300);
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(
+                    BOB,
+                    200,
+                    Some(PARA_ASSET_ID)
+                ))
+            )]
+        );
+    });
+}
+
+#[test]
+fn transfer_asset_balance_to_parachain_keeps_funds_when_send_fails() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+        let asset_id =
+            DexXCMP::asset_id_by_para_asset_id(ParaId::from(PARA_ID), Some(PARA_ASSET_ID));
+
+        TestMessageSender::reject_messages(true);
+
+        assert_noop!(
+            DexXCMP::transfer_asset_balance_to_parachain_chain(
+                Origin::signed(ALICE),
+                PARA_ID,
+                BOB,
+                Some(PARA_ASSET_ID),
+                200
+            ),
+            Error::<Test>::MessageSendFailed
+        );
+
+        assert_eq!(DexPallet::asset_balances(ALICE, asset_id), 500);
+        assert!(TestMessageSender::sent_xcmp_messages().is_empty());
+    });
+}
+
+#[test]
+fn refund_failed_message_keeps_message_parked_when_send_fails() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, Balance::max_value());
+        deposit_parachain_asset(ALICE, 1);
+        assert!(DexXCMP::failed_messages(0).is_some());
+
+        TestMessageSender::reject_messages(true);
+
+        assert_noop!(
+            DexXCMP::refund_failed_message(Origin::signed(ALICE), 0),
+            Error::<Test>::MessageSendFailed
+        );
+
+        TestMessageSender::reject_messages(false);
+
+        assert_ok!(DexXCMP::refund_failed_message(Origin::signed(ALICE), 0));
+        assert!(DexXCMP::failed_messages(0).is_none());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), Balance::max_value());
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(
+                    ALICE,
+                    1,
+                    Some(PARA_ASSET_ID)
+                ))
+            )]
+        );
+    });
+}
+
+#[test]
+fn parked_message_cannot_be_managed_by_others() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, Balance::max_value());
+        deposit_parachain_asset(ALICE, 1);
+
+        assert_noop!(
+            DexXCMP::retry_failed_message(Origin::signed(BOB), 0),
+            Error::<Test>::NotFailedMessageOwner
+        );
+        assert_noop!(
+            DexXCMP::refund_failed_message(Origin::signed(BOB), 0),
+            Error::<Test>::NotFailedMessageOwner
+        );
+    });
+}
+
+#[test]
+fn backing_ledger_tracks_inbound_and_outbound_transfers() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 500);
+
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 300);
+        assert_eq!(
+            DexXCMP::backing_ledger(),
+            vec![(PARA_ID, Some(PARA_ASSET_ID), 300)]
+        );
+    });
+}
+
+#[test]
+fn withdrawal_beyond_backed_amount_fails() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+        let asset_id =
+            DexXCMP::asset_id_by_para_asset_id(ParaId::from(PARA_ID), Some(PARA_ASSET_ID));
+
+        // Unbacked tokens, minted bypassing the XCMP deposit
+        DexPallet::mint_asset(&ALICE, Asset::ParachainAsset(asset_id), 500);
+
+        assert_noop!(
+            DexXCMP::transfer_asset_balance_to_parachain_chain(
+                Origin::signed(ALICE),
+                PARA_ID,
+                BOB,
+                Some(PARA_ASSET_ID),
+                800
+            ),
+            Error::<Test>::InsufficientBacking
+        );
+    });
+}
+
+#[test]
+fn legacy_message_encoding_is_preserved() {
+    let legacy = XCMPMessageV0::<AccountId, Balance, AssetId>::TransferToken(ALICE, 10, None);
+    let versioned = VersionedXCMPMessage::V0(legacy.clone());
+
+    assert_eq!(versioned.encode(), legacy.encode());
+    assert_eq!(
+        TestXCMPMessage::decode(&mut &legacy.encode()[..]).ok(),
+        Some(versioned)
+    );
+}
+
+#[test]
+fn versioned_message_roundtrip() {
+    let msg: TestXCMPMessage =
+        VersionedXCMPMessage::V1(XCMPMessage::TransferToken(ALICE, 10, Some(PARA_ASSET_ID)));
+
+    assert_eq!(
+        TestXCMPMessage::decode(&mut &msg.encode()[..]).ok(),
+        Some(msg)
+    );
+    assert!(TestXCMPMessage::decode(&mut &[42u8][..]).is_err());
+}
+
+#[test]
+fn configured_xcmp_version_is_used_for_sending() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+
+        assert_noop!(
+            DexXCMP::set_xcmp_version(Origin::signed(ALICE), PARA_ID, XCMPVersion::V1),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TrackedTransferToken(
+                    0,
+                    BOB,
+                    200,
+                    Some(PARA_ASSET_ID),
+                    TransferDeliveryPeriod::get()
+                ))
+            )]
+        );
+    });
+}
+
+fn initialize_main_currency_exchange(asset_id: AssetId, amount: Balance) {
+    deposit_parachain_asset(ALICE, amount);
+    assert_ok!(DexPallet::initialize_exchange(
+        Origin::signed(ALICE),
+        Asset::MainNetworkCurrency,
+        amount,
+        Asset::ParachainAsset(asset_id),
+        amount
+    ));
+}
+
+fn transfer_and_swap(dest: AccountId, amount: Balance, min_target_amount: Balance) {
+    DexXCMP::handle_xcmp_message(
+        ParaId::from(PARA_ID),
+        &VersionedXCMPMessage::V1(XCMPMessage::TransferAndSwap(
+            dest,
+            amount,
+            Some(PARA_ASSET_ID),
+            None,
+            min_target_amount,
+        )),
+    );
+}
+
+#[test]
+fn transfer_and_swap_swaps_deposited_asset_on_arrival() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+
+        transfer_and_swap(BOB, 1000, 900);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert!(Balances::free_balance(BOB) >= INITIAL_BALANCE + 900);
+        assert_eq!(
+            DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)),
+            101_000
+        );
+    });
+}
+
+#[test]
+fn transfer_and_swap_keeps_deposit_when_slippage_exceeded() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+
+        transfer_and_swap(BOB, 1000, 1000);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 1000);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+        assert!(DexXCMP::failed_messages(0).is_none());
+    });
+}
+
+#[test]
+fn swap_and_send_delivers_output_to_relay_chain() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+        deposit_parachain_asset(BOB, 1000);
+
+        assert_ok!(DexXCMP::swap_and_send(
+            Origin::signed(BOB),
+            Asset::ParachainAsset(1),
+            1000,
+            SwapOutputDestination::RelayChain,
+            900,
+            BOB
+        ));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+        match TestMessageSender::sent_upward_messages().as_slice() {
+            [TestUpwardMessage::Transfer(BOB, amount)] => assert!(*amount >= 900),
+            messages => panic!("Unexpected upward messages: {:?}", messages),
+        }
+    });
+}
+
+#[test]
+fn swap_and_send_delivers_output_to_parachain() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        assert_ok!(DexXCMP::swap_and_send(
+            Origin::signed(BOB),
+            Asset::MainNetworkCurrency,
+            1000,
+            SwapOutputDestination::Parachain(PARA_ID, Some(PARA_ASSET_ID)),
+            900,
+            BOB
+        ));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 1000);
+        match TestMessageSender::sent_xcmp_messages().as_slice() {
+            [(
+                para_id,
+                VersionedXCMPMessage::V1(XCMPMessage::TrackedTransferToken(
+                    0,
+                    BOB,
+                    amount,
+                    Some(PARA_ASSET_ID),
+                    _,
+                )),
+            )] => {
+                assert_eq!(*para_id, ParaId::from(PARA_ID));
+                assert!(*amount >= 900);
+                assert_eq!(
+                    DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)),
+                    100_000 - *amount
+                );
+            }
+            messages => panic!("Unexpected XCMP messages: {:?}", messages),
+        }
+    });
+}
+
+#[test]
+fn swap_and_send_does_not_swap_when_send_fails() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+        TestMessageSender::reject_messages(true);
+
+        assert_noop!(
+            DexXCMP::swap_and_send(
+                Origin::signed(BOB),
+                Asset::MainNetworkCurrency,
+                1000,
+                SwapOutputDestination::Parachain(PARA_ID, Some(PARA_ASSET_ID)),
+                900,
+                BOB
+            ),
+            Error::<Test>::MessageSendFailed
+        );
+    });
+}
+
+fn query_price(query_id: QueryId) {
+    DexXCMP::handle_xcmp_message(
+        ParaId::from(PARA_ID),
+        &VersionedXCMPMessage::V1(XCMPMessage::QueryPrice(None, Some(1), query_id)),
+    );
+}
+
+#[test]
+fn price_query_is_answered_with_spot_and_average_price() {
+    new_test_ext().execute_with(|| {
+        // Query arrives in V1 envelope, even though the parachain is not known to speak V1
+        query_price(1);
+        initialize_main_currency_exchange(1, 100_000);
+        query_price(2);
+
+        System::set_block_number(1);
+        Timestamp::set_timestamp(30);
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            Asset::MainNetworkCurrency,
+            25_000,
+            Asset::ParachainAsset(1),
+            0,
+            BOB
+        ));
+        let spot_price =
+            DexPallet::spot_price(Asset::MainNetworkCurrency, Asset::ParachainAsset(1)).unwrap();
+        assert!(spot_price < PRICE_ONE);
+        query_price(3);
+
+        // Average price is taken over at least the TWAP period
+        System::set_block_number(2);
+        Timestamp::set_timestamp(TwapPeriod::get());
+        query_price(4);
+
+        let price_response = |query_id, price_quote| {
+            (
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::PriceResponse(query_id, price_quote)),
+            )
+        };
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![
+                price_response(1, None),
+                price_response(
+                    2,
+                    Some(PriceQuote {
+                        spot_price: PRICE_ONE,
+                        average_price: None
+                    })
+                ),
+                price_response(
+                    3,
+                    Some(PriceQuote {
+                        spot_price,
+                        average_price: None
+                    })
+                ),
+                price_response(
+                    4,
+                    Some(PriceQuote {
+                        spot_price,
+                        average_price: Some((PRICE_ONE + spot_price) / 2)
+                    })
+                )
+            ]
+        );
+    });
+}
+
+#[test]
+fn price_query_is_not_averaged_beyond_maximum_window() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+        query_price(1);
+
+        System::set_block_number(1);
+        Timestamp::set_timestamp(MaxTwapWindow::get() + 1);
+        query_price(2);
+
+        System::set_block_number(2);
+        Timestamp::set_timestamp(MaxTwapWindow::get() + 1 + TwapPeriod::get());
+        query_price(3);
+
+        let average_prices: Vec<_> = TestMessageSender::sent_xcmp_messages()
+            .into_iter()
+            .filter_map(|(_, message)| match message {
+                VersionedXCMPMessage::V1(XCMPMessage::PriceResponse(_, Some(price_quote))) => {
+                    Some(price_quote.average_price)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(average_prices, vec![None, None, Some(PRICE_ONE)]);
+    });
+}
+
+#[test]
+fn price_queries_are_rate_limited_per_block() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+
+        System::set_block_number(1);
+        query_price(1);
+        query_price(2);
+        query_price(3);
+        assert_eq!(TestMessageSender::sent_xcmp_messages().len(), 2);
+
+        System::set_block_number(2);
+        query_price(4);
+        assert_eq!(TestMessageSender::sent_xcmp_messages().len(), 3);
+    });
+}
+
+fn transfer_from_relay_chain(
+    dest: AccountId,
+    amount: Balance,
+    instruction: DepositInstruction<Balance, AssetId>,
+) {
+    let mut relay_dest = [0u8; 32];
+    relay_dest[..8].copy_from_slice(&dest.encode());
+
+    let mut remark = [0u8; 32];
+    let encoded_instruction = instruction.encode();
+    remark[..encoded_instruction.len()].copy_from_slice(&encoded_instruction);
+
+    DexXCMP::handle_downward_message(&DownwardMessage::TransferInto(
+        AccountId32::from(relay_dest),
+        amount,
+        remark,
+    ));
+}
+
+#[test]
+fn relay_chain_transfer_with_empty_remark_is_plain_deposit() {
+    new_test_ext().execute_with(|| {
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::Deposit);
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+    });
+}
+
+#[test]
+fn relay_chain_transfer_swaps_deposit_on_instruction() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::DepositAndSwap(1, 900));
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+        assert!(DexPallet::asset_balances(BOB, 1) >= 900);
+    });
+}
+
+#[test]
+fn relay_chain_transfer_falls_back_to_plain_deposit() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::DepositAndSwap(1, 1000));
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+    });
+}
+
+#[test]
+fn relay_chain_transfer_invests_deposit_on_instruction() {
+    new_test_ext().execute_with(|| {
+        initialize_main_currency_exchange(1, 100_000);
+        deposit_parachain_asset(BOB, 1000);
+
+        // Invested deposit would cost more of the other asset, than allowed, so it is kept
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::DepositAndInvest(1, 999));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 1000);
+
+        // Only the deposited main currency is invested
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::DepositAndInvest(1, 1000));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+    });
+}
+
+#[test]
+fn relay_chain_transfer_overflowing_balance_is_parked_until_refunded() {
+    new_test_ext().execute_with(|| {
+        Balances::make_free_balance_be(&BOB, Balance::max_value());
+
+        transfer_from_relay_chain(BOB, 1000, DepositInstruction::Deposit);
+        assert_eq!(Balances::free_balance(BOB), Balance::max_value());
+        assert_eq!(
+            DexXCMP::failed_messages(0).map(|failed_message| failed_message.reason),
+            Some(FailureReason::BalanceOverflow)
+        );
+
+        assert_noop!(
+            DexXCMP::retry_failed_message(Origin::signed(BOB), 0),
+            Error::<Test>::FailedMessageProcessingFailed
+        );
+
+        assert_ok!(DexXCMP::refund_failed_message(Origin::signed(BOB), 0));
+        assert!(DexXCMP::failed_messages(0).is_none());
+        assert_eq!(Balances::free_balance(BOB), Balance::max_value());
+        assert_eq!(
+            TestMessageSender::sent_upward_messages(),
+            vec![TestUpwardMessage::Transfer(BOB, 1000)]
+        );
+    });
+}
+
+#[test]
+fn transfer_balance_to_parachain_sends_main_currency() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            1000
+        ));
+
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE - 1000);
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)),
+            1000
+        );
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(BOB, 1000, None))
+            )]
+        );
+    });
+}
+
+#[test]
+fn transfer_balance_to_parachain_keeps_funds_when_send_fails() {
+    new_test_ext().execute_with(|| {
+        TestMessageSender::reject_messages(true);
+
+        assert_noop!(
+            DexXCMP::transfer_balance_to_parachain_chain(Origin::signed(ALICE), PARA_ID, BOB, 1000),
+            Error::<Test>::MessageSendFailed
+        );
+    });
+}
+
+fn send_tracked_asset_transfer(amount: Balance) {
+    assert_ok!(DexXCMP::set_xcmp_version(
+        Origin::root(),
+        PARA_ID,
+        XCMPVersion::V1
+    ));
+    deposit_parachain_asset(ALICE, 500);
+
+    assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+        Origin::signed(ALICE),
+        PARA_ID,
+        BOB,
+        Some(PARA_ASSET_ID),
+        amount
+    ));
+}
+
+fn reply_from_parachain(msg: XCMPMessage<AccountId, Balance, AssetId>) {
+    DexXCMP::handle_xcmp_message(ParaId::from(PARA_ID), &VersionedXCMPMessage::V1(msg));
+}
+
+#[test]
+fn tracked_transfer_is_pending_until_acknowledged() {
+    new_test_ext().execute_with(|| {
+        send_tracked_asset_transfer(200);
+
+        let in_flight_transfers = DexXCMP::in_flight_transfers(&ALICE);
+        assert_eq!(in_flight_transfers.len(), 1);
+        let (transfer_id, pending_transfer) = &in_flight_transfers[0];
+        assert_eq!(*transfer_id, 0);
+        assert_eq!(pending_transfer.para_id, ParaId::from(PARA_ID));
+        assert_eq!(pending_transfer.amount, 200);
+        assert_eq!(pending_transfer.deadline, TransferTimeout::get());
+
+        // Acknowledgement from another parachain is ignored
+        DexXCMP::handle_xcmp_message(
+            ParaId::from(PARA_ID + 1),
+            &VersionedXCMPMessage::V1(XCMPMessage::TransferAck(0)),
+        );
+        assert!(DexXCMP::pending_transfers(0).is_some());
+
+        reply_from_parachain(XCMPMessage::TransferAck(0));
+
+        assert!(DexXCMP::pending_transfers(0).is_none());
+        assert!(DexXCMP::in_flight_transfers(&ALICE).is_empty());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 300);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 300);
+    });
+}
+
+#[test]
+fn rejected_transfer_is_refunded() {
+    new_test_ext().execute_with(|| {
+        send_tracked_asset_transfer(200);
+
+        reply_from_parachain(XCMPMessage::TransferRejected(0));
+
+        assert!(DexXCMP::pending_transfers(0).is_none());
+        assert!(DexXCMP::transfer_deadlines(TransferTimeout::get()).is_empty());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 500);
+    });
+}
+
+#[test]
+fn unacknowledged_transfer_is_refunded_after_timeout() {
+    new_test_ext().execute_with(|| {
+        send_tracked_asset_transfer(200);
+        let deadline = TransferTimeout::get();
+
+        DexXCMP::on_initialize(deadline - 1);
+        assert!(DexXCMP::pending_transfers(0).is_some());
+
+        DexXCMP::on_initialize(deadline);
+
+        assert!(DexXCMP::pending_transfers(0).is_none());
+        assert!(DexXCMP::in_flight_transfers(&ALICE).is_empty());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 500);
+
+        // Destination rejects the transfer after its deadline, so it can not be acknowledged once refunded
+        reply_from_parachain(XCMPMessage::TransferRejected(0));
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+    });
+}
+
+#[test]
+fn tracked_transfers_are_bounded() {
+    new_test_ext().execute_with(|| {
+        send_tracked_asset_transfer(100);
+        for _ in 0..2 {
+            assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+                Origin::signed(ALICE),
+                PARA_ID,
+                BOB,
+                Some(PARA_ASSET_ID),
+                100
+            ));
+        }
+
+        assert_noop!(
+            DexXCMP::transfer_asset_balance_to_parachain_chain(
+                Origin::signed(ALICE),
+                PARA_ID,
+                BOB,
+                Some(PARA_ASSET_ID),
+                100
+            ),
+            Error::<Test>::TooManyPendingTransfers
+        );
+        assert_noop!(
+            DexXCMP::transfer_balance_to_parachain_chain(Origin::signed(BOB), PARA_ID, ALICE, 100),
+            Error::<Test>::TooManyTrackedTransfersInBlock
+        );
+
+        // Refunds are spread over the blocks
+        System::set_block_number(2);
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(BOB),
+            PARA_ID,
+            ALICE,
+            100
+        ));
+    });
+}
+
+#[test]
+fn main_currency_in_transit_is_released_on_refund() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            1000
+        ));
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            500
+        ));
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)),
+            1500
+        );
+
+        // Acknowledged amount is held by the parachain, until it is returned
+        reply_from_parachain(XCMPMessage::TransferAck(0));
+        reply_from_parachain(XCMPMessage::TransferRejected(1));
+
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)),
+            1000
+        );
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE - 1000);
+    });
+}
+
+#[test]
+fn main_currency_returned_by_parachain_is_released_from_transit() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            1000
+        ));
+
+        // Untracked transfer is returned as main currency, up to the amount in transit
+        DexXCMP::handle_xcmp_message(
+            ParaId::from(PARA_ID),
+            &VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(BOB, 600, None)),
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 600);
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)),
+            400
+        );
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, None), 0);
+
+        // Main currency beyond the amount in transit is wrapped
+        DexXCMP::handle_xcmp_message(
+            ParaId::from(PARA_ID),
+            &VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(BOB, 500, None)),
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 600);
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 500);
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)),
+            400
+        );
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, None), 500);
+    });
+}
+
+#[test]
+fn transfer_delivery_period_ends_before_timeout() {
+    <DexXCMP as IntegrityTest>::integrity_test();
+}
+
+#[test]
+fn inbound_tracked_transfer_is_acknowledged() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+
+        reply_from_parachain(XCMPMessage::TrackedTransferToken(
+            5,
+            BOB,
+            300,
+            Some(PARA_ASSET_ID),
+            10,
+        ));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 300);
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TransferAck(5))
+            )]
+        );
+    });
+}
+
+#[test]
+fn inbound_tracked_transfer_is_not_deposited_when_ack_fails() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        TestMessageSender::reject_messages(true);
+
+        reply_from_parachain(XCMPMessage::TrackedTransferToken(
+            5,
+            BOB,
+            300,
+            Some(PARA_ASSET_ID),
+            10,
+        ));
+
+        // Sender refunds the unacknowledged transfer on timeout
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 0);
+        assert!(
+            DexXCMP::ensure_asset_id_exists(ParaId::from(PARA_ID), Some(PARA_ASSET_ID)).is_err()
+        );
+        assert!(TestMessageSender::sent_xcmp_messages().is_empty());
+
+        // Transfer, which exceeds deposit limits, is not quarantined either
+        set_deposit_limits(100, 1000, 10);
+        reply_from_parachain(XCMPMessage::TrackedTransferToken(
+            6,
+            BOB,
+            300,
+            Some(PARA_ASSET_ID),
+            10,
+        ));
+        assert!(DexXCMP::quarantined_deposits(0).is_none());
+        assert_eq!(DexXCMP::next_quarantine_id(), 0);
+    });
+}
+
+fn set_deposit_limits(max_per_message: Balance, max_per_window: Balance, window: u64) {
+    assert_ok!(DexXCMP::set_deposit_limits(
+        Origin::root(),
+        PARA_ID,
+        Some(PARA_ASSET_ID),
+        Some(DepositLimits {
+            max_per_message,
+            max_per_window,
+            window,
+        })
+    ));
+}
+
+#[test]
+fn deposit_over_message_limit_is_quarantined_until_released() {
+    new_test_ext().execute_with(|| {
+        set_deposit_limits(100, 1000, 10);
+
+        deposit_parachain_asset(BOB, 150);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 0);
+        assert!(DexXCMP::quarantined_deposits(0).is_some());
+
+        assert_noop!(
+            DexXCMP::release_quarantined_deposit(Origin::signed(BOB), 0),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_ok!(DexXCMP::release_quarantined_deposit(Origin::root(), 0));
+
+        assert!(DexXCMP::quarantined_deposits(0).is_none());
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 150);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 150);
+
+        assert_noop!(
+            DexXCMP::release_quarantined_deposit(Origin::root(), 0),
+            Error::<Test>::QuarantinedDepositDoesNotExist
+        );
+    });
+}
+
+#[test]
+fn deposits_over_window_limit_are_quarantined() {
+    new_test_ext().execute_with(|| {
+        set_deposit_limits(1000, 300, 1);
+
+        deposit_parachain_asset(BOB, 200);
+        deposit_parachain_asset(BOB, 200);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 200);
+        assert!(DexXCMP::quarantined_deposits(0).is_some());
+
+        // New window starts in the next block
+        System::set_block_number(1);
+        deposit_parachain_asset(BOB, 200);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 400);
+        assert!(DexXCMP::quarantined_deposits(1).is_none());
+    });
+}
+
+#[test]
+fn zero_deposit_window_is_rejected() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexXCMP::set_deposit_limits(
+                Origin::root(),
+                PARA_ID,
+                Some(PARA_ASSET_ID),
+                Some(DepositLimits {
+                    max_per_message: 1000,
+                    max_per_window: 1000,
+                    window: 0,
+                })
+            ),
+            Error::<Test>::ZeroDepositWindow
+        );
+    });
+}
+
+#[test]
+fn deposits_over_issuance_ceiling_are_quarantined() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_issuance_ceiling(
+            Origin::root(),
+            PARA_ID,
+            Some(PARA_ASSET_ID),
+            Some(250)
+        ));
+
+        deposit_parachain_asset(BOB, 200);
+        deposit_parachain_asset(BOB, 100);
+        // Same asset id on another parachain is a different asset with its own issuance
+        DexXCMP::handle_xcmp_message(
+            ParaId::from(PARA_ID + 1),
+            &VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(BOB, 400, Some(PARA_ASSET_ID))),
+        );
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 200);
+        assert_eq!(DexPallet::asset_balances(BOB, 2), 400);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 200);
+        assert_eq!(
+            DexXCMP::quarantined_deposits(0).map(|deposit| deposit.src),
+            Some(ParaId::from(PARA_ID))
+        );
+
+        // Each parachain asset is capped separately
+        assert_ok!(DexXCMP::set_issuance_ceiling(
+            Origin::root(),
+            PARA_ID + 1,
+            Some(PARA_ASSET_ID),
+            Some(450)
+        ));
+        DexXCMP::handle_xcmp_message(
+            ParaId::from(PARA_ID + 1),
+            &VersionedXCMPMessage::V0(XCMPMessageV0::TransferToken(BOB, 100, Some(PARA_ASSET_ID))),
+        );
+        deposit_parachain_asset(BOB, 50);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 250);
+        assert_eq!(DexPallet::asset_balances(BOB, 2), 400);
+        assert_eq!(
+            DexXCMP::quarantined_deposits(1).map(|deposit| deposit.src),
+            Some(ParaId::from(PARA_ID + 1))
+        );
+
+        // Ceiling removal lets further deposits through
+        assert_ok!(DexXCMP::set_issuance_ceiling(
+            Origin::root(),
+            PARA_ID,
+            Some(PARA_ASSET_ID),
+            None
+        ));
+        deposit_parachain_asset(BOB, 100);
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 350);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 350);
+    });
+}
+
+#[test]
+fn inbound_tracked_transfer_is_rejected_after_deadline() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        // Expired transfers are rejected before deposit limits are checked
+        set_deposit_limits(100, 1000, 10);
+
+        Timestamp::set_timestamp(11);
+        reply_from_parachain(XCMPMessage::TrackedTransferToken(
+            5,
+            BOB,
+            300,
+            Some(PARA_ASSET_ID),
+            10,
+        ));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert!(DexXCMP::quarantined_deposits(0).is_none());
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TransferRejected(5))
+            )]
+        );
+    });
+}
+
+#[test]
+fn quarantined_tracked_transfer_is_acknowledged() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        set_deposit_limits(100, 1000, 10);
+
+        reply_from_parachain(XCMPMessage::TrackedTransferToken(
+            5,
+            BOB,
+            300,
+            Some(PARA_ASSET_ID),
+            10,
+        ));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(
+            DexXCMP::quarantined_deposits(0).map(|deposit| deposit.message),
+            Some(XCMPMessage::TransferToken(BOB, 300, Some(PARA_ASSET_ID)))
+        );
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TransferAck(5))
+            )]
+        );
+    });
+}
+
+fn transfer_multi_asset(dest: AccountId, location: MultiLocation, amount: Balance) {
+    reply_from_parachain(XCMPMessage::TransferMultiAsset(
+        dest,
+        MultiAsset {
+            id: location,
+            amount,
+        },
+    ));
+}
+
+#[test]
+fn multi_asset_transfer_with_canonical_location_is_deposited() {
+    new_test_ext().execute_with(|| {
+        let location =
+            MultiLocation::sibling_asset(ParaId::from(PARA_ID), Some(PARA_ASSET_ID)).unwrap();
+        assert_eq!(
+            location.interior,
+            vec![
+                Junction::Parachain(PARA_ID),
+                Junction::GeneralIndex(PARA_ASSET_ID as u128)
+            ]
+        );
+
+        transfer_multi_asset(BOB, location.clone(), 300);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 300);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 300);
+        assert_eq!(DexXCMP::asset_id_by_location(&location), Some(1));
+    });
+}
+
+#[test]
+fn multi_asset_transfer_of_foreign_reserve_is_parked() {
+    new_test_ext().execute_with(|| {
+        let location =
+            MultiLocation::sibling_asset(ParaId::from(PARA_ID + 1), Some(PARA_ASSET_ID)).unwrap();
+
+        transfer_multi_asset(BOB, location.clone(), 300);
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_eq!(
+            DexXCMP::failed_messages(0).map(|failed_message| failed_message.reason),
+            Some(FailureReason::UnknownAssetLocation)
+        );
+
+        // Asset is sent back as it is, even though the origin is not known to speak V1
+        assert_ok!(DexXCMP::refund_failed_message(Origin::signed(BOB), 0));
+        assert!(DexXCMP::failed_messages(0).is_none());
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TransferMultiAsset(
+                    BOB,
+                    MultiAsset {
+                        id: location,
+                        amount: 300
+                    }
+                ))
+            )]
+        );
+    });
+}
+
+#[test]
+fn multi_asset_transfer_of_unknown_location_is_refunded_relative_to_origin() {
+    new_test_ext().execute_with(|| {
+        let location = MultiLocation {
+            parents: 1,
+            interior: vec![
+                Junction::Parachain(PARA_ID),
+                Junction::PalletInstance(5),
+                Junction::GeneralKey(b"USDT".to_vec()),
+            ],
+        };
+
+        transfer_multi_asset(BOB, location, 300);
+        assert_ok!(DexXCMP::refund_failed_message(Origin::signed(BOB), 0));
+
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![(
+                ParaId::from(PARA_ID),
+                VersionedXCMPMessage::V1(XCMPMessage::TransferMultiAsset(
+                    BOB,
+                    MultiAsset {
+                        id: MultiLocation {
+                            parents: 0,
+                            interior: vec![
+                                Junction::PalletInstance(5),
+                                Junction::GeneralKey(b"USDT".to_vec()),
+                            ],
+                        },
+                        amount: 300
+                    }
+                ))
+            )]
+        );
+
+        // Location within our chain can not be addressed from the origin
+        transfer_multi_asset(BOB, MultiLocation::local_asset(1u32).unwrap(), 300);
+        assert_noop!(
+            DexXCMP::refund_failed_message(Origin::signed(BOB), 1),
+            Error::<Test>::NothingToRefund
+        );
+    });
+}
+
+#[test]
+fn multi_asset_transfer_of_relay_currency_returns_main_currency() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            1000
+        ));
+
+        transfer_multi_asset(BOB, MultiLocation::relay_currency(), 1000);
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+        assert_eq!(DexXCMP::main_currency_in_transit(ParaId::from(PARA_ID)), 0);
+    });
+}
+
+#[test]
+fn transfers_are_sent_as_multi_assets_when_enabled() {
+    new_test_ext().execute_with(|| {
+        deposit_parachain_asset(ALICE, 500);
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+        assert_noop!(
+            DexXCMP::set_multi_asset_transfers(Origin::signed(ALICE), PARA_ID, true),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_ok!(DexXCMP::set_multi_asset_transfers(
+            Origin::root(),
+            PARA_ID,
+            true
+        ));
+
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            1000
+        ));
+
+        // Parachain asset is located within the destination, main currency at the relay chain.
+        // XCM-style transfers are not tracked.
+        assert!(DexXCMP::in_flight_transfers(&ALICE).is_empty());
+        assert_eq!(
+            TestMessageSender::sent_xcmp_messages(),
+            vec![
+                (
+                    ParaId::from(PARA_ID),
+                    VersionedXCMPMessage::V1(XCMPMessage::TransferMultiAsset(
+                        BOB,
+                        MultiAsset {
+                            id: MultiLocation::local_asset(PARA_ASSET_ID).unwrap(),
+                            amount: 200
+                        }
+                    ))
+                ),
+                (
+                    ParaId::from(PARA_ID),
+                    VersionedXCMPMessage::V1(XCMPMessage::TransferMultiAsset(
+                        BOB,
+                        MultiAsset {
+                            id: MultiLocation::relay_currency(),
+                            amount: 1000
+                        }
+                    ))
+                )
+            ]
+        );
+    });
+}
+
+#[test]
+fn multi_asset_transfer_with_registered_location_is_retried() {
+    new_test_ext().execute_with(|| {
+        let location = MultiLocation {
+            parents: 1,
+            interior: vec![
+                Junction::Parachain(PARA_ID),
+                Junction::PalletInstance(5),
+                Junction::GeneralKey(b"USDT".to_vec()),
+            ],
+        };
+
+        transfer_multi_asset(BOB, location.clone(), 300);
+        assert!(DexXCMP::failed_messages(0).is_some());
+
+        assert_noop!(
+            DexXCMP::set_asset_location(
+                Origin::signed(BOB),
+                location.clone(),
+                Some((PARA_ID, Some(PARA_ASSET_ID)))
+            ),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_ok!(DexXCMP::set_asset_location(
+            Origin::root(),
+            location.clone(),
+            Some((PARA_ID, Some(PARA_ASSET_ID)))
+        ));
+        assert_ok!(DexXCMP::retry_failed_message(Origin::signed(BOB), 0));
+
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 300);
+        assert_eq!(DexXCMP::asset_id_by_location(&location), Some(1));
+    });
+}
+
+// End-to-end flows, simulated on the network of dex and asset origin parachains
+
+const DEX_PARA_ID: u32 = 200;
+const UNREACHABLE_PARA_ID: u32 = 400;
+
+fn new_network() -> Network {
+    let mut network = Network::new(&[DEX_PARA_ID, PARA_ID]);
+    network.execute_with(DEX_PARA_ID, || {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            PARA_ID,
+            XCMPVersion::V1
+        ));
+    });
+    network.execute_with(PARA_ID, || {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            DEX_PARA_ID,
+            XCMPVersion::V1
+        ));
+    });
+    network
+}
+
+fn send_asset_to_dex(network: &mut Network, dest: AccountId, amount: Balance) {
+    network.send_xcmp_message(
+        PARA_ID,
+        DEX_PARA_ID,
+        VersionedXCMPMessage::V1(XCMPMessage::TransferToken(
+            dest,
+            amount,
+            Some(PARA_ASSET_ID),
+        )),
+    );
+    network.process_messages();
+}
+
+#[test]
+fn simulated_relay_chain_deposit_and_withdrawal() {
+    let mut network = new_network();
+
+    network.transfer_from_relay_chain(ALICE, DEX_PARA_ID, BOB, 1000, [0u8; 32]);
+    network.execute_with(DEX_PARA_ID, || {
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 1000);
+
+        assert_ok!(DexXCMP::transfer_balance_to_relay_chain(
+            Origin::signed(BOB),
+            ALICE,
+            400
+        ));
+    });
+
+    assert_eq!(network.relay_balance(ALICE), INITIAL_BALANCE - 600);
+    assert_eq!(network.sovereign_balance(DEX_PARA_ID), 600);
+}
+
+#[test]
+fn simulated_withdrawal_is_acknowledged_by_destination() {
+    let mut network = new_network();
+
+    send_asset_to_dex(&mut network, ALICE, 500);
+    network.execute_with(DEX_PARA_ID, || {
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+        assert!(DexXCMP::pending_transfers(0).is_some());
+    });
+
+    network.process_messages();
+
+    network.execute_with(PARA_ID, || {
+        let asset_id =
+            DexXCMP::asset_id_by_para_asset_id(ParaId::from(DEX_PARA_ID), Some(PARA_ASSET_ID));
+        assert_eq!(DexPallet::asset_balances(BOB, asset_id), 200);
+    });
+    network.execute_with(DEX_PARA_ID, || {
+        assert!(DexXCMP::pending_transfers(0).is_none());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 300);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 300);
+    });
+}
+
+#[test]
+fn simulated_transfer_is_swapped_on_arrival() {
+    let mut network = new_network();
+
+    send_asset_to_dex(&mut network, ALICE, 100_000);
+    network.execute_with(DEX_PARA_ID, || {
+        assert_ok!(DexPallet::initialize_exchange(
+            Origin::signed(ALICE),
+            Asset::MainNetworkCurrency,
+            100_000,
+            Asset::ParachainAsset(1),
+            100_000
+        ));
+    });
+
+    network.send_xcmp_message(
+        PARA_ID,
+        DEX_PARA_ID,
+        VersionedXCMPMessage::V1(XCMPMessage::TransferAndSwap(
+            BOB,
+            1000,
+            Some(PARA_ASSET_ID),
+            None,
+            900,
+        )),
+    );
+    network.process_messages();
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert!(Balances::free_balance(BOB) >= INITIAL_BALANCE + 900);
+    });
+}
+
+#[test]
+fn simulated_rejected_withdrawal_is_refunded() {
+    let mut network = new_network();
+
+    send_asset_to_dex(&mut network, ALICE, 500);
+    // Destination can not allocate an id for the withdrawn asset and rejects the transfer
+    network.execute_with(PARA_ID, || NextAssetId::<Test>::put(AssetId::max_value()));
+    network.execute_with(DEX_PARA_ID, || {
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+    });
+
+    network.process_messages();
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert!(DexXCMP::pending_transfers(0).is_none());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 500);
+    });
+}
+
+#[test]
+fn simulated_undelivered_transfer_is_refunded_after_timeout() {
+    let mut network = new_network();
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert_ok!(DexXCMP::set_xcmp_version(
+            Origin::root(),
+            UNREACHABLE_PARA_ID,
+            XCMPVersion::V1
+        ));
+        assert_ok!(DexXCMP::transfer_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            UNREACHABLE_PARA_ID,
+            BOB,
+            1000
+        ));
+    });
+
+    network.process_messages();
+    assert_eq!(network.dropped_messages().len(), 1);
+
+    network.run_to_block(DEX_PARA_ID, TransferTimeout::get());
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert!(DexXCMP::in_flight_transfers(&ALICE).is_empty());
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE);
+        assert_eq!(
+            DexXCMP::main_currency_in_transit(ParaId::from(UNREACHABLE_PARA_ID)),
+            0
+        );
+    });
+}
+
+#[test]
+fn simulated_late_transfer_is_not_deposited_after_refund() {
+    let mut network = new_network();
+
+    send_asset_to_dex(&mut network, ALICE, 500);
+    network.execute_with(DEX_PARA_ID, || {
+        assert_ok!(DexXCMP::transfer_asset_balance_to_parachain_chain(
+            Origin::signed(ALICE),
+            PARA_ID,
+            BOB,
+            Some(PARA_ASSET_ID),
+            200
+        ));
+    });
+
+    // Transfer is refunded on timeout, before it is delivered
+    network.run_to_block(DEX_PARA_ID, TransferTimeout::get());
+    network.execute_with(PARA_ID, || {
+        Timestamp::set_timestamp(TransferDeliveryPeriod::get() + 1)
+    });
+    network.process_messages();
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert!(DexXCMP::in_flight_transfers(&ALICE).is_empty());
+        assert_eq!(DexPallet::asset_balances(ALICE, 1), 500);
+        assert_eq!(DexXCMP::backed_amount(PARA_ID, Some(PARA_ASSET_ID)), 500);
+    });
+    // Destination rejected the late transfer
+    network.execute_with(PARA_ID, || {
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+    });
+}
+
+#[test]
+fn simulated_parked_deposit_is_refunded_to_origin() {
+    let mut network = new_network();
+
+    send_asset_to_dex(&mut network, ALICE, Balance::max_value() - 10);
+    // Backed amount would overflow, so deposit is parked
+    send_asset_to_dex(&mut network, BOB, 100);
+
+    network.execute_with(DEX_PARA_ID, || {
+        assert_eq!(DexPallet::asset_balances(BOB, 1), 0);
+        assert_ok!(DexXCMP::refund_failed_message(Origin::signed(BOB), 0));
+    });
+
+    network.process_messages();
+
+    network.execute_with(PARA_ID, || {
+        let asset_id =
+            DexXCMP::asset_id_by_para_asset_id(ParaId::from(DEX_PARA_ID), Some(PARA_ASSET_ID));
+        assert_eq!(DexPallet::asset_balances(BOB, asset_id), 100);
+    });
+}
@@ -0,0 +1,96 @@
+use super::*;
+use sp_std::convert::{TryFrom, TryInto};
+
+/// Single step of the XCM-style location path.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub enum Junction {
+    /// Parachain with the given id.
+    Parachain(u32),
+    /// Pallet with the given index within the runtime.
+    PalletInstance(u8),
+    /// Numeric asset identifier within the chain or pallet.
+    GeneralIndex(u128),
+    /// Opaque asset identifier within the chain or pallet.
+    GeneralKey(Vec<u8>),
+}
+
+/// XCM-style location, relative to our chain.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct MultiLocation {
+    /// Number of steps up towards the relay chain.
+    pub parents: u8,
+    /// Path down from there.
+    pub interior: Vec<Junction>,
+}
+
+/// XCM-style asset, identified by its concrete location.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct MultiAsset<XBalance> {
+    pub id: MultiLocation,
+    pub amount: XBalance,
+}
+
+impl MultiLocation {
+    /// Location of the relay chain currency, which is the main currency of the parachains.
+    pub fn relay_currency() -> Self {
+        Self {
+            parents: 1,
+            interior: sp_std::vec![],
+        }
+    }
+
+    /// Location of the asset within the chain, it is relative to.
+    /// Returns None, if asset id can not be represented as a general index.
+    pub fn local_asset<XAssetId: TryInto<u128>>(asset_id: XAssetId) -> Option<Self> {
+        Some(Self {
+            parents: 0,
+            interior: sp_std::vec![Junction::GeneralIndex(asset_id.try_into().ok()?)],
+        })
+    }
+
+    /// Canonical location of the sibling parachain asset (None for parachain main currency).
+    /// Returns None, if asset id can not be represented as a general index.
+    pub fn sibling_asset<XAssetId: TryInto<u128>>(
+        para_id: ParaId,
+        para_asset_id: Option<XAssetId>,
+    ) -> Option<Self> {
+        let interior = match para_asset_id {
+            Some(para_asset_id) => sp_std::vec![
+                Junction::Parachain(para_id.into()),
+                Junction::GeneralIndex(para_asset_id.try_into().ok()?),
+            ],
+            None => sp_std::vec![Junction::Parachain(para_id.into())],
+        };
+        Some(Self {
+            parents: 1,
+            interior,
+        })
+    }
+
+    /// Sibling parachain and its asset id, identified by the canonical location.
+    /// Returns None for any other location.
+    pub fn as_sibling_asset<XAssetId: TryFrom<u128>>(&self) -> Option<(ParaId, Option<XAssetId>)> {
+        match (self.parents, self.interior.as_slice()) {
+            (1, [Junction::Parachain(para_id)]) => Some(((*para_id).into(), None)),
+            (1, [Junction::Parachain(para_id), Junction::GeneralIndex(index)]) => {
+                Some(((*para_id).into(), Some(XAssetId::try_from(*index).ok()?)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The same location, relative to the given sibling parachain instead of our chain.
+    /// Returns None for locations within our chain, as they are not addressable without our own parachain id.
+    pub fn reanchored_to_sibling(&self, para_id: ParaId) -> Option<Self> {
+        match (self.parents, self.interior.split_first()) {
+            (0, _) => None,
+            (1, Some((Junction::Parachain(id), interior))) if ParaId::from(*id) == para_id => {
+                Some(Self {
+                    parents: 0,
+                    interior: interior.to_vec(),
+                })
+            }
+            _ => Some(self.clone()),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Drives random sequences of exchange operations against the mock runtime.
+//
+// Failing dispatchables are expected, but none of them may panic, wrap around silently
+// (fuzz builds have overflow checks enabled) or break conservation of the assets.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use pallet_subdex::{mock::*, Asset};
+
+const MAIN: Asset<AssetId> = Asset::MainNetworkCurrency;
+const FIRST_ASSET: Asset<AssetId> = Asset::ParachainAsset(FIRST_ASSET_ID);
+const SECOND_ASSET: Asset<AssetId> = Asset::ParachainAsset(SECOND_ASSET_ID);
+
+// Exchanges, operations are performed against
+const EXCHANGES: [(Asset<AssetId>, Asset<AssetId>); 2] =
+    [(MAIN, FIRST_ASSET), (FIRST_ASSET, SECOND_ASSET)];
+
+const ACCOUNTS: [AccountId; 3] = [ALICE, BOB, TREASURY];
+
+#[derive(Arbitrary, Debug)]
+enum Operation {
+    Initialize {
+        by_alice: bool,
+        exchange: bool,
+        first_asset_amount: u128,
+        second_asset_amount: u128,
+    },
+    Swap {
+        by_alice: bool,
+        exchange: bool,
+        reversed: bool,
+        amount: u128,
+        min_amount: u128,
+    },
+    Invest {
+        by_alice: bool,
+        exchange: bool,
+        shares: u128,
+    },
+    Divest {
+        by_alice: bool,
+        exchange: bool,
+        shares: u128,
+        min_first_asset_amount: u128,
+        min_second_asset_amount: u128,
+    },
+    AdvanceTime(u32),
+}
+
+fn account(by_alice: bool) -> AccountId {
+    if by_alice {
+        ALICE
+    } else {
+        BOB
+    }
+}
+
+fn exchange_assets(exchange: bool) -> (Asset<AssetId>, Asset<AssetId>) {
+    EXCHANGES[exchange as usize]
+}
+
+fn balance_of(who: AccountId, asset: Asset<AssetId>) -> Balance {
+    match asset {
+        Asset::MainNetworkCurrency => Balances::free_balance(who),
+        Asset::ParachainAsset(asset_id) => DexPallet::asset_balances(who, asset_id),
+    }
+}
+
+// Account balances and pools, holding the given asset, have to add up to its initial supply
+fn check_conservation(asset: Asset<AssetId>) {
+    let held = ACCOUNTS.iter().fold(0 as Balance, |total, who| {
+        total
+            .checked_add(balance_of(*who, asset))
+            .expect("Account balances overflow")
+    });
+
+    let pooled = EXCHANGES
+        .iter()
+        .fold(0 as Balance, |total, (first_asset, second_asset)| {
+            let exchange = DexPallet::exchanges(*first_asset, *second_asset);
+            let pool = if *first_asset == asset {
+                exchange.first_asset_pool()
+            } else if *second_asset == asset {
+                exchange.second_asset_pool()
+            } else {
+                0
+            };
+            total.checked_add(pool).expect("Pools overflow")
+        });
+
+    assert_eq!(
+        held.checked_add(pooled),
+        Some(2 * INITIAL_BALANCE),
+        "{:?} is not conserved",
+        asset
+    );
+}
+
+fn check_exchanges() {
+    for (first_asset, second_asset) in EXCHANGES.iter() {
+        let exchange = DexPallet::exchanges(*first_asset, *second_asset);
+
+        assert_eq!(
+            exchange.total_shares,
+            exchange.shares_of(&ALICE) + exchange.shares_of(&BOB)
+        );
+        // Pools are only empty, when there are no shares
+        assert_eq!(
+            exchange.total_shares == 0,
+            exchange.invariant().unwrap().is_zero()
+        );
+    }
+}
+
+fuzz_target!(|operations: Vec<Operation>| {
+    new_test_ext().execute_with(|| {
+        for operation in operations {
+            match operation {
+                Operation::Initialize {
+                    by_alice,
+                    exchange,
+                    first_asset_amount,
+                    second_asset_amount,
+                } => {
+                    let (first_asset, second_asset) = exchange_assets(exchange);
+                    let _ = DexPallet::initialize_exchange(
+                        Origin::signed(account(by_alice)),
+                        first_asset,
+                        first_asset_amount,
+                        second_asset,
+                        second_asset_amount,
+                    );
+                }
+                Operation::Swap {
+                    by_alice,
+                    exchange,
+                    reversed,
+                    amount,
+                    min_amount,
+                } => {
+                    let (first_asset, second_asset) = exchange_assets(exchange);
+                    let invariant = DexPallet::exchanges(first_asset, second_asset)
+                        .invariant()
+                        .unwrap();
+
+                    let (asset_in, asset_out) = if reversed {
+                        (second_asset, first_asset)
+                    } else {
+                        (first_asset, second_asset)
+                    };
+                    let who = account(by_alice);
+                    let _ = DexPallet::swap_to_exact(
+                        Origin::signed(who),
+                        asset_in,
+                        amount,
+                        asset_out,
+                        min_amount,
+                        who,
+                    );
+
+                    assert!(
+                        DexPallet::exchanges(first_asset, second_asset)
+                            .invariant()
+                            .unwrap()
+                            >= invariant
+                    );
+                }
+                Operation::Invest {
+                    by_alice,
+                    exchange,
+                    shares,
+                } => {
+                    let (first_asset, second_asset) = exchange_assets(exchange);
+                    let _ = DexPallet::invest_liquidity(
+                        Origin::signed(account(by_alice)),
+                        first_asset,
+                        second_asset,
+                        shares,
+                    );
+                }
+                Operation::Divest {
+                    by_alice,
+                    exchange,
+                    shares,
+                    min_first_asset_amount,
+                    min_second_asset_amount,
+                } => {
+                    let (first_asset, second_asset) = exchange_assets(exchange);
+                    let _ = DexPallet::divest_liquidity(
+                        Origin::signed(account(by_alice)),
+                        first_asset,
+                        second_asset,
+                        shares,
+                        min_first_asset_amount,
+                        min_second_asset_amount,
+                    );
+                }
+                Operation::AdvanceTime(period) => {
+                    Timestamp::set_timestamp(Timestamp::get() + u64::from(period));
+                }
+            }
+
+            check_conservation(MAIN);
+            check_conservation(FIRST_ASSET);
+            check_conservation(SECOND_ASSET);
+            check_exchanges();
+        }
+    });
+});
@@ -0,0 +1,15 @@
+//! Runtime API definition for the subdex pallet.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+
+sp_api::decl_runtime_apis! {
+    pub trait DexApi<AssetId, Balance> where
+        AssetId: Codec,
+        Balance: Codec,
+    {
+        /// Amount of `asset_out`, received for `asset_in_amount` of `asset_in` from the pool of any kind.
+        /// Assets are parachain asset ids, None stands for the main network currency.
+        fn quote(asset_in: Option<AssetId>, asset_in_amount: Balance, asset_out: Option<AssetId>) -> Option<Balance>;
+    }
+}
@@ -0,0 +1,768 @@
+use super::*;
+use math::ONE;
+use sp_core::U256;
+
+/// Price of the tick is 1.0001 ^ tick (second asset amount per first asset)
+pub const MIN_TICK: i32 = -400_000;
+pub const MAX_TICK: i32 = 400_000;
+
+/// Upper bound of the distance between the usable ticks
+pub const MAX_TICK_SPACING: u32 = 10_000;
+
+// sqrt(1.0001), scaled by ONE
+const SQRT_TICK_BASE: U256 = U256([1_000_049_998_750_062_496, 0, 0, 0]);
+
+/// Square root of the tick price, scaled by ONE
+pub fn sqrt_price_at_tick<T: Trait>(tick: i32) -> Result<U256, Error<T>> {
+    ensure!(
+        tick >= MIN_TICK && tick <= MAX_TICK,
+        Error::<T>::InvalidTick
+    );
+
+    let sqrt_price =
+        math::pow_whole::<T>(SQRT_TICK_BASE, U256::from(i64::from(tick).abs() as u64))?;
+    if tick >= 0 {
+        Ok(sqrt_price)
+    } else {
+        Ok(ONE * ONE / sqrt_price)
+    }
+}
+
+/// The greatest tick, which price does not exceed the given one
+pub fn tick_at_sqrt_price<T: Trait>(sqrt_price: U256) -> Result<i32, Error<T>> {
+    ensure!(
+        sqrt_price >= sqrt_price_at_tick::<T>(MIN_TICK)?,
+        Error::<T>::InvalidTick
+    );
+
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick::<T>(middle)? <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Ok(low)
+}
+
+// First asset amount of the liquidity between the sqrt prices: liquidity * (upper - lower) / (lower * upper)
+fn first_asset_delta<T: Trait>(
+    lower: U256,
+    upper: U256,
+    liquidity: U256,
+    round_up: bool,
+) -> Result<U256, Error<T>> {
+    let amount = math::mul_div_u256::<T>(liquidity, upper - lower, upper, round_up)?;
+    math::mul_div_u256::<T>(amount, ONE, lower, round_up)
+}
+
+// Second asset amount of the liquidity between the sqrt prices: liquidity * (upper - lower)
+fn second_asset_delta<T: Trait>(
+    lower: U256,
+    upper: U256,
+    liquidity: U256,
+    round_up: bool,
+) -> Result<U256, Error<T>> {
+    math::mul_div_u256::<T>(liquidity, upper - lower, ONE, round_up)
+}
+
+/// Liquidity, referencing the initialized tick
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Tick<Balance> {
+    // liquidity of all positions, bounded by the tick
+    pub liquidity_gross: Balance,
+    // liquidity of positions, starting at the tick
+    pub liquidity_lower: Balance,
+    // liquidity of positions, ending at the tick
+    pub liquidity_upper: Balance,
+    // fee growth on the other side of the tick, relative to the current tick
+    pub fee_growth_outside_first: U256,
+    pub fee_growth_outside_second: U256,
+}
+
+/// Liquidity, provided within the price range
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Position<AccountId, Balance> {
+    pub owner: AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: Balance,
+    // fee growth inside the range, as of the last position update
+    fee_growth_inside_first_last: U256,
+    fee_growth_inside_second_last: U256,
+    // uncollected fees
+    pub fees_first: Balance,
+    pub fees_second: Balance,
+}
+
+/// Structure, representing pool of the (ordered) assets pair with liquidity, concentrated in tick ranges.
+/// Within the range between two initialized ticks, the pool behaves as the constant product one.
+/// Fee growth per unit of liquidity is accumulated globally and outside every initialized tick,
+/// so that fees, earned inside the position range, are derived without iterating positions.
+/// Ticks and positions are stored separately, keyed by the assets pair.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct ConcentratedPool<T: Trait> {
+    pub tick_spacing: u32,
+    // square root of the current price, scaled by ONE
+    pub sqrt_price: U256,
+    // the greatest tick, which price does not exceed the current one
+    pub current_tick: i32,
+    // liquidity of the positions, which range contains the current price
+    pub liquidity: BalanceOf<T>,
+    // fees per unit of liquidity, scaled by ONE
+    pub fee_growth_global_first: U256,
+    pub fee_growth_global_second: U256,
+    // pools include uncollected fees
+    first_asset_pool: BalanceOf<T>,
+    second_asset_pool: BalanceOf<T>,
+    next_position_id: PositionId,
+}
+
+impl<T: Trait> ConcentratedPool<T> {
+    pub fn new(tick_spacing: u32, initial_tick: i32) -> Result<Self, Error<T>> {
+        ensure!(
+            tick_spacing > 0 && tick_spacing <= MAX_TICK_SPACING,
+            Error::<T>::InvalidTickSpacing
+        );
+
+        Ok(Self {
+            tick_spacing,
+            sqrt_price: sqrt_price_at_tick::<T>(initial_tick)?,
+            current_tick: initial_tick,
+            liquidity: BalanceOf::<T>::zero(),
+            fee_growth_global_first: U256::zero(),
+            fee_growth_global_second: U256::zero(),
+            first_asset_pool: BalanceOf::<T>::zero(),
+            second_asset_pool: BalanceOf::<T>::zero(),
+            next_position_id: 0,
+        })
+    }
+
+    pub fn first_asset_pool(&self) -> BalanceOf<T> {
+        self.first_asset_pool
+    }
+
+    pub fn second_asset_pool(&self) -> BalanceOf<T> {
+        self.second_asset_pool
+    }
+}
+
+/// Concentrated pool together with its ticks and positions, read from the storage on demand.
+/// Updates are kept in memory, until written by `commit`, so that the call can still fail after the pool is changed.
+pub struct ConcentratedPoolUpdate<T: Trait> {
+    first_asset: Asset<T::AssetId>,
+    second_asset: Asset<T::AssetId>,
+    pool: ConcentratedPool<T>,
+    // updated entries, removed ones are None
+    ticks: BTreeMap<i32, Option<Tick<BalanceOf<T>>>>,
+    tick_bitmaps: BTreeMap<i32, U256>,
+    positions: BTreeMap<PositionId, Option<Position<T::AccountId, BalanceOf<T>>>>,
+    // ticks (initialized or bitmap word boundaries), crossed by the swaps
+    ticks_crossed: u32,
+}
+
+impl<T: Trait> ConcentratedPoolUpdate<T> {
+    pub fn load(
+        first_asset: Asset<T::AssetId>,
+        second_asset: Asset<T::AssetId>,
+    ) -> Result<Self, Error<T>> {
+        let pool = <Module<T>>::concentrated_pools(first_asset, second_asset)
+            .ok_or(Error::<T>::ConcentratedPoolNotExists)?;
+        Ok(Self {
+            first_asset,
+            second_asset,
+            pool,
+            ticks: BTreeMap::new(),
+            tick_bitmaps: BTreeMap::new(),
+            positions: BTreeMap::new(),
+            ticks_crossed: 0,
+        })
+    }
+
+    /// Write the pool, updated ticks and positions to the storage
+    pub fn commit(self) {
+        let pair = (self.first_asset, self.second_asset);
+        for (tick, tick_info) in self.ticks {
+            match tick_info {
+                Some(tick_info) => <ConcentratedTicks<T>>::insert(pair, tick, tick_info),
+                None => <ConcentratedTicks<T>>::remove(pair, tick),
+            }
+        }
+        for (word, bitmap) in self.tick_bitmaps {
+            if bitmap.is_zero() {
+                <ConcentratedTickBitmaps<T>>::remove(pair, word);
+            } else {
+                <ConcentratedTickBitmaps<T>>::insert(pair, word, bitmap);
+            }
+        }
+        for (position_id, position) in self.positions {
+            match position {
+                Some(position) => <ConcentratedPositions<T>>::insert(pair, position_id, position),
+                None => <ConcentratedPositions<T>>::remove(pair, position_id),
+            }
+        }
+        <ConcentratedPools<T>>::insert(self.first_asset, self.second_asset, self.pool);
+    }
+
+    pub fn ticks_crossed(&self) -> u32 {
+        self.ticks_crossed
+    }
+
+    /// Provide `liquidity` within the range of the ticks, creating the new position.
+    /// Returns the position id and the first and second asset amounts, owed to the pool.
+    pub fn mint(
+        &mut self,
+        owner: T::AccountId,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity: BalanceOf<T>,
+    ) -> Result<(PositionId, BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        ensure!(liquidity > BalanceOf::<T>::zero(), Error::<T>::LowLiquidity);
+        self.ensure_valid_range(lower_tick, upper_tick)?;
+
+        let position_id = self.pool.next_position_id;
+        self.pool.next_position_id = position_id
+            .checked_add(1)
+            .ok_or(Error::<T>::OverflowOccured)?;
+
+        self.update_tick(lower_tick, liquidity, false, true)?;
+        self.update_tick(upper_tick, liquidity, true, true)?;
+        let (fee_growth_inside_first, fee_growth_inside_second) =
+            self.fee_growth_inside(lower_tick, upper_tick);
+        self.positions.insert(
+            position_id,
+            Some(Position {
+                owner,
+                lower_tick,
+                upper_tick,
+                liquidity,
+                fee_growth_inside_first_last: fee_growth_inside_first,
+                fee_growth_inside_second_last: fee_growth_inside_second,
+                fees_first: BalanceOf::<T>::zero(),
+                fees_second: BalanceOf::<T>::zero(),
+            }),
+        );
+
+        if self.in_range(lower_tick, upper_tick) {
+            self.pool.liquidity = Self::add(self.pool.liquidity, liquidity)?;
+        }
+
+        // Rounded up in favor of the pool
+        let (first_asset_amount, second_asset_amount) =
+            self.amounts(lower_tick, upper_tick, liquidity, true)?;
+        self.pool.first_asset_pool = Self::add(self.pool.first_asset_pool, first_asset_amount)?;
+        self.pool.second_asset_pool = Self::add(self.pool.second_asset_pool, second_asset_amount)?;
+
+        Ok((position_id, first_asset_amount, second_asset_amount))
+    }
+
+    /// Withdraw `liquidity` of the position. Earned fees stay in the position, until collected.
+    /// Returns the first and second asset amounts, owed to the owner.
+    pub fn burn(
+        &mut self,
+        owner: &T::AccountId,
+        position_id: PositionId,
+        liquidity: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let mut position = self.ensure_position_owner(owner, position_id)?;
+        let (lower_tick, upper_tick) = (position.lower_tick, position.upper_tick);
+        ensure!(liquidity > BalanceOf::<T>::zero(), Error::<T>::LowLiquidity);
+        ensure!(
+            liquidity <= position.liquidity,
+            Error::<T>::InsufficientPositionLiquidity
+        );
+
+        self.update_position_fees(&mut position)?;
+        self.update_tick(lower_tick, liquidity, false, false)?;
+        self.update_tick(upper_tick, liquidity, true, false)?;
+        position.liquidity -= liquidity;
+        self.positions.insert(position_id, Some(position));
+
+        if self.in_range(lower_tick, upper_tick) {
+            self.pool.liquidity = Self::sub(self.pool.liquidity, liquidity)?;
+        }
+
+        // Rounded down in favor of the pool
+        let (first_asset_amount, second_asset_amount) =
+            self.amounts(lower_tick, upper_tick, liquidity, false)?;
+        self.pool.first_asset_pool = Self::sub(self.pool.first_asset_pool, first_asset_amount)?;
+        self.pool.second_asset_pool = Self::sub(self.pool.second_asset_pool, second_asset_amount)?;
+
+        Ok((first_asset_amount, second_asset_amount))
+    }
+
+    /// Withdraw fees, earned by the position. Position without liquidity is removed.
+    /// Returns the first and second asset fees.
+    pub fn collect_fees(
+        &mut self,
+        owner: &T::AccountId,
+        position_id: PositionId,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let mut position = self.ensure_position_owner(owner, position_id)?;
+        self.update_position_fees(&mut position)?;
+
+        let fees = (position.fees_first, position.fees_second);
+        position.fees_first = BalanceOf::<T>::zero();
+        position.fees_second = BalanceOf::<T>::zero();
+        if position.liquidity == BalanceOf::<T>::zero() {
+            self.positions.insert(position_id, None);
+        } else {
+            self.positions.insert(position_id, Some(position));
+        }
+
+        self.pool.first_asset_pool = Self::sub(self.pool.first_asset_pool, fees.0)?;
+        self.pool.second_asset_pool = Self::sub(self.pool.second_asset_pool, fees.1)?;
+        Ok(fees)
+    }
+
+    /// Swap `amount_in` of the first (or second) asset, crossing at most `T::MaxTicksCrossed` ticks on the way.
+    /// Returns the amount out and the treasury fee data.
+    pub fn swap(
+        &mut self,
+        first_to_second: bool,
+        amount_in: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        ensure!(
+            amount_in > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(amount_in)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+        let net_amount_in = math::to_u256::<T>(Self::sub(amount_in, exchange_fee + treasury_fee)?)?;
+        let exchange_fee = math::to_u256::<T>(exchange_fee)?;
+
+        let mut remaining = net_amount_in;
+        let mut remaining_fee = exchange_fee;
+        let mut amount_out = U256::zero();
+        while !remaining.is_zero() {
+            // There is no liquidity beyond the price range
+            ensure!(
+                if first_to_second {
+                    self.pool.current_tick >= MIN_TICK
+                } else {
+                    self.pool.current_tick < MAX_TICK
+                },
+                Error::<T>::InsufficientPool
+            );
+            ensure!(
+                self.ticks_crossed < T::MaxTicksCrossed::get(),
+                Error::<T>::TooManyTicksCrossed
+            );
+            self.ticks_crossed += 1;
+
+            // Liquidity only changes at the initialized ticks, the search is bounded by the bitmap word
+            let (next_tick, initialized) = self.next_tick(first_to_second);
+
+            let liquidity = math::to_u256::<T>(self.pool.liquidity)?;
+            let (step_in, step_out, sqrt_price, reached) = Self::compute_step(
+                first_to_second,
+                self.pool.sqrt_price,
+                sqrt_price_at_tick::<T>(next_tick)?,
+                liquidity,
+                remaining,
+            )?;
+            remaining -= step_in;
+            amount_out += step_out;
+
+            // Fee is shared by the liquidity, the amount is swapped against
+            if !liquidity.is_zero() {
+                let step_fee = if remaining.is_zero() {
+                    remaining_fee
+                } else {
+                    math::mul_div_u256::<T>(exchange_fee, step_in, net_amount_in, false)?
+                };
+                remaining_fee -= step_fee;
+
+                let fee_growth = math::mul_div_u256::<T>(step_fee, ONE, liquidity, false)?;
+                if first_to_second {
+                    self.pool.fee_growth_global_first = self
+                        .pool
+                        .fee_growth_global_first
+                        .overflowing_add(fee_growth)
+                        .0;
+                } else {
+                    self.pool.fee_growth_global_second = self
+                        .pool
+                        .fee_growth_global_second
+                        .overflowing_add(fee_growth)
+                        .0;
+                }
+            }
+
+            self.pool.sqrt_price = sqrt_price;
+            if reached && initialized {
+                self.cross_tick(next_tick, !first_to_second)?;
+            } else if reached {
+                self.pool.current_tick = if first_to_second {
+                    next_tick - 1
+                } else {
+                    next_tick
+                };
+            } else {
+                self.pool.current_tick = tick_at_sqrt_price::<T>(sqrt_price)?;
+            }
+        }
+
+        let amount_out = math::from_u256::<T>(amount_out)?;
+        let amount_in_pool = Self::sub(amount_in, treasury_fee)?;
+        if first_to_second {
+            self.pool.first_asset_pool = Self::add(self.pool.first_asset_pool, amount_in_pool)?;
+            self.pool.second_asset_pool = self
+                .pool
+                .second_asset_pool
+                .checked_sub(&amount_out)
+                .ok_or(Error::<T>::InsufficientPool)?;
+        } else {
+            self.pool.second_asset_pool = Self::add(self.pool.second_asset_pool, amount_in_pool)?;
+            self.pool.first_asset_pool = self
+                .pool
+                .first_asset_pool
+                .checked_sub(&amount_out)
+                .ok_or(Error::<T>::InsufficientPool)?;
+        }
+        Ok((amount_out, treasury_fee_data))
+    }
+
+    // Swap within the range of the constant liquidity towards the target price.
+    // Returns amounts in and out, the new price and whether the target is reached (so the tick is crossed).
+    fn compute_step(
+        first_to_second: bool,
+        sqrt_price: U256,
+        target_sqrt_price: U256,
+        liquidity: U256,
+        remaining: U256,
+    ) -> Result<(U256, U256, U256, bool), Error<T>> {
+        if liquidity.is_zero() {
+            return Ok((U256::zero(), U256::zero(), target_sqrt_price, true));
+        }
+
+        if first_to_second {
+            let needed = first_asset_delta::<T>(target_sqrt_price, sqrt_price, liquidity, true)?;
+            if remaining >= needed {
+                let amount_out =
+                    second_asset_delta::<T>(target_sqrt_price, sqrt_price, liquidity, false)?;
+                return Ok((needed, amount_out, target_sqrt_price, true));
+            }
+
+            // new_sqrt_price = liquidity / (liquidity / sqrt_price + remaining), rounded up
+            let scaled_liquidity = liquidity
+                .checked_mul(ONE)
+                .ok_or(Error::<T>::OverflowOccured)?;
+            let denominator = (scaled_liquidity / sqrt_price)
+                .checked_add(remaining)
+                .ok_or(Error::<T>::OverflowOccured)?;
+            let new_sqrt_price =
+                math::mul_div_u256::<T>(scaled_liquidity, U256::one(), denominator, true)?
+                    .min(sqrt_price)
+                    .max(target_sqrt_price);
+            let amount_out = second_asset_delta::<T>(new_sqrt_price, sqrt_price, liquidity, false)?;
+            Ok((
+                remaining,
+                amount_out,
+                new_sqrt_price,
+                new_sqrt_price == target_sqrt_price,
+            ))
+        } else {
+            let needed = second_asset_delta::<T>(sqrt_price, target_sqrt_price, liquidity, true)?;
+            if remaining >= needed {
+                let amount_out =
+                    first_asset_delta::<T>(sqrt_price, target_sqrt_price, liquidity, false)?;
+                return Ok((needed, amount_out, target_sqrt_price, true));
+            }
+
+            // new_sqrt_price = sqrt_price + remaining / liquidity, rounded down
+            let new_sqrt_price = (sqrt_price
+                + math::mul_div_u256::<T>(remaining, ONE, liquidity, false)?)
+            .min(target_sqrt_price);
+            let amount_out = first_asset_delta::<T>(sqrt_price, new_sqrt_price, liquidity, false)?;
+            Ok((
+                remaining,
+                amount_out,
+                new_sqrt_price,
+                new_sqrt_price == target_sqrt_price,
+            ))
+        }
+    }
+
+    fn cross_tick(&mut self, tick: i32, upwards: bool) -> Result<(), Error<T>> {
+        let mut tick_info = self.tick(tick).ok_or(Error::<T>::InvalidTick)?;
+        tick_info.fee_growth_outside_first = self
+            .pool
+            .fee_growth_global_first
+            .overflowing_sub(tick_info.fee_growth_outside_first)
+            .0;
+        tick_info.fee_growth_outside_second = self
+            .pool
+            .fee_growth_global_second
+            .overflowing_sub(tick_info.fee_growth_outside_second)
+            .0;
+
+        // Positions, starting at the tick, become active upwards, ending at the tick - downwards
+        let (entering, leaving) = if upwards {
+            (tick_info.liquidity_lower, tick_info.liquidity_upper)
+        } else {
+            (tick_info.liquidity_upper, tick_info.liquidity_lower)
+        };
+        self.pool.liquidity = Self::sub(Self::add(self.pool.liquidity, entering)?, leaving)?;
+        self.pool.current_tick = if upwards { tick } else { tick - 1 };
+        self.ticks.insert(tick, Some(tick_info));
+        Ok(())
+    }
+
+    fn update_tick(
+        &mut self,
+        tick: i32,
+        liquidity: BalanceOf<T>,
+        upper: bool,
+        add: bool,
+    ) -> Result<(), Error<T>> {
+        let mut tick_info = match self.tick(tick) {
+            Some(tick_info) => tick_info,
+            // By convention, all fees so far were earned below the tick, when it is initialized below the price
+            None if tick <= self.pool.current_tick => Tick {
+                fee_growth_outside_first: self.pool.fee_growth_global_first,
+                fee_growth_outside_second: self.pool.fee_growth_global_second,
+                ..Default::default()
+            },
+            None => Tick::default(),
+        };
+        let was_initialized = tick_info.liquidity_gross != BalanceOf::<T>::zero();
+
+        let update = |value: BalanceOf<T>| {
+            if add {
+                Self::add(value, liquidity)
+            } else {
+                Self::sub(value, liquidity)
+            }
+        };
+        tick_info.liquidity_gross = update(tick_info.liquidity_gross)?;
+        if upper {
+            tick_info.liquidity_upper = update(tick_info.liquidity_upper)?;
+        } else {
+            tick_info.liquidity_lower = update(tick_info.liquidity_lower)?;
+        }
+
+        let initialized = tick_info.liquidity_gross != BalanceOf::<T>::zero();
+        if initialized != was_initialized {
+            self.flip_tick(tick);
+        }
+        self.ticks
+            .insert(tick, if initialized { Some(tick_info) } else { None });
+        Ok(())
+    }
+
+    // The next tick in the swap direction, which is either initialized or the bound of the bitmap word,
+    // together with whether it is initialized
+    fn next_tick(&self, first_to_second: bool) -> (i32, bool) {
+        let tick_spacing = self.pool.tick_spacing as i32;
+        // rounded towards negative infinity
+        let compressed = self.pool.current_tick.div_euclid(tick_spacing);
+
+        let (next_compressed, initialized) = if first_to_second {
+            // the current tick and below
+            let (word, bit) = Self::bitmap_position(compressed);
+            let mask = (U256::one() << bit) - U256::one() + (U256::one() << bit);
+            let masked = self.tick_bitmap(word) & mask;
+            if masked.is_zero() {
+                (compressed - bit as i32, false)
+            } else {
+                let most_significant_bit = masked.bits() as i32 - 1;
+                (compressed - (bit as i32 - most_significant_bit), true)
+            }
+        } else {
+            // above the current tick
+            let (word, bit) = Self::bitmap_position(compressed + 1);
+            let mask = !((U256::one() << bit) - U256::one());
+            let masked = self.tick_bitmap(word) & mask;
+            if masked.is_zero() {
+                (compressed + 1 + (255 - bit as i32), false)
+            } else {
+                let least_significant_bit = masked.trailing_zeros() as i32;
+                (compressed + 1 + (least_significant_bit - bit as i32), true)
+            }
+        };
+        (
+            (next_compressed * tick_spacing).max(MIN_TICK).min(MAX_TICK),
+            initialized,
+        )
+    }
+
+    fn flip_tick(&mut self, tick: i32) {
+        let (word, bit) = Self::bitmap_position(tick / self.pool.tick_spacing as i32);
+        let bitmap = self.tick_bitmap(word) ^ (U256::one() << bit);
+        self.tick_bitmaps.insert(word, bitmap);
+    }
+
+    // Word and bit of the tick, divided by the tick spacing, in the bitmap of the initialized ticks
+    fn bitmap_position(compressed: i32) -> (i32, usize) {
+        (compressed >> 8, (compressed & 0xff) as usize)
+    }
+
+    fn tick(&self, tick: i32) -> Option<Tick<BalanceOf<T>>> {
+        match self.ticks.get(&tick) {
+            Some(tick_info) => tick_info.clone(),
+            None => <ConcentratedTicks<T>>::get((self.first_asset, self.second_asset), tick),
+        }
+    }
+
+    fn tick_bitmap(&self, word: i32) -> U256 {
+        match self.tick_bitmaps.get(&word) {
+            Some(bitmap) => *bitmap,
+            None => <ConcentratedTickBitmaps<T>>::get((self.first_asset, self.second_asset), word),
+        }
+    }
+
+    fn position(&self, position_id: PositionId) -> Option<Position<T::AccountId, BalanceOf<T>>> {
+        match self.positions.get(&position_id) {
+            Some(position) => position.clone(),
+            None => {
+                <ConcentratedPositions<T>>::get((self.first_asset, self.second_asset), position_id)
+            }
+        }
+    }
+
+    // Fee growth per unit of liquidity inside the range, modulo 2^256
+    fn fee_growth_inside(&self, lower_tick: i32, upper_tick: i32) -> (U256, U256) {
+        let outside = |tick: i32| {
+            self.tick(tick)
+                .map(|tick_info| {
+                    (
+                        tick_info.fee_growth_outside_first,
+                        tick_info.fee_growth_outside_second,
+                    )
+                })
+                .unwrap_or_default()
+        };
+        let global = (
+            self.pool.fee_growth_global_first,
+            self.pool.fee_growth_global_second,
+        );
+        let sub = |a: (U256, U256), b: (U256, U256)| {
+            (a.0.overflowing_sub(b.0).0, a.1.overflowing_sub(b.1).0)
+        };
+
+        let below = if self.pool.current_tick >= lower_tick {
+            outside(lower_tick)
+        } else {
+            sub(global, outside(lower_tick))
+        };
+        let above = if self.pool.current_tick < upper_tick {
+            outside(upper_tick)
+        } else {
+            sub(global, outside(upper_tick))
+        };
+        sub(sub(global, below), above)
+    }
+
+    fn update_position_fees(
+        &self,
+        position: &mut Position<T::AccountId, BalanceOf<T>>,
+    ) -> Result<(), Error<T>> {
+        let (fee_growth_inside_first, fee_growth_inside_second) =
+            self.fee_growth_inside(position.lower_tick, position.upper_tick);
+        let liquidity = math::to_u256::<T>(position.liquidity)?;
+
+        let earned_first = math::from_u256::<T>(math::mul_div_u256::<T>(
+            liquidity,
+            fee_growth_inside_first
+                .overflowing_sub(position.fee_growth_inside_first_last)
+                .0,
+            ONE,
+            false,
+        )?)?;
+        let earned_second = math::from_u256::<T>(math::mul_div_u256::<T>(
+            liquidity,
+            fee_growth_inside_second
+                .overflowing_sub(position.fee_growth_inside_second_last)
+                .0,
+            ONE,
+            false,
+        )?)?;
+
+        position.fees_first = Self::add(position.fees_first, earned_first)?;
+        position.fees_second = Self::add(position.fees_second, earned_second)?;
+        position.fee_growth_inside_first_last = fee_growth_inside_first;
+        position.fee_growth_inside_second_last = fee_growth_inside_second;
+        Ok(())
+    }
+
+    // First and second asset amounts of the liquidity within the range at the current price
+    fn amounts(
+        &self,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity: BalanceOf<T>,
+        round_up: bool,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let lower = sqrt_price_at_tick::<T>(lower_tick)?;
+        let upper = sqrt_price_at_tick::<T>(upper_tick)?;
+        let liquidity = math::to_u256::<T>(liquidity)?;
+
+        let (first_asset_amount, second_asset_amount) = if self.pool.current_tick < lower_tick {
+            (
+                first_asset_delta::<T>(lower, upper, liquidity, round_up)?,
+                U256::zero(),
+            )
+        } else if self.pool.current_tick < upper_tick {
+            (
+                first_asset_delta::<T>(self.pool.sqrt_price, upper, liquidity, round_up)?,
+                second_asset_delta::<T>(lower, self.pool.sqrt_price, liquidity, round_up)?,
+            )
+        } else {
+            (
+                U256::zero(),
+                second_asset_delta::<T>(lower, upper, liquidity, round_up)?,
+            )
+        };
+        Ok((
+            math::from_u256::<T>(first_asset_amount)?,
+            math::from_u256::<T>(second_asset_amount)?,
+        ))
+    }
+
+    fn in_range(&self, lower_tick: i32, upper_tick: i32) -> bool {
+        self.pool.current_tick >= lower_tick && self.pool.current_tick < upper_tick
+    }
+
+    fn ensure_valid_range(&self, lower_tick: i32, upper_tick: i32) -> Result<(), Error<T>> {
+        ensure!(lower_tick < upper_tick, Error::<T>::InvalidTickRange);
+        ensure!(
+            lower_tick >= MIN_TICK && upper_tick <= MAX_TICK,
+            Error::<T>::InvalidTick
+        );
+        let tick_spacing = self.pool.tick_spacing as i32;
+        ensure!(
+            lower_tick % tick_spacing == 0 && upper_tick % tick_spacing == 0,
+            Error::<T>::InvalidTick
+        );
+        Ok(())
+    }
+
+    fn ensure_position_owner(
+        &self,
+        owner: &T::AccountId,
+        position_id: PositionId,
+    ) -> Result<Position<T::AccountId, BalanceOf<T>>, Error<T>> {
+        let position = self
+            .position(position_id)
+            .ok_or(Error::<T>::PositionNotExists)?;
+        ensure!(position.owner == *owner, Error::<T>::NotPositionOwner);
+        Ok(position)
+    }
+
+    fn add(a: BalanceOf<T>, b: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
+        a.checked_add(&b).ok_or(Error::<T>::OverflowOccured)
+    }
+
+    fn sub(a: BalanceOf<T>, b: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
+        a.checked_sub(&b).ok_or(Error::<T>::UnderflowOccured)
+    }
+}
@@ -0,0 +1,100 @@
+use super::*;
+
+/// Order to swap escrowed `asset_in_amount` of `asset_in` for at least `min_asset_out_amount` of `asset_out`,
+/// once the time-weighted average price of `asset_in` in `asset_out` crosses the `trigger_price`.
+/// Average is taken over the window, started by the last observation, so a single trade can not trigger it.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct ConditionalOrder<T: Trait> {
+    pub owner: T::AccountId,
+    pub condition: TriggerCondition,
+    pub asset_in: Asset<T::AssetId>,
+    pub asset_out: Asset<T::AssetId>,
+    pub asset_in_amount: BalanceOf<T>,
+    // slippage bound of the triggered swap
+    pub min_asset_out_amount: BalanceOf<T>,
+    // asset_out_pool / asset_in_pool, scaled by PRICE_ONE, as accumulated by the exchange
+    pub trigger_price: BalanceOf<T>,
+    // cumulative price and timestamp at the start of the averaging window
+    pub price_cumulative: BalanceOf<T>,
+    pub observed_at: BalanceOf<T>,
+    // order can not be triggered after this block
+    pub expiry: T::BlockNumber,
+}
+
+impl<T: Trait> ConditionalOrder<T> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        owner: T::AccountId,
+        condition: TriggerCondition,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+        trigger_price: BalanceOf<T>,
+        expiry: T::BlockNumber,
+    ) -> Result<Self, Error<T>> {
+        ensure!(
+            asset_in_amount > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+        ensure!(
+            expiry > <system::Module<T>>::block_number(),
+            Error::<T>::ConditionalOrderExpired
+        );
+
+        Ok(Self {
+            owner,
+            condition,
+            asset_in,
+            asset_out,
+            asset_in_amount,
+            min_asset_out_amount,
+            trigger_price,
+            price_cumulative: BalanceOf::<T>::zero(),
+            observed_at: BalanceOf::<T>::zero(),
+            expiry,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        <system::Module<T>>::block_number() > self.expiry
+    }
+
+    /// Start the new averaging window
+    pub fn observe(&mut self, price_cumulative: BalanceOf<T>, timestamp: BalanceOf<T>) {
+        self.price_cumulative = price_cumulative;
+        self.observed_at = timestamp;
+    }
+
+    /// Time-weighted average price since the last observation.
+    /// Window should last for at least `twap_period`. Window, longer than `max_window`, is not averaged (None),
+    /// as the cumulative price could have wrapped around too many times within it.
+    pub fn average_price(
+        &self,
+        price_cumulative: BalanceOf<T>,
+        timestamp: BalanceOf<T>,
+        twap_period: BalanceOf<T>,
+        max_window: BalanceOf<T>,
+    ) -> Result<Option<BalanceOf<T>>, Error<T>> {
+        let time_elapsed = timestamp
+            .checked_sub(&self.observed_at)
+            .ok_or(Error::<T>::UnderflowOccured)?;
+        ensure!(
+            time_elapsed >= twap_period && time_elapsed > BalanceOf::<T>::zero(),
+            Error::<T>::TwapPeriodNotElapsed
+        );
+        if time_elapsed > max_window {
+            return Ok(None);
+        }
+
+        math::average_price::<T>(self.price_cumulative, price_cumulative, time_elapsed).map(Some)
+    }
+
+    pub fn is_triggered(&self, average_price: BalanceOf<T>) -> bool {
+        match self.condition {
+            TriggerCondition::StopLoss => average_price <= self.trigger_price,
+            TriggerCondition::TakeProfit => average_price >= self.trigger_price,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+
+/// Schedule of recurring swaps of `asset_in_amount` of `asset_in` for `asset_out`, executed every `interval` blocks.
+/// Amounts of all remaining executions are escrowed.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct DcaOrder<T: Trait> {
+    pub owner: T::AccountId,
+    pub asset_in: Asset<T::AssetId>,
+    pub asset_out: Asset<T::AssetId>,
+    // swapped per execution
+    pub asset_in_amount: BalanceOf<T>,
+    // slippage bound of every execution
+    pub min_asset_out_amount: BalanceOf<T>,
+    pub interval: T::BlockNumber,
+    pub remaining_executions: u32,
+    // main currency, returned with the last execution or on cancellation
+    pub deposit: BalanceOf<T>,
+}
+
+impl<T: Trait> DcaOrder<T> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        owner: T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+        interval: T::BlockNumber,
+        executions: u32,
+        deposit: BalanceOf<T>,
+    ) -> Result<Self, Error<T>> {
+        ensure!(
+            asset_in_amount > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+        ensure!(
+            interval > T::BlockNumber::zero() && executions > 0,
+            Error::<T>::InvalidDcaSchedule
+        );
+
+        Ok(Self {
+            owner,
+            asset_in,
+            asset_out,
+            asset_in_amount,
+            min_asset_out_amount,
+            interval,
+            remaining_executions: executions,
+            deposit,
+        })
+    }
+
+    /// Escrowed amount of `asset_in` for all remaining executions
+    pub fn escrowed_amount(&self) -> Result<BalanceOf<T>, Error<T>> {
+        self.asset_in_amount
+            .checked_mul(&BalanceOf::<T>::from(self.remaining_executions))
+            .ok_or(Error::<T>::OverflowOccured)
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use sp_core::U256;
 
 /// Structure, representing exchange pool
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
@@ -6,7 +7,6 @@ use super::*;
 pub struct Exchange<T: Trait> {
     first_asset_pool: BalanceOf<T>,
     second_asset_pool: BalanceOf<T>,
-    pub invariant: BalanceOf<T>,
     // total pool shares
     pub total_shares: BalanceOf<T>,
     // last timestamp, after pool update performed, needed for time_elapsed calculation
@@ -24,7 +24,6 @@ impl<T: Trait> Default for Exchange<T> {
         Self {
             first_asset_pool: BalanceOf::<T>::default(),
             second_asset_pool: BalanceOf::<T>::default(),
-            invariant: BalanceOf::<T>::default(),
             total_shares: BalanceOf::<T>::default(),
             last_timestamp: <pallet_timestamp::Module<T>>::get().into(),
             price1_cumulative_last: BalanceOf::<T>::default(),
@@ -34,6 +33,37 @@ impl<T: Trait> Default for Exchange<T> {
     }
 }
 
+/// Exchange layout of the storage version V1, with the stored invariant and integer cumulative prices
+#[derive(Decode)]
+pub struct ExchangeV1<T: Trait> {
+    first_asset_pool: BalanceOf<T>,
+    second_asset_pool: BalanceOf<T>,
+    _invariant: BalanceOf<T>,
+    total_shares: BalanceOf<T>,
+    last_timestamp: T::IMoment,
+    price1_cumulative_last: BalanceOf<T>,
+    price2_cumulative_last: BalanceOf<T>,
+    shares: BTreeMap<T::AccountId, BalanceOf<T>>,
+}
+
+impl<T: Trait> From<ExchangeV1<T>> for Exchange<T> {
+    fn from(exchange: ExchangeV1<T>) -> Self {
+        // Cumulative prices keep accumulating from the scaled values, as they only matter by differences
+        let scale = |price_cumulative| {
+            math::scale_price_cumulative::<T>(price_cumulative).unwrap_or_default()
+        };
+        Self {
+            first_asset_pool: exchange.first_asset_pool,
+            second_asset_pool: exchange.second_asset_pool,
+            total_shares: exchange.total_shares,
+            last_timestamp: exchange.last_timestamp,
+            price1_cumulative_last: scale(exchange.price1_cumulative_last),
+            price2_cumulative_last: scale(exchange.price2_cumulative_last),
+            shares: exchange.shares,
+        }
+    }
+}
+
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
 pub struct SwapDelta<T: Trait> {
@@ -58,32 +88,6 @@ impl<T: Trait> SwapDelta<T> {
 }
 
 impl<T: Trait> Exchange<T> {
-    // Avoid casting to float
-    fn sqrt(y: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
-        let z = if y > 3.into() {
-            let mut z = y;
-            let mut x = y
-                .checked_div(&2.into())
-                .map(|res| res.checked_add(&1.into()))
-                .flatten()
-                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-            while x < z {
-                z = x;
-                x = y
-                    .checked_div(&(x + x))
-                    .map(|res| res.checked_div(&2.into()))
-                    .flatten()
-                    .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-            }
-            z
-        } else if y != BalanceOf::<T>::zero() {
-            BalanceOf::<T>::one()
-        } else {
-            BalanceOf::<T>::zero()
-        };
-        Ok(z)
-    }
-
     // Reconsider this approach after setting
     // first_asset & second_asset minimal amount restrictions
 
@@ -104,7 +108,10 @@ impl<T: Trait> Exchange<T> {
         let mut shares_map = BTreeMap::new();
         // let min_fee = Self::get_min_fee();
 
-        let initial_shares = Self::sqrt(first_asset_amount * second_asset_amount)?;
+        let initial_shares = math::from_u256::<T>(math::sqrt(math::product::<T>(
+            first_asset_amount,
+            second_asset_amount,
+        )?))?;
         // .checked_sub(&min_fee)
         // .ok_or(Error::<T>::UnderflowOccured)?;
 
@@ -112,9 +119,6 @@ impl<T: Trait> Exchange<T> {
         let exchange = Self {
             first_asset_pool: first_asset_amount,
             second_asset_pool: second_asset_amount,
-            invariant: first_asset_amount
-                .checked_mul(&second_asset_amount)
-                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?,
             total_shares: initial_shares,
             shares: shares_map,
             last_timestamp: <pallet_timestamp::Module<T>>::get().into(),
@@ -127,19 +131,24 @@ impl<T: Trait> Exchange<T> {
     fn perform_first_to_second_asset_swap_calculation(
         &self,
         exchange_fee: BalanceOf<T>,
+        treasury_fee: BalanceOf<T>,
         first_asset_amount: BalanceOf<T>,
     ) -> Result<SwapDelta<T>, Error<T>> {
+        // Treasury fee is paid out of the pool
         let new_first_asset_pool = self
             .first_asset_pool
             .checked_add(&first_asset_amount)
-            .ok_or(Error::<T>::OverflowOccured)?;
+            .map(|result| result.checked_sub(&treasury_fee))
+            .flatten()
+            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
         let temp_first_asset_pool = new_first_asset_pool
             .checked_sub(&exchange_fee)
             .ok_or(Error::<T>::UnderflowOccured)?;
-        let new_second_asset_pool = self
-            .invariant
-            .checked_div(&temp_first_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        // Rounded up, so that invariant never decreases
+        let new_second_asset_pool = math::div_ceil::<T>(
+            self.invariant()?,
+            math::to_u256::<T>(temp_first_asset_pool)?,
+        )?;
         let second_asset_amount = self
             .second_asset_pool
             .checked_sub(&new_second_asset_pool)
@@ -156,46 +165,40 @@ impl<T: Trait> Exchange<T> {
         &self,
         first_asset_amount: BalanceOf<T>,
     ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
-        let fee = T::FeeRateNominator::get()
-            .checked_mul(&first_asset_amount)
-            .map(|result| result.checked_div(&T::FeeRateDenominator::get()))
-            .flatten()
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-
-        if let Ok(dex_treasury) = <DEXTreasury<T>>::try_get() {
-            let treasury_fee = dex_treasury
-                .treasury_fee_rate_nominator
-                .checked_mul(&fee)
-                .map(|result| result.checked_div(&dex_treasury.treasury_fee_rate_denominator))
-                .flatten()
-                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-            let exchange_fee = fee - treasury_fee;
-            let swap_delta = self
-                .perform_first_to_second_asset_swap_calculation(exchange_fee, first_asset_amount)?;
-            Ok((swap_delta, Some((treasury_fee, dex_treasury.dex_account))))
-        } else {
-            let swap_delta =
-                self.perform_first_to_second_asset_swap_calculation(fee, first_asset_amount)?;
-            Ok((swap_delta, None))
-        }
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(first_asset_amount)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+
+        let swap_delta = self.perform_first_to_second_asset_swap_calculation(
+            exchange_fee,
+            treasury_fee,
+            first_asset_amount,
+        )?;
+        Ok((swap_delta, treasury_fee_data))
     }
 
     fn perform_second_to_first_asset_swap_calculation(
         &self,
         exchange_fee: BalanceOf<T>,
+        treasury_fee: BalanceOf<T>,
         second_asset_amount: BalanceOf<T>,
     ) -> Result<SwapDelta<T>, Error<T>> {
+        // Treasury fee is paid out of the pool
         let new_second_asset_pool = self
             .second_asset_pool
             .checked_add(&second_asset_amount)
-            .ok_or(Error::<T>::OverflowOccured)?;
+            .map(|result| result.checked_sub(&treasury_fee))
+            .flatten()
+            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
         let temp_second_asset_pool = new_second_asset_pool
             .checked_sub(&exchange_fee)
             .ok_or(Error::<T>::UnderflowOccured)?;
-        let new_first_asset_pool = self
-            .invariant
-            .checked_div(&temp_second_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        // Rounded up, so that invariant never decreases
+        let new_first_asset_pool = math::div_ceil::<T>(
+            self.invariant()?,
+            math::to_u256::<T>(temp_second_asset_pool)?,
+        )?;
         let first_asset_amount = self
             .first_asset_pool
             .checked_sub(&new_first_asset_pool)
@@ -212,44 +215,28 @@ impl<T: Trait> Exchange<T> {
         &self,
         second_asset_amount: BalanceOf<T>,
     ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
-        let fee = T::FeeRateNominator::get()
-            .checked_mul(&second_asset_amount)
-            .map(|result| result.checked_div(&T::FeeRateDenominator::get()))
-            .flatten()
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-
-        if let Ok(dex_treasury) = <DEXTreasury<T>>::try_get() {
-            let treasury_fee = dex_treasury
-                .treasury_fee_rate_nominator
-                .checked_mul(&fee)
-                .map(|result| result.checked_div(&dex_treasury.treasury_fee_rate_denominator))
-                .flatten()
-                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-            let exchange_fee = fee - treasury_fee;
-            let swap_delta = self.perform_second_to_first_asset_swap_calculation(
-                exchange_fee,
-                second_asset_amount,
-            )?;
-            Ok((swap_delta, Some((treasury_fee, dex_treasury.dex_account))))
-        } else {
-            let swap_delta =
-                self.perform_second_to_first_asset_swap_calculation(fee, second_asset_amount)?;
-            Ok((swap_delta, None))
-        }
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(second_asset_amount)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+
+        let swap_delta = self.perform_second_to_first_asset_swap_calculation(
+            exchange_fee,
+            treasury_fee,
+            second_asset_amount,
+        )?;
+        Ok((swap_delta, treasury_fee_data))
     }
 
+    // Costs are rounded down, so that invest/divest round trip can not create value
     pub fn calculate_costs(
         &self,
         shares: BalanceOf<T>,
     ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
-        let first_asset_cost = shares
-            .checked_div(&self.total_shares)
-            .map(|ratio| ratio * self.first_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-        let second_asset_cost = shares
-            .checked_div(&self.total_shares)
-            .map(|ratio| ratio * self.second_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        let first_asset_cost =
+            math::mul_div::<T>(shares, self.first_asset_pool, self.total_shares)?;
+        let second_asset_cost =
+            math::mul_div::<T>(shares, self.second_asset_pool, self.total_shares)?;
 
         Ok((first_asset_cost, second_asset_cost))
     }
@@ -282,10 +269,6 @@ impl<T: Trait> Exchange<T> {
             .second_asset_pool
             .checked_add(&second_asset_amount)
             .ok_or(Error::<T>::OverflowOccured)?;
-        self.invariant = self
-            .first_asset_pool
-            .checked_mul(&self.second_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
         Ok(())
     }
 
@@ -296,10 +279,17 @@ impl<T: Trait> Exchange<T> {
         shares: BalanceOf<T>,
         sender: &T::AccountId,
     ) -> Result<(), Error<T>> {
-        if let Some(share) = self.shares.get_mut(sender) {
-            share
-                .checked_sub(&shares)
-                .ok_or(Error::<T>::UnderflowOccured)?;
+        let remaining_shares = self
+            .shares
+            .get(sender)
+            .ok_or(Error::<T>::DoesNotOwnShare)?
+            .checked_sub(&shares)
+            .ok_or(Error::<T>::UnderflowOccured)?;
+
+        if remaining_shares == BalanceOf::<T>::zero() {
+            self.shares.remove(sender);
+        } else {
+            self.shares.insert(sender.clone(), remaining_shares);
         }
 
         self.total_shares = self
@@ -314,66 +304,93 @@ impl<T: Trait> Exchange<T> {
             .second_asset_pool
             .checked_sub(&second_asset_amount)
             .ok_or(Error::<T>::UnderflowOccured)?;
-        if self.total_shares == BalanceOf::<T>::zero() {
-            self.invariant = BalanceOf::<T>::zero();
-        } else {
-            self.invariant = self
-                .first_asset_pool
-                .checked_mul(&self.second_asset_pool)
-                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-        }
         Ok(())
     }
 
+    /// Lend the pools to the flash swap
+    pub fn lend(
+        &mut self,
+        first_asset_amount: BalanceOf<T>,
+        second_asset_amount: BalanceOf<T>,
+    ) -> Result<(), Error<T>> {
+        let first_asset_pool = self
+            .first_asset_pool
+            .checked_sub(&first_asset_amount)
+            .ok_or(Error::<T>::InsufficientPool)?;
+        let second_asset_pool = self
+            .second_asset_pool
+            .checked_sub(&second_asset_amount)
+            .ok_or(Error::<T>::InsufficientPool)?;
+        self.update_pools(first_asset_pool, second_asset_pool)
+    }
+
+    /// Return the lent pools (including the fee) from the flash swap
+    pub fn repay(
+        &mut self,
+        first_asset_amount: BalanceOf<T>,
+        second_asset_amount: BalanceOf<T>,
+    ) -> Result<(), Error<T>> {
+        let first_asset_pool = self
+            .first_asset_pool
+            .checked_add(&first_asset_amount)
+            .ok_or(Error::<T>::OverflowOccured)?;
+        let second_asset_pool = self
+            .second_asset_pool
+            .checked_add(&second_asset_amount)
+            .ok_or(Error::<T>::OverflowOccured)?;
+        self.update_pools(first_asset_pool, second_asset_pool)
+    }
+
     pub fn update_pools(
         &mut self,
         first_asset_pool: BalanceOf<T>,
         second_asset_pool: BalanceOf<T>,
     ) -> Result<(), Error<T>> {
-        self.first_asset_pool = first_asset_pool;
-        self.second_asset_pool = second_asset_pool;
-
         let now: T::IMoment = <pallet_timestamp::Module<T>>::get().into();
         let time_elapsed: T::IMoment = now
             .checked_sub(&self.last_timestamp)
             .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
 
-        let price1_cumulative = first_asset_pool
-            .checked_div(&second_asset_pool)
-            .map(|result| result.checked_mul(&time_elapsed.into()))
-            .flatten()
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        // Prices held since the last update are accumulated, so the update itself does not move the average
+        self.price1_cumulative_last = math::accumulate_price::<T>(
+            self.price1_cumulative_last,
+            self.first_asset_pool,
+            self.second_asset_pool,
+            time_elapsed.into(),
+        )?;
+        self.price2_cumulative_last = math::accumulate_price::<T>(
+            self.price2_cumulative_last,
+            self.second_asset_pool,
+            self.first_asset_pool,
+            time_elapsed.into(),
+        )?;
 
-        self.price1_cumulative_last = self
-            .price1_cumulative_last
-            .checked_add(&price1_cumulative)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        self.first_asset_pool = first_asset_pool;
+        self.second_asset_pool = second_asset_pool;
+        self.last_timestamp = now;
+        Ok(())
+    }
 
-        let price2_cumulative = second_asset_pool
-            .checked_div(&first_asset_pool)
-            .map(|result| result.checked_mul(&time_elapsed.into()))
-            .flatten()
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    pub fn first_asset_pool(&self) -> BalanceOf<T> {
+        self.first_asset_pool
+    }
 
-        self.price2_cumulative_last = self
-            .price2_cumulative_last
-            .checked_add(&price2_cumulative)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    pub fn second_asset_pool(&self) -> BalanceOf<T> {
+        self.second_asset_pool
+    }
 
-        self.last_timestamp = now;
+    /// Constant product of the pools (zero for the exchange, which is not launched).
+    pub fn invariant(&self) -> Result<U256, Error<T>> {
+        math::product::<T>(self.first_asset_pool, self.second_asset_pool)
+    }
 
-        self.invariant = self
-            .first_asset_pool
-            .checked_mul(&self.second_asset_pool)
-            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
-        Ok(())
+    /// Shares, owned by the given account.
+    pub fn shares_of(&self, who: &T::AccountId) -> BalanceOf<T> {
+        self.shares.get(who).copied().unwrap_or_default()
     }
 
     pub fn ensure_launch(&self) -> dispatch::DispatchResult {
-        ensure!(
-            self.invariant == BalanceOf::<T>::zero(),
-            Error::<T>::InvariantNotNull
-        );
+        ensure!(self.invariant()?.is_zero(), Error::<T>::InvariantNotNull);
         ensure!(
             self.total_shares == BalanceOf::<T>::zero(),
             Error::<T>::TotalSharesNotNull
@@ -421,7 +438,7 @@ impl<T: Trait> Exchange<T> {
     ) -> dispatch::DispatchResult {
         ensure!(
             first_asset_out_amount >= min_first_asset_out_amount,
-            Error::<T>::SecondAssetAmountBelowExpectation
+            Error::<T>::FirstAssetAmountBelowExpectation
         );
         ensure!(
             first_asset_out_amount <= self.first_asset_pool,
@@ -4,22 +4,48 @@ use codec::{Codec, Decode, Encode};
 use frame_support::traits::Currency;
 use frame_support::{
     decl_error, decl_event, decl_module, decl_storage, dispatch, ensure,
+    storage::{with_transaction, IterableStorageDoubleMap},
     traits::{Get, WithdrawReason},
+    weights::{GetDispatchInfo, PostDispatchInfo, Weight},
     Parameter,
 };
-use frame_system::{self as system, ensure_signed};
-use sp_arithmetic::traits::{BaseArithmetic, One, Zero};
-use sp_runtime::traits::{
-    CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, MaybeSerializeDeserialize, Member,
+use frame_system::{self as system, ensure_root, ensure_signed};
+use sp_arithmetic::traits::{BaseArithmetic, Zero};
+use sp_core::U256;
+use sp_runtime::{
+    traits::{
+        CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Dispatchable, MaybeSerializeDeserialize,
+        Member, One,
+    },
+    TransactionOutcome,
 };
 
 use sp_std::{collections::btree_map::BTreeMap, fmt::Debug, prelude::*};
 
+mod concentrated_pool;
+mod conditional_order;
+mod dca_order;
 mod exchange;
-use exchange::Exchange;
-
-#[cfg(test)]
-mod mock;
+mod limit_order;
+mod liquidity_bootstrapping;
+mod math;
+mod pool;
+mod stable_exchange;
+mod twap_order;
+mod weighted_pool;
+use concentrated_pool::{ConcentratedPool, ConcentratedPoolUpdate, Position, Tick};
+use conditional_order::ConditionalOrder;
+use dca_order::DcaOrder;
+use exchange::{Exchange, ExchangeV1, SwapDelta};
+use limit_order::LimitOrder;
+use liquidity_bootstrapping::LiquidityBootstrapping;
+use pool::Pool;
+use stable_exchange::StableExchange;
+use twap_order::TwapOrder;
+use weighted_pool::WeightedPool;
+
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod mock;
 
 #[cfg(test)]
 mod tests;
@@ -31,6 +57,34 @@ pub use serde::{Deserialize, Serialize};
 pub type BalanceOf<T> =
     <<T as Trait>::Currency as Currency<<T as frame_system::Trait>::AccountId>>::Balance;
 
+/// Id of the weighted pool
+pub type WeightedPoolId = u64;
+
+/// Id of the position within the concentrated liquidity pool
+pub type PositionId = u64;
+
+/// Id of the limit order
+pub type LimitOrderId = u64;
+
+/// Id of the recurring (DCA) order
+pub type DcaOrderId = u64;
+
+/// Id of the time-sliced (TWAP) order
+pub type TwapOrderId = u64;
+
+/// Id of the conditional (stop-loss or take-profit) order
+pub type ConditionalOrderId = u64;
+
+/// Estimated computation weight of a single scheduled order execution, not including the storage accesses
+pub const SCHEDULED_EXECUTION_WEIGHT: Weight = 50_000_000;
+
+/// Estimated computation weight of the concentrated pool swap, not including the crossed ticks and the storage accesses.
+/// Includes the price tick search (about 20 tick price calculations), done when the swap stops between the ticks.
+pub const CONCENTRATED_SWAP_WEIGHT: Weight = 50_000_000;
+
+/// Estimated computation weight of a single tick crossing by the concentrated pool swap, not including the storage accesses
+pub const TICK_CROSSING_WEIGHT: Weight = 5_000_000;
+
 /// Enum, representing either main network currency, supported natively or our internal represenation for assets from other parachains
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
@@ -69,6 +123,68 @@ impl<AccountId: Default + Debug, Balance: Default + Debug> DexTreasury<AccountId
     }
 }
 
+/// Fixed point one of the exchange prices, which are scaled by it the same way, as FixedU128
+pub const PRICE_ONE: u128 = 1_000_000_000_000_000_000;
+
+/// Pools and cumulative prices of the exchange, ordered as requested
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PriceData<Balance> {
+    pub asset_a_pool: Balance,
+    pub asset_b_pool: Balance,
+    // asset_a_pool / asset_b_pool * PRICE_ONE * time_elapsed, wrapping around on overflow
+    pub price_a_cumulative: Balance,
+    // asset_b_pool / asset_a_pool * PRICE_ONE * time_elapsed, wrapping around on overflow
+    pub price_b_cumulative: Balance,
+    // timestamp of the last cumulative prices update
+    pub last_timestamp: Balance,
+}
+
+/// Storage layout version of the pallet
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Releases {
+    // exchanges with the stored invariant and integer cumulative prices
+    V1,
+    V2,
+}
+
+impl Default for Releases {
+    fn default() -> Self {
+        Releases::V1
+    }
+}
+
+/// Order, executed by the pallet at the scheduled block
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScheduledOrder {
+    Dca(DcaOrderId),
+    Twap(TwapOrderId),
+}
+
+/// Condition on the average price of the conditional order, triggering the swap
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TriggerCondition {
+    // price falls to or below the trigger one
+    StopLoss,
+    // price rises to or above the trigger one
+    TakeProfit,
+}
+
+/// Swap, which passed all checks and can be applied without failures
+pub struct PreparedSwap<T: Trait> {
+    first_asset: Asset<T::AssetId>,
+    second_asset: Asset<T::AssetId>,
+    // pool state after the swap
+    pool: Pool<T>,
+    asset_in: Asset<T::AssetId>,
+    asset_in_amount: BalanceOf<T>,
+    asset_out: Asset<T::AssetId>,
+    pub asset_out_amount: BalanceOf<T>,
+    treasury_fee_data: Option<(BalanceOf<T>, T::AccountId)>,
+}
+
 pub trait Trait: system::Trait + pallet_timestamp::Trait {
     type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 
@@ -98,18 +214,111 @@ pub trait Trait: system::Trait + pallet_timestamp::Trait {
     type FeeRateNominator: Get<BalanceOf<Self>>;
 
     type FeeRateDenominator: Get<BalanceOf<Self>>;
+
+    /// Maximum weight of the scheduled orders, executed in a single block.
+    /// Due orders beyond it are deferred to the next block.
+    type MaxScheduledWeight: Get<Weight>;
+
+    /// Main currency deposit of the recurring and time-sliced orders, returned once the order is completed or cancelled.
+    type ScheduledOrderDeposit: Get<BalanceOf<Self>>;
+
+    /// Minimum averaging period of the price, triggering the conditional orders.
+    type TwapPeriod: Get<Self::IMoment>;
+
+    /// Maximum averaging period of the price. Cumulative price wraps around, so the average over the longer window
+    /// could be ambiguous. Averages up to `u128::max_value() / MaxTwapWindow` are measured exactly.
+    type MaxTwapWindow: Get<Self::IMoment>;
+
+    /// Maximum number of the ticks (initialized or bitmap word bounds), crossed by the concentrated pool swap.
+    type MaxTicksCrossed: Get<u32>;
+
+    /// Call, dispatched by the flash swap borrower, while holding the borrowed assets.
+    type Call: Parameter
+        + Dispatchable<Origin = Self::Origin, PostInfo = PostDispatchInfo>
+        + GetDispatchInfo;
 }
 
 decl_storage! {
     trait Store for Module<T: Trait> as TemplateModule {
         pub Exchanges get(fn exchanges): double_map hasher(blake2_128_concat) Asset<T::AssetId>, hasher(blake2_128_concat) Asset<T::AssetId> => Exchange<T>;
 
+        // StableSwap pools of the pegged assets pairs, registered by governance.
+        pub StableExchanges get(fn stable_exchanges):
+            double_map hasher(blake2_128_concat) Asset<T::AssetId>, hasher(blake2_128_concat) Asset<T::AssetId> => Option<StableExchange<T>>;
+
+        // Weighted pools of two to eight assets.
+        pub WeightedPools get(fn weighted_pools): map hasher(twox_64_concat) WeightedPoolId => Option<WeightedPool<T>>;
+
+        pub NextWeightedPoolId get(fn next_weighted_pool_id): WeightedPoolId;
+
+        // Weighted pools, used for the token launches.
+        pub LiquidityBootstrappingPools get(fn liquidity_bootstrapping_pools):
+            map hasher(twox_64_concat) WeightedPoolId => Option<LiquidityBootstrapping<T>>;
+
+        // Pools of the ordered assets pairs with liquidity, concentrated in tick ranges.
+        pub ConcentratedPools get(fn concentrated_pools):
+            double_map hasher(blake2_128_concat) Asset<T::AssetId>, hasher(blake2_128_concat) Asset<T::AssetId> => Option<ConcentratedPool<T>>;
+
+        // Initialized ticks of the concentrated pools.
+        pub ConcentratedTicks get(fn concentrated_ticks):
+            double_map hasher(blake2_128_concat) (Asset<T::AssetId>, Asset<T::AssetId>), hasher(twox_64_concat) i32 => Option<Tick<BalanceOf<T>>>;
+
+        // Bitmaps of the initialized ticks of the concentrated pools, divided by the tick spacing, 256 per word.
+        pub ConcentratedTickBitmaps get(fn concentrated_tick_bitmaps):
+            double_map hasher(blake2_128_concat) (Asset<T::AssetId>, Asset<T::AssetId>), hasher(twox_64_concat) i32 => U256;
+
+        // Liquidity positions of the concentrated pools.
+        pub ConcentratedPositions get(fn concentrated_positions):
+            double_map hasher(blake2_128_concat) (Asset<T::AssetId>, Asset<T::AssetId>), hasher(twox_64_concat) PositionId => Option<Position<T::AccountId, BalanceOf<T>>>;
+
+        // Accounts, allowed to create liquidity bootstrapping pools.
+        pub LiquidityBootstrappingPoolCreators get(fn liquidity_bootstrapping_pool_creators):
+            map hasher(blake2_128_concat) T::AccountId => bool;
+
+        // Limit orders, waiting to be filled against the exchange pools.
+        pub LimitOrders get(fn limit_orders): map hasher(twox_64_concat) LimitOrderId => Option<LimitOrder<T>>;
+
+        pub NextLimitOrderId get(fn next_limit_order_id): LimitOrderId;
+
+        // Recurring swaps, executed by the pallet.
+        pub DcaOrders get(fn dca_orders): map hasher(twox_64_concat) DcaOrderId => Option<DcaOrder<T>>;
+
+        pub NextDcaOrderId get(fn next_dca_order_id): DcaOrderId;
+
+        // Large swaps, executed by the pallet in slices.
+        pub TwapOrders get(fn twap_orders): map hasher(twox_64_concat) TwapOrderId => Option<TwapOrder<T>>;
+
+        pub NextTwapOrderId get(fn next_twap_order_id): TwapOrderId;
+
+        // Stop-loss and take-profit orders, triggered by the average exchange price.
+        pub ConditionalOrders get(fn conditional_orders):
+            map hasher(twox_64_concat) ConditionalOrderId => Option<ConditionalOrder<T>>;
+
+        pub NextConditionalOrderId get(fn next_conditional_order_id): ConditionalOrderId;
+
+        // Orders, due at the given block, by the position in the block queue.
+        pub ScheduledOrders get(fn scheduled_orders):
+            double_map hasher(twox_64_concat) T::BlockNumber, hasher(twox_64_concat) u32 => Option<ScheduledOrder>;
+
+        // Number of the orders, ever scheduled at the given block.
+        pub ScheduledOrdersCount get(fn scheduled_orders_count): map hasher(twox_64_concat) T::BlockNumber => u32;
+
+        // Block and position of the next scheduled order to be executed.
+        pub ScheduledOrdersCursor get(fn scheduled_orders_cursor): Option<(T::BlockNumber, u32)>;
+
+        // Exchanges, lending their pools to the flash swap in progress.
+        pub LockedExchanges get(fn locked_exchanges):
+            double_map hasher(blake2_128_concat) Asset<T::AssetId>, hasher(blake2_128_concat) Asset<T::AssetId> => bool;
+
         // Balances of assets, located on other parachains.
         pub AssetBalances get(fn asset_balances):
             double_map hasher(blake2_128_concat) T::AccountId, hasher(blake2_128_concat) T::AssetId => BalanceOf<T>;
 
         // Treasury data (used to charge fee, when enabled)
         pub DEXTreasury get(fn dex_treasury) config(): DexTreasury<T::AccountId, BalanceOf<T>>;
+
+        // Storage layout version, new chains start with the latest one.
+        pub StorageVersion get(fn storage_version) build(|_: &GenesisConfig<T>| Releases::V2): Releases;
     }
     add_extra_genesis {
         config(assets): Vec<T::AssetId>;
@@ -134,11 +343,106 @@ decl_event!(
         Shares = BalanceOf<T>,
         Balance = BalanceOf<T>,
         TreasuryFee = Option<BalanceOf<T>>,
+        BlockNumber = <T as system::Trait>::BlockNumber,
     {
         // account id, asset in, asset in amount, asset out, asset out amount, treasury fee
         Exchanged(AccountId, Asset, Balance, Asset, Balance, TreasuryFee),
         Invested(AccountId, Asset, Asset, Shares),
         Divested(AccountId, Asset, Asset, Shares),
+        // first asset, second asset, amplification
+        StableExchangeRegistered(Asset, Asset, u32),
+        // first asset, second asset, current amplification, future amplification, future block
+        AmplificationRampStarted(Asset, Asset, u32, u32, BlockNumber),
+        // weighted pool id, creator, initial shares
+        WeightedPoolCreated(WeightedPoolId, AccountId, Shares),
+        // weighted pool id, account id, asset in, asset in amount, asset out, asset out amount, treasury fee
+        WeightedExchanged(
+            WeightedPoolId,
+            AccountId,
+            Asset,
+            Balance,
+            Asset,
+            Balance,
+            TreasuryFee,
+        ),
+        WeightedPoolJoined(WeightedPoolId, AccountId, Shares),
+        WeightedPoolExited(WeightedPoolId, AccountId, Shares),
+        // account id, whether it is allowed to create liquidity bootstrapping pools
+        LiquidityBootstrappingPoolCreatorSet(AccountId, bool),
+        // weighted pool id, owner, start block, end block
+        LiquidityBootstrappingPoolCreated(WeightedPoolId, AccountId, BlockNumber, BlockNumber),
+        LiquidityBootstrappingPoolPaused(WeightedPoolId),
+        LiquidityBootstrappingPoolUnpaused(WeightedPoolId),
+        LiquidityBootstrappingPoolWithdrawn(WeightedPoolId, AccountId),
+        // first asset, second asset, tick spacing, initial tick
+        ConcentratedPoolCreated(Asset, Asset, u32, i32),
+        // account id, first asset, second asset, position id, lower tick, upper tick, liquidity
+        PositionMinted(AccountId, Asset, Asset, PositionId, i32, i32, Balance),
+        // account id, first asset, second asset, position id, burned liquidity
+        PositionBurned(AccountId, Asset, Asset, PositionId, Balance),
+        // account id, first asset, second asset, position id, first asset fees, second asset fees
+        FeesCollected(AccountId, Asset, Asset, PositionId, Balance, Balance),
+        // account id, asset in, asset in amount, asset out, asset out amount, treasury fee
+        ConcentratedExchanged(AccountId, Asset, Balance, Asset, Balance, TreasuryFee),
+        // limit order id, owner, asset in, asset in amount, asset out, min asset out amount, expiry
+        LimitOrderPlaced(
+            LimitOrderId,
+            AccountId,
+            Asset,
+            Balance,
+            Asset,
+            Balance,
+            BlockNumber,
+        ),
+        // limit order id, keeper, filled asset in amount, asset out amount, keeper bounty
+        LimitOrderFilled(LimitOrderId, AccountId, Balance, Balance, Balance),
+        // limit order id, refunded asset in amount
+        LimitOrderCancelled(LimitOrderId, Balance),
+        // dca order id, owner, asset in, asset in amount per execution, asset out, interval, executions
+        DcaOrderCreated(
+            DcaOrderId,
+            AccountId,
+            Asset,
+            Balance,
+            Asset,
+            BlockNumber,
+            u32,
+        ),
+        // dca order id, asset in amount, asset out amount, remaining executions
+        DcaOrderExecuted(DcaOrderId, Balance, Balance, u32),
+        // dca order id, refunded asset in amount, remaining executions (execution failed, e.g. due to the slippage bound)
+        DcaOrderExecutionSkipped(DcaOrderId, Balance, u32),
+        // dca order id, refunded asset in amount
+        DcaOrderCancelled(DcaOrderId, Balance),
+        // twap order id, owner, asset in, asset in amount, asset out, min asset out amount, slices
+        TwapOrderCreated(TwapOrderId, AccountId, Asset, Balance, Asset, Balance, u32),
+        // twap order id, slice asset in amount, asset out amount, remaining slices
+        TwapSliceExecuted(TwapOrderId, Balance, Balance, u32),
+        // twap order id, refunded slice asset in amount, remaining slices (slice would break the minimum average price)
+        TwapSliceSkipped(TwapOrderId, Balance, u32),
+        // twap order id, refunded asset in amount
+        TwapOrderCancelled(TwapOrderId, Balance),
+        // conditional order id, owner, condition, asset in, asset in amount, asset out, trigger price, expiry
+        ConditionalOrderPlaced(
+            ConditionalOrderId,
+            AccountId,
+            TriggerCondition,
+            Asset,
+            Balance,
+            Asset,
+            Balance,
+            BlockNumber,
+        ),
+        // conditional order id, average price (condition is not met, new averaging window is started)
+        ConditionalOrderObserved(ConditionalOrderId, Balance),
+        // conditional order id (averaging window exceeded the maximum, new one is started)
+        ConditionalOrderWindowRestarted(ConditionalOrderId),
+        // conditional order id, average price, asset in amount, asset out amount
+        ConditionalOrderTriggered(ConditionalOrderId, Balance, Balance, Balance),
+        // conditional order id, refunded asset in amount
+        ConditionalOrderCancelled(ConditionalOrderId, Balance),
+        // account id, borrowed asset, borrowed amount, paired asset, fee, treasury fee
+        FlashSwapped(AccountId, Asset, Balance, Asset, Balance, TreasuryFee),
     }
 );
 
@@ -159,6 +463,54 @@ decl_error! {
         DoesNotOwnShare,
         InsufficientKsmBalance,
         InsufficientOtherAssetBalance,
+        StableExchangeNotExists,
+        InvalidAmplification,
+        InvalidAmplificationRamp,
+        InvariantNotConverged,
+        StablePriceNotSupported,
+        WeightedPoolNotExists,
+        InvalidWeightedPool,
+        InvalidWeight,
+        AssetNotInWeightedPool,
+        AssetAmountsMismatch,
+        LowAssetAmount,
+        AssetAmountBelowExpectation,
+        AssetAmountAboveLimit,
+        SharesBelowExpectation,
+        MaxInRatioExceeded,
+        MaxOutRatioExceeded,
+        PowNotConverged,
+        LiquidityBootstrappingPoolNotExists,
+        NotLiquidityBootstrappingPoolCreator,
+        NotLiquidityBootstrappingPoolOwner,
+        InvalidLiquidityBootstrappingSchedule,
+        LiquidityBootstrappingPoolPaused,
+        ConcentratedPoolNotExists,
+        ConcentratedPoolAlreadyExists,
+        InvalidTick,
+        InvalidTickSpacing,
+        InvalidTickRange,
+        LowLiquidity,
+        PositionNotExists,
+        NotPositionOwner,
+        InsufficientPositionLiquidity,
+        TooManyTicksCrossed,
+        LimitOrderNotExists,
+        LimitOrderExpired,
+        NotLimitOrderOwner,
+        InvalidFillAmount,
+        DcaOrderNotExists,
+        NotDcaOrderOwner,
+        InvalidDcaSchedule,
+        TwapOrderNotExists,
+        NotTwapOrderOwner,
+        InvalidTwapSchedule,
+        ConditionalOrderNotExists,
+        ConditionalOrderExpired,
+        NotConditionalOrderOwner,
+        TwapPeriodNotElapsed,
+        ExchangeLocked,
+        FlashSwapNotSupported,
 
         // Safe math
         OverflowOccured,
@@ -170,189 +522,1755 @@ decl_error! {
 decl_module! {
     pub struct Module<T: Trait> for enum Call where origin: T::Origin {
 
-        type Error = Error<T>;
+        type Error = Error<T>;
+
+        fn deposit_event() = default;
+
+        /// Execute due scheduled orders within the weight budget.
+        fn on_initialize(now: T::BlockNumber) -> Weight {
+            Self::execute_scheduled_orders(now)
+        }
+
+        fn integrity_test() {
+            assert!(
+                T::MaxTwapWindow::get() >= T::TwapPeriod::get(),
+                "Maximum averaging window should not be shorter than the TWAP period"
+            );
+        }
+
+        fn on_runtime_upgrade() -> Weight {
+            Self::migrate_to_v2()
+        }
+
+        #[weight = 10_000]
+        pub fn initialize_exchange(origin, first_asset: Asset<T::AssetId>, first_asset_amount: BalanceOf<T>, second_asset: Asset<T::AssetId>, second_asset_amount: BalanceOf<T>) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (first_asset, first_asset_amount, second_asset, second_asset_amount) =
+                Self::adjust_assets_amount_order(first_asset, first_asset_amount, second_asset, second_asset_amount);
+
+            // TODO adjust first and second min asset amounts
+            ensure!(
+                first_asset_amount > BalanceOf::<T>::zero(),
+                Error::<T>::LowFirstAssetAmount
+            );
+            ensure!(
+                second_asset_amount > BalanceOf::<T>::zero(),
+                Error::<T>::LowSecondAssetAmount
+            );
+
+            Self::ensure_exchange_not_exists(first_asset, second_asset)?;
+            // Pairs, registered as stable, are launched as StableSwap pools
+            let mut pool = Self::pool(first_asset, second_asset);
+            pool.liquidity().ensure_launch()?;
+            Self::ensure_sufficient_balances(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount)?;
+
+            // TODO adjust shares allocation
+            let (exchange, initial_shares) = Exchange::<T>::initialize_new(first_asset_amount, second_asset_amount, sender.clone())?;
+            *pool.liquidity_mut() = exchange;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_assets(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount);
+
+            Self::insert_pool(first_asset, second_asset, pool);
+
+            Self::deposit_event(RawEvent::Invested(sender, first_asset, second_asset, initial_shares));
+            Ok(())
+        }
+
+        #[weight = 10_000]
+        pub fn swap_to_exact(
+            origin,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>,
+            receiver: T::AccountId
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::swap(&sender, asset_in, asset_in_amount, asset_out, min_asset_out_amount)?;
+            Ok(())
+        }
+
+        #[weight = 10_000]
+        pub fn invest_liquidity(origin, first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>, shares: BalanceOf<T>) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::invest(&sender, first_asset, second_asset, shares)
+        }
+
+        #[weight = 10_000]
+        pub fn divest_liquidity(
+            origin,
+            first_asset: Asset<T::AssetId>,
+            second_asset: Asset<T::AssetId>,
+            shares_burned:  BalanceOf<T>,
+            min_first_asset_received: BalanceOf<T>,
+            min_second_asset_received: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+
+            let mut pool = Self::ensure_exchange_exists(first_asset, second_asset)?;
+            pool.liquidity().ensure_burned_shares(&sender, shares_burned)?;
+
+            let (first_asset_cost, second_asset_cost) = pool.liquidity().calculate_costs(shares_burned)?;
+            Self::ensure_divest_expectations(first_asset_cost, second_asset_cost, min_first_asset_received, min_second_asset_received)?;
+
+            // Avoid overflow risks
+            Self::ensure_can_hold_balances(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost)?;
+
+            // Divest funds from exchange
+            pool.liquidity_mut().divest(first_asset_cost, second_asset_cost, shares_burned, &sender)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_assets(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost);
+
+            // Update runtime exchange storage state
+            Self::insert_pool(first_asset, second_asset, pool);
+
+            Self::deposit_event(RawEvent::Divested(sender, first_asset, second_asset, shares_burned));
+            Ok(())
+        }
+
+        /// Register StableSwap pool for the pair of pegged assets. It is launched by `initialize_exchange`.
+        #[weight = 10_000]
+        pub fn register_stable_exchange(origin, first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>, amplification: u32) -> dispatch::DispatchResult {
+            ensure_root(origin)?;
+
+            Self::ensure_valid_exchange(first_asset, second_asset)?;
+            let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+
+            Self::ensure_exchange_not_exists(first_asset, second_asset)?;
+            ensure!(
+                !<StableExchanges<T>>::contains_key(first_asset, second_asset),
+                Error::<T>::ExchangeAlreadyExists
+            );
+            ensure!(
+                amplification > 0 && amplification <= stable_exchange::MAX_AMPLIFICATION,
+                Error::<T>::InvalidAmplification
+            );
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            // Drop the state of the divested constant product exchange, if any
+            <Exchanges<T>>::remove(first_asset, second_asset);
+            <StableExchanges<T>>::insert(first_asset, second_asset, StableExchange::new(amplification));
+
+            Self::deposit_event(RawEvent::StableExchangeRegistered(first_asset, second_asset, amplification));
+            Ok(())
+        }
+
+        /// Linearly change amplification of the StableSwap pool from its current value to `future_amplification`,
+        /// reached at `future_block`.
+        #[weight = 10_000]
+        pub fn ramp_amplification(
+            origin,
+            first_asset: Asset<T::AssetId>,
+            second_asset: Asset<T::AssetId>,
+            future_amplification: u32,
+            future_block: T::BlockNumber
+        ) -> dispatch::DispatchResult {
+            ensure_root(origin)?;
+
+            let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+
+            let mut stable_exchange = Self::stable_exchanges(first_asset, second_asset)
+                .ok_or(Error::<T>::StableExchangeNotExists)?;
+            stable_exchange.ramp_amplification(future_amplification, future_block)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            let amplification = stable_exchange.amplification.clone();
+            <StableExchanges<T>>::insert(first_asset, second_asset, stable_exchange);
+
+            Self::deposit_event(RawEvent::AmplificationRampStarted(
+                first_asset,
+                second_asset,
+                amplification.initial,
+                amplification.future,
+                amplification.future_block,
+            ));
+            Ok(())
+        }
+
+        /// Create weighted pool of two to eight distinct (asset, weight, amount) entries, seeded by the sender.
+        #[weight = 10_000]
+        pub fn create_weighted_pool(origin, assets: Vec<(Asset<T::AssetId>, u32, BalanceOf<T>)>) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (weighted_pool, initial_shares) = WeightedPool::<T>::initialize_new(assets, sender.clone())?;
+            let pool_id = Self::ensure_can_seed_weighted_pool(&sender, &weighted_pool)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::seed_weighted_pool(&sender, pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::WeightedPoolCreated(pool_id, sender, initial_shares));
+            Ok(())
+        }
+
+        /// Swap exact `asset_in_amount` of `asset_in` for at least `min_asset_out_amount` of `asset_out` in the weighted pool.
+        #[weight = 10_000]
+        pub fn swap_exact_in_weighted(
+            origin,
+            pool_id: WeightedPoolId,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_tradable(pool_id)?;
+            let index_in = weighted_pool.asset_index(asset_in)?;
+            let index_out = weighted_pool.asset_index(asset_out)?;
+
+            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+
+            let (asset_out_amount, treasury_fee_data) = weighted_pool.swap_exact_in(index_in, index_out, asset_in_amount)?;
+            ensure!(asset_out_amount >= min_asset_out_amount, Error::<T>::AssetAmountBelowExpectation);
+
+            Self::ensure_can_hold_balance(&sender, asset_out, asset_out_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::apply_weighted_swap(&sender, pool_id, weighted_pool, asset_in, asset_in_amount, asset_out, asset_out_amount, treasury_fee_data);
+            Ok(())
+        }
+
+        /// Swap at most `max_asset_in_amount` of `asset_in` for exact `asset_out_amount` of `asset_out` in the weighted pool.
+        #[weight = 10_000]
+        pub fn swap_exact_out_weighted(
+            origin,
+            pool_id: WeightedPoolId,
+            asset_in: Asset<T::AssetId>,
+            max_asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            asset_out_amount: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_tradable(pool_id)?;
+            let index_in = weighted_pool.asset_index(asset_in)?;
+            let index_out = weighted_pool.asset_index(asset_out)?;
+
+            let (asset_in_amount, treasury_fee_data) = weighted_pool.swap_exact_out(index_in, index_out, asset_out_amount)?;
+            ensure!(asset_in_amount <= max_asset_in_amount, Error::<T>::AssetAmountAboveLimit);
+
+            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+            Self::ensure_can_hold_balance(&sender, asset_out, asset_out_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::apply_weighted_swap(&sender, pool_id, weighted_pool, asset_in, asset_in_amount, asset_out, asset_out_amount, treasury_fee_data);
+            Ok(())
+        }
+
+        /// Buy `shares` of the weighted pool, depositing at most `max_asset_amounts` (ordered as the pool assets).
+        #[weight = 10_000]
+        pub fn join_weighted_pool(origin, pool_id: WeightedPoolId, shares: BalanceOf<T>, max_asset_amounts: Vec<BalanceOf<T>>) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_joinable(pool_id, &sender)?;
+            ensure!(max_asset_amounts.len() == weighted_pool.assets.len(), Error::<T>::AssetAmountsMismatch);
+
+            let asset_amounts = weighted_pool.join(shares, &sender)?;
+            for ((weighted_asset, asset_amount), max_asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()).zip(max_asset_amounts.iter()) {
+                ensure!(asset_amount <= max_asset_amount, Error::<T>::AssetAmountAboveLimit);
+                Self::ensure_sufficient_balance(&sender, weighted_asset.asset, *asset_amount)?;
+            }
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            for (weighted_asset, asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()) {
+                Self::slash_asset(&sender, weighted_asset.asset, *asset_amount);
+            }
+
+            Self::insert_weighted_pool(pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::WeightedPoolJoined(pool_id, sender, shares));
+            Ok(())
+        }
+
+        /// Burn `shares` of the weighted pool, withdrawing at least `min_asset_amounts` (ordered as the pool assets).
+        #[weight = 10_000]
+        pub fn exit_weighted_pool(origin, pool_id: WeightedPoolId, shares: BalanceOf<T>, min_asset_amounts: Vec<BalanceOf<T>>) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_joinable(pool_id, &sender)?;
+            ensure!(min_asset_amounts.len() == weighted_pool.assets.len(), Error::<T>::AssetAmountsMismatch);
+
+            let asset_amounts = weighted_pool.exit(shares, &sender)?;
+            for ((weighted_asset, asset_amount), min_asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()).zip(min_asset_amounts.iter()) {
+                ensure!(asset_amount >= min_asset_amount, Error::<T>::AssetAmountBelowExpectation);
+                Self::ensure_can_hold_balance(&sender, weighted_asset.asset, *asset_amount)?;
+            }
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            for (weighted_asset, asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()) {
+                Self::mint_asset(&sender, weighted_asset.asset, *asset_amount);
+            }
+
+            Self::insert_weighted_pool(pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::WeightedPoolExited(pool_id, sender, shares));
+            Ok(())
+        }
+
+        /// Buy at least `min_shares` of the weighted pool, depositing `asset_in_amount` of `asset_in` only.
+        #[weight = 10_000]
+        pub fn join_weighted_pool_single_asset(
+            origin,
+            pool_id: WeightedPoolId,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            min_shares: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_joinable(pool_id, &sender)?;
+            let index = weighted_pool.asset_index(asset_in)?;
+
+            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+
+            let (shares, treasury_fee_data) = weighted_pool.join_single_asset(index, asset_in_amount, &sender)?;
+            ensure!(shares >= min_shares, Error::<T>::SharesBelowExpectation);
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, asset_in_amount);
+            Self::charge_treasury_fee(asset_in, treasury_fee_data);
+
+            Self::insert_weighted_pool(pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::WeightedPoolJoined(pool_id, sender, shares));
+            Ok(())
+        }
+
+        /// Burn `shares` of the weighted pool, withdrawing at least `min_asset_out_amount` of `asset_out` only.
+        #[weight = 10_000]
+        pub fn exit_weighted_pool_single_asset(
+            origin,
+            pool_id: WeightedPoolId,
+            shares: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut weighted_pool = Self::ensure_weighted_pool_joinable(pool_id, &sender)?;
+            let index = weighted_pool.asset_index(asset_out)?;
+
+            let (asset_out_amount, treasury_fee_data) = weighted_pool.exit_single_asset(index, shares, &sender)?;
+            ensure!(asset_out_amount >= min_asset_out_amount, Error::<T>::AssetAmountBelowExpectation);
+
+            Self::ensure_can_hold_balance(&sender, asset_out, asset_out_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_asset(&sender, asset_out, asset_out_amount);
+            Self::charge_treasury_fee(asset_out, treasury_fee_data);
+
+            Self::insert_weighted_pool(pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::WeightedPoolExited(pool_id, sender, shares));
+            Ok(())
+        }
+
+        /// Allow or disallow the account to create liquidity bootstrapping pools.
+        #[weight = 10_000]
+        pub fn set_liquidity_bootstrapping_pool_creator(origin, who: T::AccountId, allowed: bool) -> dispatch::DispatchResult {
+            ensure_root(origin)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            if allowed {
+                <LiquidityBootstrappingPoolCreators<T>>::insert(&who, true);
+            } else {
+                <LiquidityBootstrappingPoolCreators<T>>::remove(&who);
+            }
+
+            Self::deposit_event(RawEvent::LiquidityBootstrappingPoolCreatorSet(who, allowed));
+            Ok(())
+        }
+
+        /// Create weighted pool for the token launch of two to eight distinct (asset, start weight, end weight, amount) entries,
+        /// seeded by the sender. Weights shift linearly between `start_block` and `end_block`.
+        #[weight = 10_000]
+        pub fn create_liquidity_bootstrapping_pool(
+            origin,
+            assets: Vec<(Asset<T::AssetId>, u32, u32, BalanceOf<T>)>,
+            start_block: T::BlockNumber,
+            end_block: T::BlockNumber
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            ensure!(Self::liquidity_bootstrapping_pool_creators(&sender), Error::<T>::NotLiquidityBootstrappingPoolCreator);
+
+            let end_weights = assets.iter().map(|(_, _, end_weight, _)| *end_weight).collect();
+            let assets = assets
+                .into_iter()
+                .map(|(asset, start_weight, _, amount)| (asset, start_weight, amount))
+                .collect::<Vec<_>>();
+            let start_weights = assets.iter().map(|(_, start_weight, _)| *start_weight).collect();
+
+            let (mut weighted_pool, _) = WeightedPool::<T>::initialize_new(assets, sender.clone())?;
+            let liquidity_bootstrapping = LiquidityBootstrapping::<T>::new(sender.clone(), start_weights, end_weights, start_block, end_block)?;
+            weighted_pool.set_weights(liquidity_bootstrapping.weights_at(<system::Module<T>>::block_number()));
+
+            let pool_id = Self::ensure_can_seed_weighted_pool(&sender, &weighted_pool)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::seed_weighted_pool(&sender, pool_id, weighted_pool);
+            <LiquidityBootstrappingPools<T>>::insert(pool_id, liquidity_bootstrapping);
+
+            Self::deposit_event(RawEvent::LiquidityBootstrappingPoolCreated(pool_id, sender, start_block, end_block));
+            Ok(())
+        }
+
+        /// Disable swaps in the liquidity bootstrapping pool.
+        #[weight = 10_000]
+        pub fn pause_liquidity_bootstrapping_pool(origin, pool_id: WeightedPoolId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut liquidity_bootstrapping = Self::ensure_liquidity_bootstrapping_pool_owner(pool_id, &sender)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            liquidity_bootstrapping.paused = true;
+            <LiquidityBootstrappingPools<T>>::insert(pool_id, liquidity_bootstrapping);
+
+            Self::deposit_event(RawEvent::LiquidityBootstrappingPoolPaused(pool_id));
+            Ok(())
+        }
+
+        /// Enable swaps in the liquidity bootstrapping pool.
+        #[weight = 10_000]
+        pub fn unpause_liquidity_bootstrapping_pool(origin, pool_id: WeightedPoolId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut liquidity_bootstrapping = Self::ensure_liquidity_bootstrapping_pool_owner(pool_id, &sender)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            liquidity_bootstrapping.paused = false;
+            <LiquidityBootstrappingPools<T>>::insert(pool_id, liquidity_bootstrapping);
+
+            Self::deposit_event(RawEvent::LiquidityBootstrappingPoolUnpaused(pool_id));
+            Ok(())
+        }
+
+        /// Withdraw all assets of the liquidity bootstrapping pool to its owner, closing the pool.
+        #[weight = 10_000]
+        pub fn withdraw_liquidity_bootstrapping_pool(origin, pool_id: WeightedPoolId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_liquidity_bootstrapping_pool_owner(pool_id, &sender)?;
+            let mut weighted_pool = Self::ensure_weighted_pool_exists(pool_id)?;
+
+            // Owner is the only liquidity provider
+            let shares = weighted_pool.shares_of(&sender);
+            let asset_amounts = weighted_pool.exit(shares, &sender)?;
+            for (weighted_asset, asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()) {
+                Self::ensure_can_hold_balance(&sender, weighted_asset.asset, *asset_amount)?;
+            }
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            for (weighted_asset, asset_amount) in weighted_pool.assets.iter().zip(asset_amounts.iter()) {
+                Self::mint_asset(&sender, weighted_asset.asset, *asset_amount);
+            }
+
+            Self::insert_weighted_pool(pool_id, weighted_pool);
+
+            Self::deposit_event(RawEvent::LiquidityBootstrappingPoolWithdrawn(pool_id, sender));
+            Ok(())
+        }
+
+        /// Create concentrated liquidity pool of the assets pair. Price of the tick is 1.0001 ^ tick
+        /// (`second_asset` amount per `first_asset`), positions are bounded by the multiples of `tick_spacing`.
+        #[weight = 10_000]
+        pub fn create_concentrated_pool(origin, first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>, tick_spacing: u32, initial_tick: i32) -> dispatch::DispatchResult {
+            ensure_signed(origin)?;
+
+            Self::ensure_valid_exchange(first_asset, second_asset)?;
+            let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(first_asset, second_asset);
+            ensure!(
+                !<ConcentratedPools<T>>::contains_key(first_asset, second_asset),
+                Error::<T>::ConcentratedPoolAlreadyExists
+            );
+
+            // Price of the reversed pair is inverted
+            let initial_tick = if adjusted { initial_tick.saturating_neg() } else { initial_tick };
+            let concentrated_pool = ConcentratedPool::<T>::new(tick_spacing, initial_tick)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            <ConcentratedPools<T>>::insert(first_asset, second_asset, concentrated_pool);
+
+            Self::deposit_event(RawEvent::ConcentratedPoolCreated(first_asset, second_asset, tick_spacing, initial_tick));
+            Ok(())
+        }
+
+        /// Provide `liquidity` to the concentrated pool within the price range between `lower_tick` and `upper_tick`,
+        /// depositing at most `max_first_asset_amount` and `max_second_asset_amount`.
+        #[weight = 10_000]
+        #[allow(clippy::too_many_arguments)]
+        pub fn mint_concentrated_position(
+            origin,
+            first_asset: Asset<T::AssetId>,
+            second_asset: Asset<T::AssetId>,
+            lower_tick: i32,
+            upper_tick: i32,
+            liquidity: BalanceOf<T>,
+            max_first_asset_amount: BalanceOf<T>,
+            max_second_asset_amount: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (_, _, adjusted) = Self::adjust_assets_order(first_asset, second_asset);
+            let (first_asset, max_first_asset_amount, second_asset, max_second_asset_amount) =
+                Self::adjust_assets_amount_order(first_asset, max_first_asset_amount, second_asset, max_second_asset_amount);
+            let (lower_tick, upper_tick) = if adjusted {
+                (upper_tick.saturating_neg(), lower_tick.saturating_neg())
+            } else {
+                (lower_tick, upper_tick)
+            };
+
+            let mut concentrated_pool = ConcentratedPoolUpdate::<T>::load(first_asset, second_asset)?;
+            let (position_id, first_asset_amount, second_asset_amount) =
+                concentrated_pool.mint(sender.clone(), lower_tick, upper_tick, liquidity)?;
+            ensure!(
+                first_asset_amount <= max_first_asset_amount && second_asset_amount <= max_second_asset_amount,
+                Error::<T>::AssetAmountAboveLimit
+            );
+
+            Self::ensure_sufficient_balances(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_assets(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount);
+
+            concentrated_pool.commit();
+
+            Self::deposit_event(RawEvent::PositionMinted(sender, first_asset, second_asset, position_id, lower_tick, upper_tick, liquidity));
+            Ok(())
+        }
+
+        /// Withdraw `liquidity` of the concentrated pool position, receiving at least `min_first_asset_received`
+        /// and `min_second_asset_received`. Earned fees are withdrawn by `collect_fees`.
+        #[weight = 10_000]
+        pub fn burn_concentrated_position(
+            origin,
+            first_asset: Asset<T::AssetId>,
+            second_asset: Asset<T::AssetId>,
+            position_id: PositionId,
+            liquidity: BalanceOf<T>,
+            min_first_asset_received: BalanceOf<T>,
+            min_second_asset_received: BalanceOf<T>
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (first_asset, min_first_asset_received, second_asset, min_second_asset_received) =
+                Self::adjust_assets_amount_order(first_asset, min_first_asset_received, second_asset, min_second_asset_received);
+
+            let mut concentrated_pool = ConcentratedPoolUpdate::<T>::load(first_asset, second_asset)?;
+            let (first_asset_amount, second_asset_amount) = concentrated_pool.burn(&sender, position_id, liquidity)?;
+            Self::ensure_divest_expectations(first_asset_amount, second_asset_amount, min_first_asset_received, min_second_asset_received)?;
+
+            Self::ensure_can_hold_balances(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_assets(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount);
+
+            concentrated_pool.commit();
+
+            Self::deposit_event(RawEvent::PositionBurned(sender, first_asset, second_asset, position_id, liquidity));
+            Ok(())
+        }
+
+        /// Withdraw fees, earned by the concentrated pool position. Position without liquidity is closed.
+        #[weight = 10_000]
+        pub fn collect_fees(origin, first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>, position_id: PositionId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+
+            let mut concentrated_pool = ConcentratedPoolUpdate::<T>::load(first_asset, second_asset)?;
+            let (first_asset_fees, second_asset_fees) = concentrated_pool.collect_fees(&sender, position_id)?;
+
+            Self::ensure_can_hold_balances(&sender, first_asset, first_asset_fees, second_asset, second_asset_fees)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_assets(&sender, first_asset, first_asset_fees, second_asset, second_asset_fees);
+
+            concentrated_pool.commit();
+
+            Self::deposit_event(RawEvent::FeesCollected(sender, first_asset, second_asset, position_id, first_asset_fees, second_asset_fees));
+            Ok(())
+        }
+
+        /// Swap `asset_in_amount` of `asset_in` for at least `min_asset_out_amount` of `asset_out` in the concentrated pool.
+        /// Weight of the crossed ticks, below `T::MaxTicksCrossed`, is refunded.
+        #[weight = <Module<T>>::concentrated_swap_weight(T::MaxTicksCrossed::get())]
+        pub fn swap_concentrated(
+            origin,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>
+        ) -> dispatch::DispatchResultWithPostInfo {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_valid_exchange(asset_in, asset_out)?;
+            let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset_in, asset_out);
+
+            let mut concentrated_pool = ConcentratedPoolUpdate::<T>::load(first_asset, second_asset)?;
+
+            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+
+            let (asset_out_amount, treasury_fee_data) = concentrated_pool.swap(!adjusted, asset_in_amount)?;
+            ensure!(asset_out_amount >= min_asset_out_amount, Error::<T>::AssetAmountBelowExpectation);
+
+            Self::ensure_can_hold_balance(&sender, asset_out, asset_out_amount)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, asset_in_amount);
+
+            Self::mint_asset(&sender, asset_out, asset_out_amount);
+
+            let treasury_fee = Self::charge_treasury_fee(asset_in, treasury_fee_data);
+
+            let ticks_crossed = concentrated_pool.ticks_crossed();
+            concentrated_pool.commit();
+
+            Self::deposit_event(RawEvent::ConcentratedExchanged(sender, asset_in, asset_in_amount, asset_out, asset_out_amount, treasury_fee));
+            Ok(Some(Self::concentrated_swap_weight(ticks_crossed)).into())
+        }
+
+        /// Escrow `asset_in_amount` of `asset_in` and `bounty` to be swapped for at least `min_asset_out_amount`
+        /// of `asset_out`. Order can be filled by keepers until the `expiry` block (inclusive).
+        #[weight = 10_000]
+        pub fn place_limit_order(
+            origin,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>,
+            bounty: BalanceOf<T>,
+            expiry: T::BlockNumber
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_valid_exchange(asset_in, asset_out)?;
+            let limit_order = LimitOrder::<T>::new(sender.clone(), asset_in, asset_in_amount, asset_out, min_asset_out_amount, bounty, expiry)?;
+            let escrowed_amount = limit_order.escrowed_amount()?;
+            Self::ensure_sufficient_balance(&sender, asset_in, escrowed_amount)?;
+
+            let order_id = Self::next_limit_order_id();
+            let next_order_id = order_id.checked_add(1).ok_or(Error::<T>::OverflowOccured)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, escrowed_amount);
+
+            <LimitOrders<T>>::insert(order_id, limit_order);
+            NextLimitOrderId::put(next_order_id);
+
+            Self::deposit_event(RawEvent::LimitOrderPlaced(order_id, sender, asset_in, asset_in_amount, asset_out, min_asset_out_amount, expiry));
+            Ok(())
+        }
+
+        /// Fill `asset_in_amount` of the limit order against the exchange pool at the limit price or better.
+        /// Anyone can fill the order, receiving the bounty share.
+        #[weight = 10_000]
+        pub fn fill_limit_order(origin, order_id: LimitOrderId, asset_in_amount: BalanceOf<T>) -> dispatch::DispatchResult {
+            let keeper = ensure_signed(origin)?;
+
+            let mut limit_order = Self::limit_orders(order_id).ok_or(Error::<T>::LimitOrderNotExists)?;
+            let (min_asset_out_amount, bounty) = limit_order.fill(asset_in_amount)?;
+
+            // Swap is paid from the escrow
+            let prepared_swap = Self::prepare_escrowed_swap(
+                &limit_order.owner,
+                limit_order.asset_in,
+                asset_in_amount,
+                limit_order.asset_out,
+                min_asset_out_amount,
+            )?;
+            Self::ensure_can_hold_balance(&keeper, limit_order.asset_in, bounty)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            let asset_out_amount = Self::apply_escrowed_swap(&limit_order.owner, prepared_swap);
+            Self::mint_asset(&keeper, limit_order.asset_in, bounty);
+
+            if limit_order.is_filled() {
+                <LimitOrders<T>>::remove(order_id);
+            } else {
+                <LimitOrders<T>>::insert(order_id, limit_order);
+            }
+
+            Self::deposit_event(RawEvent::LimitOrderFilled(order_id, keeper, asset_in_amount, asset_out_amount, bounty));
+            Ok(())
+        }
+
+        /// Cancel the limit order, refunding the unfilled remainder to the owner.
+        /// Expired orders can be cancelled by anyone.
+        #[weight = 10_000]
+        pub fn cancel_limit_order(origin, order_id: LimitOrderId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let limit_order = Self::limit_orders(order_id).ok_or(Error::<T>::LimitOrderNotExists)?;
+            ensure!(
+                limit_order.owner == sender || limit_order.is_expired(),
+                Error::<T>::NotLimitOrderOwner
+            );
+
+            let refund = limit_order.escrowed_amount()?;
+            Self::ensure_can_hold_balance(&limit_order.owner, limit_order.asset_in, refund)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_asset(&limit_order.owner, limit_order.asset_in, refund);
+
+            <LimitOrders<T>>::remove(order_id);
+
+            Self::deposit_event(RawEvent::LimitOrderCancelled(order_id, refund));
+            Ok(())
+        }
+
+        /// Escrow `executions` times `asset_in_amount` of `asset_in`, swapped for at least `min_asset_out_amount`
+        /// of `asset_out` every `interval` blocks, starting from the next block.
+        #[weight = 10_000]
+        pub fn create_dca_order(
+            origin,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>,
+            interval: T::BlockNumber,
+            executions: u32
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_valid_exchange(asset_in, asset_out)?;
+            let deposit = T::ScheduledOrderDeposit::get();
+            let dca_order = DcaOrder::<T>::new(sender.clone(), asset_in, asset_in_amount, asset_out, min_asset_out_amount, interval, executions, deposit)?;
+            let escrowed_amount = dca_order.escrowed_amount()?;
+            Self::ensure_sufficient_balance_with_deposit(&sender, asset_in, escrowed_amount, deposit)?;
+
+            let order_id = Self::next_dca_order_id();
+            let next_order_id = order_id.checked_add(1).ok_or(Error::<T>::OverflowOccured)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, escrowed_amount);
+            Self::slash_asset(&sender, Asset::MainNetworkCurrency, deposit);
+
+            <DcaOrders<T>>::insert(order_id, dca_order);
+            NextDcaOrderId::put(next_order_id);
+            Self::schedule_order(<system::Module<T>>::block_number() + One::one(), ScheduledOrder::Dca(order_id));
+
+            Self::deposit_event(RawEvent::DcaOrderCreated(order_id, sender, asset_in, asset_in_amount, asset_out, interval, executions));
+            Ok(())
+        }
+
+        /// Cancel the recurring order, refunding amounts of the remaining executions.
+        #[weight = 10_000]
+        pub fn cancel_dca_order(origin, order_id: DcaOrderId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let dca_order = Self::dca_orders(order_id).ok_or(Error::<T>::DcaOrderNotExists)?;
+            ensure!(dca_order.owner == sender, Error::<T>::NotDcaOrderOwner);
+
+            let refund = dca_order.escrowed_amount()?;
+            Self::ensure_can_hold_balances(&sender, dca_order.asset_in, refund, Asset::MainNetworkCurrency, dca_order.deposit)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_assets(&sender, dca_order.asset_in, refund, Asset::MainNetworkCurrency, dca_order.deposit);
+
+            // Scheduled execution is skipped
+            <DcaOrders<T>>::remove(order_id);
+
+            Self::deposit_event(RawEvent::DcaOrderCancelled(order_id, refund));
+            Ok(())
+        }
+
+        /// Escrow `asset_in_amount` of `asset_in`, swapped for `asset_out` in equal `slices`, one per block,
+        /// starting from the next block. Average price of the order is kept above `min_asset_out_amount` / `asset_in_amount`.
+        #[weight = 10_000]
+        pub fn create_twap_order(
+            origin,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>,
+            slices: u32
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            Self::ensure_valid_exchange(asset_in, asset_out)?;
+            let deposit = T::ScheduledOrderDeposit::get();
+            let twap_order = TwapOrder::<T>::new(sender.clone(), asset_in, asset_in_amount, asset_out, min_asset_out_amount, slices, deposit)?;
+            Self::ensure_sufficient_balance_with_deposit(&sender, asset_in, asset_in_amount, deposit)?;
+
+            let order_id = Self::next_twap_order_id();
+            let next_order_id = order_id.checked_add(1).ok_or(Error::<T>::OverflowOccured)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, asset_in_amount);
+            Self::slash_asset(&sender, Asset::MainNetworkCurrency, deposit);
+
+            <TwapOrders<T>>::insert(order_id, twap_order);
+            NextTwapOrderId::put(next_order_id);
+            Self::schedule_order(<system::Module<T>>::block_number() + One::one(), ScheduledOrder::Twap(order_id));
+
+            Self::deposit_event(RawEvent::TwapOrderCreated(order_id, sender, asset_in, asset_in_amount, asset_out, min_asset_out_amount, slices));
+            Ok(())
+        }
+
+        /// Cancel the time-sliced order, refunding the unswapped remainder.
+        #[weight = 10_000]
+        pub fn cancel_twap_order(origin, order_id: TwapOrderId) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let twap_order = Self::twap_orders(order_id).ok_or(Error::<T>::TwapOrderNotExists)?;
+            ensure!(twap_order.owner == sender, Error::<T>::NotTwapOrderOwner);
+
+            let refund = twap_order.remaining_amount;
+            Self::ensure_can_hold_balances(&sender, twap_order.asset_in, refund, Asset::MainNetworkCurrency, twap_order.deposit)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::mint_assets(&sender, twap_order.asset_in, refund, Asset::MainNetworkCurrency, twap_order.deposit);
+
+            // Scheduled slice is skipped
+            <TwapOrders<T>>::remove(order_id);
+
+            Self::deposit_event(RawEvent::TwapOrderCancelled(order_id, refund));
+            Ok(())
+        }
+
+        /// Escrow `asset_in_amount` of `asset_in`, swapped for at least `min_asset_out_amount` of `asset_out`,
+        /// once the average price of `asset_in` in `asset_out` crosses the `trigger_price` in the `condition` direction.
+        /// Prices are scaled by `PRICE_ONE`.
+        #[weight = 10_000]
+        #[allow(clippy::too_many_arguments)]
+        pub fn place_conditional_order(
+            origin,
+            condition: TriggerCondition,
+            asset_in: Asset<T::AssetId>,
+            asset_in_amount: BalanceOf<T>,
+            asset_out: Asset<T::AssetId>,
+            min_asset_out_amount: BalanceOf<T>,
+            trigger_price: BalanceOf<T>,
+            expiry: T::BlockNumber
+        ) -> dispatch::DispatchResult {
+            let sender = ensure_signed(origin)?;
+
+            let mut conditional_order = ConditionalOrder::<T>::new(
+                sender.clone(), condition, asset_in, asset_in_amount, asset_out, min_asset_out_amount, trigger_price, expiry
+            )?;
+            let (price_cumulative, timestamp) = Self::current_price_cumulative(asset_in, asset_out)?;
+            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+
+            let order_id = Self::next_conditional_order_id();
+            let next_order_id = order_id.checked_add(1).ok_or(Error::<T>::OverflowOccured)?;
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            Self::slash_asset(&sender, asset_in, asset_in_amount);
+
+            conditional_order.observe(price_cumulative, timestamp);
+            <ConditionalOrders<T>>::insert(order_id, conditional_order);
+            NextConditionalOrderId::put(next_order_id);
+
+            Self::deposit_event(RawEvent::ConditionalOrderPlaced(order_id, sender, condition, asset_in, asset_in_amount, asset_out, trigger_price, expiry));
+            Ok(())
+        }
+
+        /// Check the conditional order against the average price since its last observation,
+        /// once the averaging period has elapsed. Triggered order is swapped through the exchange,
+        /// otherwise (or if the window exceeds the maximum) the new averaging window is started. Anyone can check the order.
+        #[weight = 10_000]
+        pub fn trigger_conditional_order(origin, order_id: ConditionalOrderId) -> dispatch::DispatchResult {
+            ensure_signed(origin)?;
+
+            let mut conditional_order = Self::conditional_orders(order_id).ok_or(Error::<T>::ConditionalOrderNotExists)?;
+            ensure!(!conditional_order.is_expired(), Error::<T>::ConditionalOrderExpired);
+
+            let (price_cumulative, timestamp) = Self::current_price_cumulative(conditional_order.asset_in, conditional_order.asset_out)?;
+            let average_price = conditional_order.average_price(
+                price_cumulative, timestamp, T::TwapPeriod::get().into(), T::MaxTwapWindow::get().into()
+            )?;
+
+            // Swap is paid from the escrow
+            let prepared_swap = match average_price {
+                Some(average_price) if conditional_order.is_triggered(average_price) => {
+                    Some((average_price, Self::prepare_escrowed_swap(
+                        &conditional_order.owner,
+                        conditional_order.asset_in,
+                        conditional_order.asset_in_amount,
+                        conditional_order.asset_out,
+                        conditional_order.min_asset_out_amount,
+                    )?))
+                }
+                _ => None,
+            };
+
+            //
+            // == MUTATION SAFE ==
+            //
+
+            if let Some((average_price, prepared_swap)) = prepared_swap {
+                let asset_out_amount = Self::apply_escrowed_swap(&conditional_order.owner, prepared_swap);
+                <ConditionalOrders<T>>::remove(order_id);
+
+                Self::deposit_event(RawEvent::ConditionalOrderTriggered(order_id, average_price, conditional_order.asset_in_amount, asset_out_amount));
+            } else {
+                conditional_order.observe(price_cumulative, timestamp);
+                <ConditionalOrders<T>>::insert(order_id, conditional_order);
 
-        fn deposit_event() = default;
+                match average_price {
+                    Some(average_price) => Self::deposit_event(RawEvent::ConditionalOrderObserved(order_id, average_price)),
+                    None => Self::deposit_event(RawEvent::ConditionalOrderWindowRestarted(order_id)),
+                }
+            }
+            Ok(())
+        }
 
+        /// Cancel the conditional order, refunding the escrow to the owner.
+        /// Expired orders can be cancelled by anyone.
         #[weight = 10_000]
-        pub fn initialize_exchange(origin, first_asset: Asset<T::AssetId>, first_asset_amount: BalanceOf<T>, second_asset: Asset<T::AssetId>, second_asset_amount: BalanceOf<T>) -> dispatch::DispatchResult {
+        pub fn cancel_conditional_order(origin, order_id: ConditionalOrderId) -> dispatch::DispatchResult {
             let sender = ensure_signed(origin)?;
 
-            let (first_asset, first_asset_amount, second_asset, second_asset_amount) =
-                Self::adjust_assets_amount_order(first_asset, first_asset_amount, second_asset, second_asset_amount);
-
-            // TODO adjust first and second min asset amounts
-            ensure!(
-                first_asset_amount > BalanceOf::<T>::zero(),
-                Error::<T>::LowFirstAssetAmount
-            );
+            let conditional_order = Self::conditional_orders(order_id).ok_or(Error::<T>::ConditionalOrderNotExists)?;
             ensure!(
-                second_asset_amount > BalanceOf::<T>::zero(),
-                Error::<T>::LowSecondAssetAmount
+                conditional_order.owner == sender || conditional_order.is_expired(),
+                Error::<T>::NotConditionalOrderOwner
             );
 
-            Self::ensure_exchange_not_exists(first_asset, second_asset)?;
-            Self::exchanges(first_asset, second_asset).ensure_launch()?;
-            Self::ensure_sufficient_balances(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount)?;
-
-            // TODO adjust shares allocation
-            let (exchange, initial_shares) = Exchange::<T>::initialize_new(first_asset_amount, second_asset_amount, sender.clone())?;
+            let refund = conditional_order.asset_in_amount;
+            Self::ensure_can_hold_balance(&conditional_order.owner, conditional_order.asset_in, refund)?;
 
             //
             // == MUTATION SAFE ==
             //
 
-            Self::slash_assets(&sender, first_asset, first_asset_amount, second_asset, second_asset_amount);
+            Self::mint_asset(&conditional_order.owner, conditional_order.asset_in, refund);
 
-            Exchanges::<T>::insert(first_asset, second_asset, exchange);
+            <ConditionalOrders<T>>::remove(order_id);
 
-            Self::deposit_event(RawEvent::Invested(sender, first_asset, second_asset, initial_shares));
+            Self::deposit_event(RawEvent::ConditionalOrderCancelled(order_id, refund));
             Ok(())
         }
 
-        #[weight = 10_000]
-        pub fn swap_to_exact(
+        /// Lend `asset_amount` of `asset` from the constant product exchange with `paired_asset` and dispatch `call`,
+        /// while holding it. Borrowed amount is repaid in the same asset with the swap fee afterwards, split between
+        /// the pool and the treasury, so the pool invariant only grows. Exchange is locked until the repayment.
+        /// If the call or the repayment fails, everything is reverted.
+        #[weight = 10_000 + call.get_dispatch_info().weight]
+        pub fn flash_swap(
             origin,
-            asset_in: Asset<T::AssetId>,
-            asset_in_amount: BalanceOf<T>,
-            asset_out: Asset<T::AssetId>,
-            min_asset_out_amount: BalanceOf<T>,
-            receiver: T::AccountId
+            asset: Asset<T::AssetId>,
+            asset_amount: BalanceOf<T>,
+            paired_asset: Asset<T::AssetId>,
+            call: Box<<T as Trait>::Call>
         ) -> dispatch::DispatchResult {
             let sender = ensure_signed(origin)?;
 
-            Self::ensure_valid_exchange(asset_in, asset_out)?;
+            Self::ensure_valid_exchange(asset, paired_asset)?;
+            let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset, paired_asset);
 
-            let (adjusted_first_asset_id, adjusted_second_asset_id, adjsuted) = Self::adjust_assets_order(asset_in, asset_out);
+            let exchange = match Self::ensure_exchange_exists(first_asset, second_asset)? {
+                Pool::ConstantProduct(exchange) => exchange,
+                Pool::Stable(_) => return Err(Error::<T>::FlashSwapNotSupported.into()),
+            };
+            ensure!(asset_amount > BalanceOf::<T>::zero(), Error::<T>::LowAssetAmount);
+            let asset_pool = if !adjusted {
+                exchange.first_asset_pool()
+            } else {
+                exchange.second_asset_pool()
+            };
+            ensure!(asset_amount < asset_pool, Error::<T>::InsufficientPool);
+            Self::ensure_can_hold_balance(&sender, asset, asset_amount)?;
 
-            let mut exchange = Self::ensure_exchange_exists(adjusted_first_asset_id, adjusted_second_asset_id)?;
+            //
+            // == MUTATION SAFE ==
+            //
 
-            Self::ensure_sufficient_balance(&sender, asset_in, asset_in_amount)?;
+            // Changes of the call are reverted together with the flash swap
+            with_transaction(|| {
+                match Self::execute_flash_swap(&sender, asset, asset_amount, paired_asset, *call) {
+                    Ok(()) => TransactionOutcome::Commit(Ok(())),
+                    Err(error) => TransactionOutcome::Rollback(Err(error)),
+                }
+            })
+        }
+    }
+}
 
-            let (asset_swap_delta, treasury_fee_data) = if !adjsuted {
-                let (first_to_second_asset_swap_delta, treasury_fee_data) =
-                    exchange.calculate_first_to_second_asset_swap(asset_in_amount)?;
+impl<T: Trait> Module<T> {
+    /// Migrate the storage of the version V1: exchanges drop the stored invariant and scale cumulative prices.
+    /// Storage items, introduced since V1, start empty on the upgraded chain.
+    pub fn migrate_to_v2() -> Weight {
+        if Self::storage_version() != Releases::V1 {
+            return 0;
+        }
 
-                    exchange.ensure_second_asset_amount(first_to_second_asset_swap_delta.amount, min_asset_out_amount)?;
+        <Exchanges<T>>::translate(|_, _, exchange: ExchangeV1<T>| Some(exchange.into()));
+        StorageVersion::put(Releases::V2);
+        T::MaximumBlockWeight::get()
+    }
 
-                    Self::ensure_can_hold_balance(&sender, asset_out, first_to_second_asset_swap_delta.amount)?;
+    /// Swap `asset_in_amount` of `asset_in` to at least `min_asset_out_amount` of `asset_out`.
+    /// Returns received `asset_out` amount.
+    pub fn swap(
+        sender: &T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, dispatch::DispatchError> {
+        let prepared_swap = Self::prepare_swap(
+            sender,
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            min_asset_out_amount,
+        )?;
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        Ok(Self::apply_swap(sender, prepared_swap))
+    }
 
-                    (first_to_second_asset_swap_delta, treasury_fee_data)
-            } else {
-                let (second_to_first_asset_swap_delta, treasury_fee_data) =
-                    exchange.calculate_second_to_first_asset_swap(asset_in_amount)?;
+    /// Amount of `asset_out`, received for `asset_in_amount` of `asset_in` in the current pool state.
+    pub fn quote(
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+    ) -> Result<BalanceOf<T>, Error<T>> {
+        Self::ensure_valid_exchange(asset_in, asset_out)?;
 
-                    exchange.ensure_first_asset_amount(second_to_first_asset_swap_delta.amount, min_asset_out_amount)?;
+        let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset_in, asset_out);
 
-                    Self::ensure_can_hold_balance(&sender, asset_out, second_to_first_asset_swap_delta.amount)?;
+        let pool = Self::ensure_exchange_exists(first_asset, second_asset)?;
 
-                    (second_to_first_asset_swap_delta, treasury_fee_data)
-            };
+        let (asset_swap_delta, _) = if !adjusted {
+            pool.calculate_first_to_second_asset_swap(asset_in_amount)?
+        } else {
+            pool.calculate_second_to_first_asset_swap(asset_in_amount)?
+        };
+        Ok(asset_swap_delta.amount)
+    }
 
-            // Update exchange pools
-            exchange.update_pools(asset_swap_delta.first_asset_pool, asset_swap_delta.second_asset_pool)?;
+    /// Perform all swap checks and calculations without mutating the storage.
+    pub fn prepare_swap(
+        sender: &T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+    ) -> Result<PreparedSwap<T>, dispatch::DispatchError> {
+        Self::ensure_sufficient_balance(sender, asset_in, asset_in_amount)?;
 
-            //
-            // == MUTATION SAFE ==
-            //
+        Self::prepare_escrowed_swap(
+            sender,
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            min_asset_out_amount,
+        )
+    }
 
-            // Perform exchange
-            Self::slash_asset(&sender, asset_in, asset_in_amount);
+    /// Perform all checks and calculations of the swap, paid from the escrow, without mutating the storage.
+    /// `asset_out` is received by `sender`.
+    pub fn prepare_escrowed_swap(
+        sender: &T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+    ) -> Result<PreparedSwap<T>, dispatch::DispatchError> {
+        Self::ensure_valid_exchange(asset_in, asset_out)?;
 
-            Self::mint_asset(&sender, asset_out, asset_swap_delta.amount);
+        let (adjusted_first_asset_id, adjusted_second_asset_id, adjsuted) =
+            Self::adjust_assets_order(asset_in, asset_out);
 
-            // Charge treasury fee
-            let treasury_fee = if let Some((treasury_fee, dex_account_id)) = treasury_fee_data {
-                Self::mint_asset(&dex_account_id, asset_in, treasury_fee);
-                Some(treasury_fee)
-            } else {
-                None
-            };
+        let mut pool =
+            Self::ensure_exchange_exists(adjusted_first_asset_id, adjusted_second_asset_id)?;
 
-            // Update runtime exchange storage state
-            <Exchanges<T>>::insert(adjusted_first_asset_id, adjusted_second_asset_id, exchange);
+        let (asset_swap_delta, treasury_fee_data) = if !adjsuted {
+            let (first_to_second_asset_swap_delta, treasury_fee_data) =
+                pool.calculate_first_to_second_asset_swap(asset_in_amount)?;
+
+            pool.liquidity().ensure_second_asset_amount(
+                first_to_second_asset_swap_delta.amount,
+                min_asset_out_amount,
+            )?;
 
-            Self::deposit_event(RawEvent::Exchanged(
+            Self::ensure_can_hold_balance(
                 sender,
-                asset_in,
-                asset_in_amount,
                 asset_out,
-                asset_swap_delta.amount,
-                treasury_fee
-            ));
-            Ok(())
-        }
+                first_to_second_asset_swap_delta.amount,
+            )?;
 
-        #[weight = 10_000]
-        pub fn invest_liquidity(origin, first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>, shares: BalanceOf<T>) -> dispatch::DispatchResult {
-            let sender = ensure_signed(origin)?;
+            (first_to_second_asset_swap_delta, treasury_fee_data)
+        } else {
+            let (second_to_first_asset_swap_delta, treasury_fee_data) =
+                pool.calculate_second_to_first_asset_swap(asset_in_amount)?;
 
-            let (first_asset, second_asset, _) =
-                Self::adjust_assets_order(first_asset, second_asset);
+            pool.liquidity().ensure_first_asset_amount(
+                second_to_first_asset_swap_delta.amount,
+                min_asset_out_amount,
+            )?;
 
-            let mut exchange = Self::ensure_exchange_exists(first_asset, second_asset)?;
-            let (first_asset_cost, second_asset_cost) = exchange.calculate_costs(shares)?;
+            Self::ensure_can_hold_balance(
+                sender,
+                asset_out,
+                second_to_first_asset_swap_delta.amount,
+            )?;
+
+            (second_to_first_asset_swap_delta, treasury_fee_data)
+        };
+
+        // Update exchange pools
+        pool.liquidity_mut().update_pools(
+            asset_swap_delta.first_asset_pool,
+            asset_swap_delta.second_asset_pool,
+        )?;
+
+        Ok(PreparedSwap {
+            first_asset: adjusted_first_asset_id,
+            second_asset: adjusted_second_asset_id,
+            pool,
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            asset_out_amount: asset_swap_delta.amount,
+            treasury_fee_data,
+        })
+    }
 
-            Self::ensure_sufficient_balances(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost)?;
+    /// Apply swap, previously checked by `prepare_swap`. Returns received `asset_out` amount.
+    pub fn apply_swap(sender: &T::AccountId, prepared_swap: PreparedSwap<T>) -> BalanceOf<T> {
+        Self::slash_asset(
+            sender,
+            prepared_swap.asset_in,
+            prepared_swap.asset_in_amount,
+        );
 
-            // Invest funds into exchange
-            exchange.invest(first_asset_cost, second_asset_cost, shares, &sender)?;
+        Self::apply_escrowed_swap(sender, prepared_swap)
+    }
 
-            //
-            // == MUTATION SAFE ==
-            //
+    /// Apply swap, previously checked by `prepare_escrowed_swap`. Returns received `asset_out` amount.
+    pub fn apply_escrowed_swap(
+        sender: &T::AccountId,
+        prepared_swap: PreparedSwap<T>,
+    ) -> BalanceOf<T> {
+        let PreparedSwap {
+            first_asset,
+            second_asset,
+            pool,
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            asset_out_amount,
+            treasury_fee_data,
+        } = prepared_swap;
+
+        // Perform exchange
+        Self::mint_asset(sender, asset_out, asset_out_amount);
+
+        let treasury_fee = Self::charge_treasury_fee(asset_in, treasury_fee_data);
+
+        // Update runtime exchange storage state
+        Self::insert_pool(first_asset, second_asset, pool);
+
+        Self::deposit_event(RawEvent::Exchanged(
+            sender.clone(),
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            asset_out_amount,
+            treasury_fee,
+        ));
+        asset_out_amount
+    }
+
+    /// Apply weighted pool swap, previously checked by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_weighted_swap(
+        sender: &T::AccountId,
+        pool_id: WeightedPoolId,
+        weighted_pool: WeightedPool<T>,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        asset_out_amount: BalanceOf<T>,
+        treasury_fee_data: Option<(BalanceOf<T>, T::AccountId)>,
+    ) {
+        Self::slash_asset(sender, asset_in, asset_in_amount);
 
-            // Slash user assets
-            Self::slash_assets(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost);
+        Self::mint_asset(sender, asset_out, asset_out_amount);
 
-            // Update runtime exchange storage state
-            <Exchanges<T>>::insert(first_asset, second_asset, exchange);
+        let treasury_fee = Self::charge_treasury_fee(asset_in, treasury_fee_data);
 
-            Self::deposit_event(RawEvent::Invested(sender, first_asset, second_asset, shares));
-            Ok(())
+        Self::insert_weighted_pool(pool_id, weighted_pool);
+
+        Self::deposit_event(RawEvent::WeightedExchanged(
+            pool_id,
+            sender.clone(),
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            asset_out_amount,
+            treasury_fee,
+        ));
+    }
+
+    /// Weight of a single scheduled order execution.
+    pub fn scheduled_execution_weight() -> Weight {
+        // Reads: queue length and entry, order, stable and constant product exchanges, exchange lock,
+        // timestamp, treasury account, treasury and owner balances, owner deposit balance, next queue length.
+        // Writes: queue entry, order, exchange, treasury and owner balances, owner deposit balance,
+        // next queue length and entry, event.
+        T::DbWeight::get()
+            .reads_writes(12, 9)
+            .saturating_add(SCHEDULED_EXECUTION_WEIGHT)
+    }
+
+    /// Execute orders, due by `now`, in the scheduled order, while the weight budget allows.
+    /// Orders beyond the budget are deferred, the cursor stays at the first of them.
+    /// Returns the consumed weight.
+    pub fn execute_scheduled_orders(now: T::BlockNumber) -> Weight {
+        let budget = T::MaxScheduledWeight::get();
+        let execution_weight = Self::scheduled_execution_weight();
+        // Queue length is read and removed
+        let drain_weight = T::DbWeight::get().reads_writes(1, 1);
+        let (mut block_number, mut position) = Self::scheduled_orders_cursor().unwrap_or((now, 0));
+
+        // Cursor is read and written back
+        let mut consumed_weight = T::DbWeight::get().reads_writes(1, 1);
+        // Every step either executes the order or moves the cursor past the drained block
+        while block_number <= now && consumed_weight.saturating_add(execution_weight) <= budget {
+            if position < Self::scheduled_orders_count(block_number) {
+                consumed_weight = consumed_weight.saturating_add(execution_weight);
+                match <ScheduledOrders<T>>::take(block_number, position) {
+                    Some(ScheduledOrder::Dca(order_id)) => Self::execute_dca_order(order_id, now),
+                    Some(ScheduledOrder::Twap(order_id)) => Self::execute_twap_order(order_id, now),
+                    None => (),
+                }
+                position += 1;
+            } else {
+                consumed_weight = consumed_weight.saturating_add(drain_weight);
+                <ScheduledOrdersCount<T>>::remove(block_number);
+                block_number += One::one();
+                position = 0;
+            }
         }
 
-        #[weight = 10_000]
-        pub fn divest_liquidity(
-            origin,
-            first_asset: Asset<T::AssetId>,
-            second_asset: Asset<T::AssetId>,
-            shares_burned:  BalanceOf<T>,
-            min_first_asset_received: BalanceOf<T>,
-            min_second_asset_received: BalanceOf<T>
-        ) -> dispatch::DispatchResult {
-            let sender = ensure_signed(origin)?;
+        <ScheduledOrdersCursor<T>>::put((block_number, position));
+        consumed_weight
+    }
 
-            let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+    pub fn schedule_order(block_number: T::BlockNumber, scheduled_order: ScheduledOrder) {
+        let position = Self::scheduled_orders_count(block_number);
+        <ScheduledOrders<T>>::insert(block_number, position, scheduled_order);
+        <ScheduledOrdersCount<T>>::insert(block_number, position.saturating_add(1));
+    }
 
-            let mut exchange = Self::ensure_exchange_exists(first_asset, second_asset)?;
-            exchange.ensure_burned_shares(&sender, shares_burned)?;
+    /// Lend the pool to `sender`, dispatch the `call` and collect the repayment of the same asset with the fee.
+    /// Storage changes are not reverted on failure, so it should be executed within the transaction.
+    fn execute_flash_swap(
+        sender: &T::AccountId,
+        asset: Asset<T::AssetId>,
+        asset_amount: BalanceOf<T>,
+        paired_asset: Asset<T::AssetId>,
+        call: <T as Trait>::Call,
+    ) -> dispatch::DispatchResult {
+        let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset, paired_asset);
+        // Amounts of the ordered assets pair
+        let amounts = |asset_amount: BalanceOf<T>| {
+            if !adjusted {
+                (asset_amount, BalanceOf::<T>::zero())
+            } else {
+                (BalanceOf::<T>::zero(), asset_amount)
+            }
+        };
+
+        // Lend
+        let mut exchange = Self::exchanges(first_asset, second_asset);
+        let (first_asset_amount, second_asset_amount) = amounts(asset_amount);
+        exchange.lend(first_asset_amount, second_asset_amount)?;
+        <Exchanges<T>>::insert(first_asset, second_asset, exchange);
+        <LockedExchanges<T>>::insert(first_asset, second_asset, true);
+        Self::mint_asset(sender, asset, asset_amount);
+
+        let call_result = call.dispatch(system::RawOrigin::Signed(sender.clone()).into());
+        <LockedExchanges<T>>::remove(first_asset, second_asset);
+        call_result.map_err(|error| error.error)?;
+
+        // Repay with the fee
+        let (exchange_fee, treasury_fee_data) = Self::swap_fees(asset_amount)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+        let returned_amount = asset_amount
+            .checked_add(&exchange_fee)
+            .ok_or(Error::<T>::OverflowOccured)?;
+        let repaid_amount = returned_amount
+            .checked_add(&treasury_fee)
+            .ok_or(Error::<T>::OverflowOccured)?;
+        Self::ensure_sufficient_balance(sender, asset, repaid_amount)?;
+
+        let mut exchange = Self::exchanges(first_asset, second_asset);
+        let (first_asset_amount, second_asset_amount) = amounts(returned_amount);
+        exchange.repay(first_asset_amount, second_asset_amount)?;
+
+        Self::slash_asset(sender, asset, repaid_amount);
+        let treasury_fee = Self::charge_treasury_fee(asset, treasury_fee_data);
+        <Exchanges<T>>::insert(first_asset, second_asset, exchange);
+
+        Self::deposit_event(RawEvent::FlashSwapped(
+            sender.clone(),
+            asset,
+            asset_amount,
+            paired_asset,
+            exchange_fee,
+            treasury_fee,
+        ));
+        Ok(())
+    }
 
-            let (first_asset_cost, second_asset_cost) = exchange.calculate_costs(shares_burned)?;
-            Self::ensure_divest_expectations(first_asset_cost, second_asset_cost, min_first_asset_received, min_second_asset_received)?;
+    /// Swap `asset_in_amount` of `asset_in` from the escrow for at least `min_asset_out_amount` of `asset_out`,
+    /// received by `owner`. If the swap fails the checks (e.g. the slippage bound), the amount is refunded instead.
+    /// Returns the received `asset_out` amount, if swapped. Fails without changes, if `owner` can not hold the refund.
+    pub fn swap_or_refund_escrowed(
+        owner: &T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+    ) -> Result<Option<BalanceOf<T>>, dispatch::DispatchError> {
+        match Self::prepare_escrowed_swap(
+            owner,
+            asset_in,
+            asset_in_amount,
+            asset_out,
+            min_asset_out_amount,
+        ) {
+            Ok(prepared_swap) => Ok(Some(Self::apply_escrowed_swap(owner, prepared_swap))),
+            Err(_) => {
+                Self::ensure_can_hold_balance(owner, asset_in, asset_in_amount)?;
+                Self::mint_asset(owner, asset_in, asset_in_amount);
+                Ok(None)
+            }
+        }
+    }
 
-            // Avoid overflow risks
-            Self::ensure_can_hold_balances(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost)?;
+    /// Execute the order once. Amounts, that the owner can not hold, are kept escrowed by the order:
+    /// the execution is postponed by the interval, and the deposit is returned once it can be credited.
+    fn execute_dca_order(order_id: DcaOrderId, now: T::BlockNumber) {
+        // Order could be cancelled
+        let mut dca_order = match Self::dca_orders(order_id) {
+            Some(dca_order) => dca_order,
+            None => return,
+        };
+
+        if dca_order.remaining_executions > 0 {
+            let event = match Self::swap_or_refund_escrowed(
+                &dca_order.owner,
+                dca_order.asset_in,
+                dca_order.asset_in_amount,
+                dca_order.asset_out,
+                dca_order.min_asset_out_amount,
+            ) {
+                Ok(Some(asset_out_amount)) => RawEvent::DcaOrderExecuted(
+                    order_id,
+                    dca_order.asset_in_amount,
+                    asset_out_amount,
+                    dca_order.remaining_executions - 1,
+                ),
+                Ok(None) => RawEvent::DcaOrderExecutionSkipped(
+                    order_id,
+                    dca_order.asset_in_amount,
+                    dca_order.remaining_executions - 1,
+                ),
+                Err(_) => {
+                    Self::schedule_order(now + dca_order.interval, ScheduledOrder::Dca(order_id));
+                    return;
+                }
+            };
+            dca_order.remaining_executions -= 1;
+            Self::deposit_event(event);
+        }
 
-            // Divest funds from exchange
-            exchange.divest(first_asset_cost, second_asset_cost, shares_burned, &sender)?;
+        if dca_order.remaining_executions == 0
+            && Self::ensure_can_hold_balance(
+                &dca_order.owner,
+                Asset::MainNetworkCurrency,
+                dca_order.deposit,
+            )
+            .is_ok()
+        {
+            Self::mint_asset(
+                &dca_order.owner,
+                Asset::MainNetworkCurrency,
+                dca_order.deposit,
+            );
+            <DcaOrders<T>>::remove(order_id);
+        } else {
+            Self::schedule_order(now + dca_order.interval, ScheduledOrder::Dca(order_id));
+            <DcaOrders<T>>::insert(order_id, dca_order);
+        }
+    }
 
-            //
-            // == MUTATION SAFE ==
-            //
+    /// Execute the next slice of the order. Amounts, that the owner can not hold, are kept escrowed by the order:
+    /// the slice is postponed to the next block, and the deposit is returned once it can be credited.
+    fn execute_twap_order(order_id: TwapOrderId, now: T::BlockNumber) {
+        // Order could be cancelled
+        let mut twap_order = match Self::twap_orders(order_id) {
+            Some(twap_order) => twap_order,
+            None => return,
+        };
+
+        if twap_order.remaining_slices > 0 {
+            let (slice_amount, min_asset_out_amount) = match twap_order.take_slice() {
+                Ok(slice) => slice,
+                Err(_) => return,
+            };
 
-            Self::mint_assets(&sender, first_asset, first_asset_cost, second_asset, second_asset_cost);
+            let event = match Self::swap_or_refund_escrowed(
+                &twap_order.owner,
+                twap_order.asset_in,
+                slice_amount,
+                twap_order.asset_out,
+                min_asset_out_amount,
+            ) {
+                Ok(Some(asset_out_amount)) => {
+                    twap_order.record_slice(slice_amount, asset_out_amount);
+                    RawEvent::TwapSliceExecuted(
+                        order_id,
+                        slice_amount,
+                        asset_out_amount,
+                        twap_order.remaining_slices,
+                    )
+                }
+                Ok(None) => {
+                    RawEvent::TwapSliceSkipped(order_id, slice_amount, twap_order.remaining_slices)
+                }
+                // Stored order still holds the slice
+                Err(_) => {
+                    Self::schedule_order(now + One::one(), ScheduledOrder::Twap(order_id));
+                    return;
+                }
+            };
+            Self::deposit_event(event);
+        }
 
-            // Update runtime exchange storage state
-            <Exchanges<T>>::insert(first_asset, second_asset, exchange);
+        if twap_order.remaining_slices == 0
+            && Self::ensure_can_hold_balance(
+                &twap_order.owner,
+                Asset::MainNetworkCurrency,
+                twap_order.deposit,
+            )
+            .is_ok()
+        {
+            Self::mint_asset(
+                &twap_order.owner,
+                Asset::MainNetworkCurrency,
+                twap_order.deposit,
+            );
+            <TwapOrders<T>>::remove(order_id);
+        } else {
+            Self::schedule_order(now + One::one(), ScheduledOrder::Twap(order_id));
+            <TwapOrders<T>>::insert(order_id, twap_order);
+        }
+    }
 
-            Self::deposit_event(RawEvent::Divested(sender, first_asset, second_asset, shares_burned));
-            Ok(())
+    /// Mint treasury fee (when enabled) to the dex account. Returns the charged fee.
+    pub fn charge_treasury_fee(
+        asset: Asset<T::AssetId>,
+        treasury_fee_data: Option<(BalanceOf<T>, T::AccountId)>,
+    ) -> Option<BalanceOf<T>> {
+        treasury_fee_data.map(|(treasury_fee, dex_account_id)| {
+            Self::mint_asset(&dex_account_id, asset, treasury_fee);
+            treasury_fee
+        })
+    }
+
+    /// Buy `shares` of the exchange between `first_asset` and `second_asset`.
+    pub fn invest(
+        sender: &T::AccountId,
+        first_asset: Asset<T::AssetId>,
+        second_asset: Asset<T::AssetId>,
+        shares: BalanceOf<T>,
+    ) -> dispatch::DispatchResult {
+        let (first_asset, second_asset, _) = Self::adjust_assets_order(first_asset, second_asset);
+
+        let mut pool = Self::ensure_exchange_exists(first_asset, second_asset)?;
+        let (first_asset_cost, second_asset_cost) = pool.liquidity().calculate_costs(shares)?;
+
+        // Shares, which cost nothing, would dilute other investors
+        ensure!(
+            first_asset_cost > BalanceOf::<T>::zero(),
+            Error::<T>::LowFirstAssetAmount
+        );
+        ensure!(
+            second_asset_cost > BalanceOf::<T>::zero(),
+            Error::<T>::LowSecondAssetAmount
+        );
+
+        Self::ensure_sufficient_balances(
+            sender,
+            first_asset,
+            first_asset_cost,
+            second_asset,
+            second_asset_cost,
+        )?;
+
+        // Invest funds into exchange
+        pool.liquidity_mut()
+            .invest(first_asset_cost, second_asset_cost, shares, sender)?;
+
+        //
+        // == MUTATION SAFE ==
+        //
+
+        // Slash user assets
+        Self::slash_assets(
+            sender,
+            first_asset,
+            first_asset_cost,
+            second_asset,
+            second_asset_cost,
+        );
+
+        // Update runtime exchange storage state
+        Self::insert_pool(first_asset, second_asset, pool);
+
+        Self::deposit_event(RawEvent::Invested(
+            sender.clone(),
+            first_asset,
+            second_asset,
+            shares,
+        ));
+        Ok(())
+    }
+
+    /// Split the fee, charged for swapping `asset_in_amount`, into the part, staying in the pool,
+    /// and the treasury fee (when enabled).
+    pub fn swap_fees(
+        asset_in_amount: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        let fee = math::mul_div::<T>(
+            T::FeeRateNominator::get(),
+            asset_in_amount,
+            T::FeeRateDenominator::get(),
+        )?;
+
+        if let Ok(dex_treasury) = <DEXTreasury<T>>::try_get() {
+            let treasury_fee = math::mul_div::<T>(
+                dex_treasury.treasury_fee_rate_nominator,
+                fee,
+                dex_treasury.treasury_fee_rate_denominator,
+            )?;
+            Ok((
+                fee - treasury_fee,
+                Some((treasury_fee, dex_treasury.dex_account)),
+            ))
+        } else {
+            Ok((fee, None))
         }
     }
-}
 
-impl<T: Trait> Module<T> {
+    /// Exchange shares, which cost at most `asset_a_amount` of `asset_a`, and their cost in `asset_b`
+    pub fn shares_for_amount(
+        asset_a: Asset<T::AssetId>,
+        asset_a_amount: BalanceOf<T>,
+        asset_b: Asset<T::AssetId>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset_a, asset_b);
+
+        let pool = Self::ensure_exchange_exists(first_asset, second_asset)?;
+        let exchange = pool.liquidity();
+        let asset_a_pool = if !adjusted {
+            exchange.first_asset_pool()
+        } else {
+            exchange.second_asset_pool()
+        };
+
+        // Rounded down, so that the shares can not cost more, than the given amount
+        let shares = math::mul_div::<T>(asset_a_amount, exchange.total_shares, asset_a_pool)?;
+        let (first_asset_cost, second_asset_cost) = exchange.calculate_costs(shares)?;
+        if !adjusted {
+            Ok((shares, second_asset_cost))
+        } else {
+            Ok((shares, first_asset_cost))
+        }
+    }
+
+    /// Current timestamp, as used by the exchanges
+    pub fn now() -> T::IMoment {
+        <pallet_timestamp::Module<T>>::get().into()
+    }
+
+    /// Price data of the exchange between `asset_a` and `asset_b`.
+    /// TWAP between two observations is (price_cumulative_2 - price_cumulative_1) / (timestamp_2 - timestamp_1),
+    /// scaled by `PRICE_ONE`. Difference should be taken with wrapping subtraction.
+    /// Prices are implied by the constant product pools only, so StableSwap pools are not supported.
+    pub fn price_data(
+        asset_a: Asset<T::AssetId>,
+        asset_b: Asset<T::AssetId>,
+    ) -> Result<PriceData<BalanceOf<T>>, Error<T>> {
+        Self::ensure_valid_exchange(asset_a, asset_b)?;
+
+        let (first_asset, second_asset, adjusted) = Self::adjust_assets_order(asset_a, asset_b);
+
+        // Ratio of the StableSwap pools is not their price, which stays near the peg until a pool is drained
+        let exchange = match Self::ensure_exchange_exists(first_asset, second_asset)? {
+            Pool::ConstantProduct(exchange) => exchange,
+            Pool::Stable(_) => return Err(Error::<T>::StablePriceNotSupported),
+        };
+
+        let price_data = if !adjusted {
+            PriceData {
+                asset_a_pool: exchange.first_asset_pool(),
+                asset_b_pool: exchange.second_asset_pool(),
+                price_a_cumulative: exchange.price1_cumulative_last,
+                price_b_cumulative: exchange.price2_cumulative_last,
+                last_timestamp: exchange.last_timestamp.into(),
+            }
+        } else {
+            PriceData {
+                asset_a_pool: exchange.second_asset_pool(),
+                asset_b_pool: exchange.first_asset_pool(),
+                price_a_cumulative: exchange.price2_cumulative_last,
+                price_b_cumulative: exchange.price1_cumulative_last,
+                last_timestamp: exchange.last_timestamp.into(),
+            }
+        };
+        Ok(price_data)
+    }
+
+    /// Price of `asset_in` in `asset_out`, implied by the exchange pools and scaled by `PRICE_ONE`
+    pub fn spot_price(
+        asset_in: Asset<T::AssetId>,
+        asset_out: Asset<T::AssetId>,
+    ) -> Result<BalanceOf<T>, Error<T>> {
+        let price_data = Self::price_data(asset_in, asset_out)?;
+
+        math::spot_price::<T>(price_data.asset_b_pool, price_data.asset_a_pool)
+    }
+
+    /// Average price between two cumulative price observations, taken `time_elapsed` apart, scaled by `PRICE_ONE`
+    pub fn average_price(
+        price_cumulative_last: BalanceOf<T>,
+        price_cumulative: BalanceOf<T>,
+        time_elapsed: BalanceOf<T>,
+    ) -> Result<BalanceOf<T>, Error<T>> {
+        math::average_price::<T>(price_cumulative_last, price_cumulative, time_elapsed)
+    }
+
+    /// Cumulative price of `asset_in` in `asset_out`, accumulated up to the current timestamp, and the timestamp
+    pub fn current_price_cumulative(
+        asset_in: Asset<T::AssetId>,
+        asset_out: Asset<T::AssetId>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let price_data = Self::price_data(asset_in, asset_out)?;
+
+        let now: T::IMoment = <pallet_timestamp::Module<T>>::get().into();
+        let now: BalanceOf<T> = now.into();
+        let time_elapsed = now
+            .checked_sub(&price_data.last_timestamp)
+            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+
+        // Current prices are held since the last update
+        let price_cumulative = math::accumulate_price::<T>(
+            price_data.price_b_cumulative,
+            price_data.asset_b_pool,
+            price_data.asset_a_pool,
+            time_elapsed,
+        )?;
+        Ok((price_cumulative, now))
+    }
+
     pub fn ensure_valid_exchange(
         asset_in: Asset<T::AssetId>,
         asset_out: Asset<T::AssetId>,
@@ -425,17 +2343,145 @@ impl<T: Trait> Module<T> {
         }
     }
 
+    /// Pool of the ordered assets pair: StableSwap one, if registered, constant product otherwise.
+    pub fn pool(first_asset: Asset<T::AssetId>, second_asset: Asset<T::AssetId>) -> Pool<T> {
+        match Self::stable_exchanges(first_asset, second_asset) {
+            Some(stable_exchange) => Pool::Stable(stable_exchange),
+            None => Pool::ConstantProduct(Self::exchanges(first_asset, second_asset)),
+        }
+    }
+
+    pub fn insert_pool(
+        first_asset: Asset<T::AssetId>,
+        second_asset: Asset<T::AssetId>,
+        pool: Pool<T>,
+    ) {
+        match pool {
+            Pool::ConstantProduct(exchange) => {
+                <Exchanges<T>>::insert(first_asset, second_asset, exchange)
+            }
+            Pool::Stable(stable_exchange) => {
+                <StableExchanges<T>>::insert(first_asset, second_asset, stable_exchange)
+            }
+        }
+    }
+
     pub fn ensure_exchange_exists(
         first_asset: Asset<T::AssetId>,
         second_asset: Asset<T::AssetId>,
-    ) -> Result<Exchange<T>, Error<T>> {
-        let exchange = Self::exchanges(first_asset, second_asset);
+    ) -> Result<Pool<T>, Error<T>> {
+        let pool = Self::pool(first_asset, second_asset);
 
         ensure!(
-            exchange.invariant > BalanceOf::<T>::zero(),
+            pool.liquidity().total_shares > BalanceOf::<T>::zero(),
             Error::<T>::ExchangeNotExists
         );
-        Ok(exchange)
+        // Pools are lent to the flash swap
+        ensure!(
+            !Self::locked_exchanges(first_asset, second_asset),
+            Error::<T>::ExchangeLocked
+        );
+        Ok(pool)
+    }
+
+    pub fn ensure_weighted_pool_exists(
+        pool_id: WeightedPoolId,
+    ) -> Result<WeightedPool<T>, Error<T>> {
+        Self::weighted_pools(pool_id).ok_or(Error::<T>::WeightedPoolNotExists)
+    }
+
+    /// Weighted pool with the current weights. Swaps in the paused liquidity bootstrapping pools are not allowed.
+    pub fn ensure_weighted_pool_tradable(
+        pool_id: WeightedPoolId,
+    ) -> Result<WeightedPool<T>, Error<T>> {
+        let mut weighted_pool = Self::ensure_weighted_pool_exists(pool_id)?;
+        if let Some(liquidity_bootstrapping) = Self::liquidity_bootstrapping_pools(pool_id) {
+            ensure!(
+                !liquidity_bootstrapping.paused,
+                Error::<T>::LiquidityBootstrappingPoolPaused
+            );
+            weighted_pool.set_weights(
+                liquidity_bootstrapping.weights_at(<system::Module<T>>::block_number()),
+            );
+        }
+        Ok(weighted_pool)
+    }
+
+    /// Weighted pool with the current weights. Only owners provide liquidity to the liquidity bootstrapping pools.
+    pub fn ensure_weighted_pool_joinable(
+        pool_id: WeightedPoolId,
+        who: &T::AccountId,
+    ) -> Result<WeightedPool<T>, Error<T>> {
+        let mut weighted_pool = Self::ensure_weighted_pool_exists(pool_id)?;
+        if let Some(liquidity_bootstrapping) = Self::liquidity_bootstrapping_pools(pool_id) {
+            liquidity_bootstrapping.ensure_owner(who)?;
+            weighted_pool.set_weights(
+                liquidity_bootstrapping.weights_at(<system::Module<T>>::block_number()),
+            );
+        }
+        Ok(weighted_pool)
+    }
+
+    /// Weight of the concentrated pool swap, crossing `ticks_crossed` ticks
+    pub fn concentrated_swap_weight(ticks_crossed: u32) -> Weight {
+        // Reads: pool, treasury account, sender balances of both assets, treasury balance.
+        // Writes: pool, sender balances of both assets, treasury balance, event.
+        let swap_weight = T::DbWeight::get()
+            .reads_writes(5, 5)
+            .saturating_add(CONCENTRATED_SWAP_WEIGHT);
+        // Every step reads the bitmap word and the next tick, the crossed tick is written back
+        let tick_crossing_weight = T::DbWeight::get()
+            .reads_writes(2, 1)
+            .saturating_add(TICK_CROSSING_WEIGHT);
+        swap_weight.saturating_add(tick_crossing_weight.saturating_mul(Weight::from(ticks_crossed)))
+    }
+
+    pub fn ensure_liquidity_bootstrapping_pool_owner(
+        pool_id: WeightedPoolId,
+        who: &T::AccountId,
+    ) -> Result<LiquidityBootstrapping<T>, Error<T>> {
+        let liquidity_bootstrapping = Self::liquidity_bootstrapping_pools(pool_id)
+            .ok_or(Error::<T>::LiquidityBootstrappingPoolNotExists)?;
+        liquidity_bootstrapping.ensure_owner(who)?;
+        Ok(liquidity_bootstrapping)
+    }
+
+    /// Check, whether the sender can seed the new weighted pool. Returns id of the pool.
+    pub fn ensure_can_seed_weighted_pool(
+        sender: &T::AccountId,
+        weighted_pool: &WeightedPool<T>,
+    ) -> Result<WeightedPoolId, dispatch::DispatchError> {
+        for weighted_asset in weighted_pool.assets.iter() {
+            Self::ensure_sufficient_balance(sender, weighted_asset.asset, weighted_asset.pool)?;
+        }
+
+        let pool_id = Self::next_weighted_pool_id();
+        pool_id.checked_add(1).ok_or(Error::<T>::OverflowOccured)?;
+        Ok(pool_id)
+    }
+
+    /// Seed the new weighted pool, previously checked by `ensure_can_seed_weighted_pool`.
+    pub fn seed_weighted_pool(
+        sender: &T::AccountId,
+        pool_id: WeightedPoolId,
+        weighted_pool: WeightedPool<T>,
+    ) {
+        for weighted_asset in weighted_pool.assets.iter() {
+            Self::slash_asset(sender, weighted_asset.asset, weighted_asset.pool);
+        }
+
+        <WeightedPools<T>>::insert(pool_id, weighted_pool);
+        NextWeightedPoolId::put(pool_id + 1);
+    }
+
+    // Pools without shares are dropped
+    pub fn insert_weighted_pool(pool_id: WeightedPoolId, weighted_pool: WeightedPool<T>) {
+        if weighted_pool.total_shares == BalanceOf::<T>::zero() {
+            <WeightedPools<T>>::remove(pool_id);
+            <LiquidityBootstrappingPools<T>>::remove(pool_id);
+        } else {
+            <WeightedPools<T>>::insert(pool_id, weighted_pool);
+        }
     }
 
     pub fn adjust_assets_amount_order(
@@ -505,10 +2551,10 @@ impl<T: Trait> Module<T> {
         first_asset: Asset<T::AssetId>,
         second_asset: Asset<T::AssetId>,
     ) -> dispatch::DispatchResult {
-        let first_exchange = Self::exchanges(first_asset, second_asset);
+        let pool = Self::pool(first_asset, second_asset);
 
         ensure!(
-            first_exchange.invariant == BalanceOf::<T>::zero(),
+            pool.liquidity().total_shares == BalanceOf::<T>::zero(),
             Error::<T>::ExchangeAlreadyExists
         );
         Ok(())
@@ -525,6 +2571,30 @@ impl<T: Trait> Module<T> {
         Self::ensure_sufficient_balance(sender, asset_out, asset_out_amount)
     }
 
+    /// Ensure `sender` can escrow `asset_amount` of `asset` together with the main currency `deposit`
+    pub fn ensure_sufficient_balance_with_deposit(
+        sender: &T::AccountId,
+        asset: Asset<T::AssetId>,
+        asset_amount: BalanceOf<T>,
+        deposit: BalanceOf<T>,
+    ) -> dispatch::DispatchResult {
+        match asset {
+            Asset::MainNetworkCurrency => {
+                let amount = asset_amount
+                    .checked_add(&deposit)
+                    .ok_or(Error::<T>::OverflowOccured)?;
+                Self::ensure_sufficient_balance(sender, asset, amount)
+            }
+            Asset::ParachainAsset(_) => Self::ensure_sufficient_balances(
+                sender,
+                asset,
+                asset_amount,
+                Asset::MainNetworkCurrency,
+                deposit,
+            ),
+        }
+    }
+
     pub fn ensure_sufficient_balance(
         from: &T::AccountId,
         asset: Asset<T::AssetId>,
@@ -581,6 +2651,12 @@ impl<T: Trait> Module<T> {
         second_asset: Asset<T::AssetId>,
         second_asset_amount: BalanceOf<T>,
     ) -> dispatch::DispatchResult {
+        if first_asset == second_asset {
+            let amount = first_asset_amount
+                .checked_add(&second_asset_amount)
+                .ok_or(Error::<T>::OverflowOccured)?;
+            return Self::ensure_can_hold_balance(who, first_asset, amount);
+        }
         Self::ensure_can_hold_balance(who, first_asset, first_asset_amount)?;
         Self::ensure_can_hold_balance(who, second_asset, second_asset_amount)
     }
@@ -0,0 +1,97 @@
+use super::*;
+
+/// Order to swap escrowed `asset_in_amount` of `asset_in` for at least `min_asset_out_amount` of `asset_out`.
+/// It is filled (fully or partially) against the exchange pool by keepers, once the pool price reaches
+/// the limit one. Keepers are paid the `bounty`, pro rata to the filled amount.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct LimitOrder<T: Trait> {
+    pub owner: T::AccountId,
+    pub asset_in: Asset<T::AssetId>,
+    pub asset_out: Asset<T::AssetId>,
+    // unfilled remainders, their ratio is the limit price
+    pub asset_in_amount: BalanceOf<T>,
+    pub min_asset_out_amount: BalanceOf<T>,
+    // unpaid remainder of the keeper bounty, escrowed in asset_in
+    pub bounty: BalanceOf<T>,
+    // order can not be filled after this block
+    pub expiry: T::BlockNumber,
+}
+
+impl<T: Trait> LimitOrder<T> {
+    pub fn new(
+        owner: T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+        bounty: BalanceOf<T>,
+        expiry: T::BlockNumber,
+    ) -> Result<Self, Error<T>> {
+        ensure!(
+            asset_in_amount > BalanceOf::<T>::zero()
+                && min_asset_out_amount > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+        ensure!(
+            expiry > <system::Module<T>>::block_number(),
+            Error::<T>::LimitOrderExpired
+        );
+
+        Ok(Self {
+            owner,
+            asset_in,
+            asset_out,
+            asset_in_amount,
+            min_asset_out_amount,
+            bounty,
+            expiry,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        <system::Module<T>>::block_number() > self.expiry
+    }
+
+    /// Escrowed amount of `asset_in`, including the unpaid bounty
+    pub fn escrowed_amount(&self) -> Result<BalanceOf<T>, Error<T>> {
+        self.asset_in_amount
+            .checked_add(&self.bounty)
+            .ok_or(Error::<T>::OverflowOccured)
+    }
+
+    /// Fill `asset_in_amount` of the order.
+    /// Returns the minimum `asset_out` amount at the limit price (rounded up) and the keeper bounty.
+    pub fn fill(
+        &mut self,
+        asset_in_amount: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        ensure!(!self.is_expired(), Error::<T>::LimitOrderExpired);
+        ensure!(
+            asset_in_amount > BalanceOf::<T>::zero() && asset_in_amount <= self.asset_in_amount,
+            Error::<T>::InvalidFillAmount
+        );
+
+        let (min_asset_out_amount, bounty) = if asset_in_amount == self.asset_in_amount {
+            (self.min_asset_out_amount, self.bounty)
+        } else {
+            (
+                math::div_ceil::<T>(
+                    math::product::<T>(asset_in_amount, self.min_asset_out_amount)?,
+                    math::to_u256::<T>(self.asset_in_amount)?,
+                )?,
+                math::mul_div::<T>(asset_in_amount, self.bounty, self.asset_in_amount)?,
+            )
+        };
+
+        // Can not underflow, as the filled part does not exceed the order
+        self.asset_in_amount -= asset_in_amount;
+        self.min_asset_out_amount -= min_asset_out_amount;
+        self.bounty -= bounty;
+        Ok((min_asset_out_amount, bounty))
+    }
+
+    pub fn is_filled(&self) -> bool {
+        self.asset_in_amount == BalanceOf::<T>::zero()
+    }
+}
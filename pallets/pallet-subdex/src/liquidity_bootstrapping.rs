@@ -0,0 +1,87 @@
+use super::*;
+use sp_runtime::traits::UniqueSaturatedInto;
+
+/// Weights of the liquidity bootstrapping pool are interpolated with this precision
+pub const WEIGHT_SCALE: u32 = 1_000_000;
+
+/// Schedule and controls of the weighted pool, used for the token launch.
+/// Weights shift linearly from `start_weights` to `end_weights` between `start_block` and `end_block`,
+/// so that the price of the launched token gradually declines, until it is discovered by the buyers.
+/// Only the owner provides liquidity.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct LiquidityBootstrapping<T: Trait> {
+    pub owner: T::AccountId,
+    // ordered as the pool assets
+    pub start_weights: Vec<u32>,
+    pub end_weights: Vec<u32>,
+    pub start_block: T::BlockNumber,
+    pub end_block: T::BlockNumber,
+    // swaps are not allowed, while the pool is paused
+    pub paused: bool,
+}
+
+impl<T: Trait> LiquidityBootstrapping<T> {
+    pub fn new(
+        owner: T::AccountId,
+        start_weights: Vec<u32>,
+        end_weights: Vec<u32>,
+        start_block: T::BlockNumber,
+        end_block: T::BlockNumber,
+    ) -> Result<Self, Error<T>> {
+        ensure!(
+            start_block >= <system::Module<T>>::block_number() && end_block > start_block,
+            Error::<T>::InvalidLiquidityBootstrappingSchedule
+        );
+        ensure!(
+            end_weights.len() == start_weights.len(),
+            Error::<T>::InvalidWeight
+        );
+        WeightedPool::<T>::ensure_valid_weights(end_weights.iter().copied())?;
+
+        Ok(Self {
+            owner,
+            start_weights,
+            end_weights,
+            start_block,
+            end_block,
+            paused: false,
+        })
+    }
+
+    /// Weights at the given block, scaled by WEIGHT_SCALE
+    pub fn weights_at(&self, now: T::BlockNumber) -> Vec<u32> {
+        let (elapsed, duration): (u64, u64) = if now >= self.end_block {
+            (1, 1)
+        } else if now <= self.start_block {
+            (0, 1)
+        } else {
+            (
+                (now - self.start_block).unique_saturated_into(),
+                (self.end_block - self.start_block).unique_saturated_into(),
+            )
+        };
+
+        // Can not overflow, as weights do not exceed MAX_TOTAL_WEIGHT
+        self.start_weights
+            .iter()
+            .zip(self.end_weights.iter())
+            .map(|(start_weight, end_weight)| {
+                math::interpolate(
+                    start_weight * WEIGHT_SCALE,
+                    end_weight * WEIGHT_SCALE,
+                    elapsed,
+                    duration,
+                )
+            })
+            .collect()
+    }
+
+    pub fn ensure_owner(&self, who: &T::AccountId) -> Result<(), Error<T>> {
+        ensure!(
+            self.owner == *who,
+            Error::<T>::NotLiquidityBootstrappingPoolOwner
+        );
+        Ok(())
+    }
+}
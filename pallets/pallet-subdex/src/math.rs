@@ -0,0 +1,269 @@
+// Pool math is performed with 256-bit intermediates, so that it can not overflow
+// for any pair of 128-bit pools. Results are converted back, when they fit into a balance.
+
+use super::*;
+use sp_core::U256;
+use sp_std::convert::{TryFrom, TryInto};
+
+pub fn to_u256<T: Trait>(amount: BalanceOf<T>) -> Result<U256, Error<T>> {
+    TryInto::<u128>::try_into(amount)
+        .map(U256::from)
+        .map_err(|_| Error::<T>::UnderflowOrOverflowOccured)
+}
+
+fn to_u128<T: Trait>(amount: BalanceOf<T>) -> Result<u128, Error<T>> {
+    TryInto::<u128>::try_into(amount).map_err(|_| Error::<T>::UnderflowOrOverflowOccured)
+}
+
+pub fn from_u256<T: Trait>(amount: U256) -> Result<BalanceOf<T>, Error<T>> {
+    ensure!(
+        amount <= U256::from(u128::max_value()),
+        Error::<T>::OverflowOccured
+    );
+    BalanceOf::<T>::try_from(amount.low_u128()).map_err(|_| Error::<T>::OverflowOccured)
+}
+
+pub fn product<T: Trait>(first: BalanceOf<T>, second: BalanceOf<T>) -> Result<U256, Error<T>> {
+    to_u256::<T>(first)?
+        .checked_mul(to_u256::<T>(second)?)
+        .ok_or(Error::<T>::OverflowOccured)
+}
+
+// a * b / c, rounded down
+pub fn mul_div<T: Trait>(
+    a: BalanceOf<T>,
+    b: BalanceOf<T>,
+    c: BalanceOf<T>,
+) -> Result<BalanceOf<T>, Error<T>> {
+    let quotient = product::<T>(a, b)?
+        .checked_div(to_u256::<T>(c)?)
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    from_u256::<T>(quotient)
+}
+
+pub fn div_ceil<T: Trait>(dividend: U256, divisor: U256) -> Result<BalanceOf<T>, Error<T>> {
+    let quotient = dividend
+        .checked_div(divisor)
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    if quotient * divisor == dividend {
+        from_u256::<T>(quotient)
+    } else {
+        from_u256::<T>(quotient + 1)
+    }
+}
+
+// Avoid casting to float
+pub fn sqrt(y: U256) -> U256 {
+    if y > U256::from(3) {
+        let mut z = y;
+        // Can not overflow, as x never exceeds y / 2 + 1
+        let mut x = y / 2 + 1;
+        while x < z {
+            z = x;
+            x = (y / x + x) / 2;
+        }
+        z
+    } else if !y.is_zero() {
+        U256::one()
+    } else {
+        U256::zero()
+    }
+}
+
+// Linear interpolation from initial to future value, elapsed must not exceed duration
+pub fn interpolate(initial: u32, future: u32, elapsed: u64, duration: u64) -> u32 {
+    // Can not overflow, as elapsed is less than or equal to duration
+    if future > initial {
+        let change = u64::from(future - initial) * elapsed / duration;
+        initial + change as u32
+    } else {
+        let change = u64::from(initial - future) * elapsed / duration;
+        initial - change as u32
+    }
+}
+
+// numerator_pool / denominator_pool, scaled by PRICE_ONE. Price, exceeding 128 bits, saturates.
+pub fn price<T: Trait>(
+    numerator_pool: BalanceOf<T>,
+    denominator_pool: BalanceOf<T>,
+) -> Result<u128, Error<T>> {
+    let denominator_pool = to_u256::<T>(denominator_pool)?;
+    let price = to_u256::<T>(numerator_pool)?
+        .checked_mul(U256::from(PRICE_ONE))
+        .and_then(|result| result.checked_div(denominator_pool))
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    if price > U256::from(u128::max_value()) {
+        Ok(u128::max_value())
+    } else {
+        Ok(price.low_u128())
+    }
+}
+
+// numerator_pool / denominator_pool, scaled by PRICE_ONE
+pub fn spot_price<T: Trait>(
+    numerator_pool: BalanceOf<T>,
+    denominator_pool: BalanceOf<T>,
+) -> Result<BalanceOf<T>, Error<T>> {
+    let price = price::<T>(numerator_pool, denominator_pool)?;
+    BalanceOf::<T>::try_from(price).map_err(|_| Error::<T>::OverflowOccured)
+}
+
+// price_cumulative_last + numerator_pool / denominator_pool * time_elapsed, where the price is scaled by PRICE_ONE.
+// Price, exceeding 128 bits, saturates and the cumulative price wraps around on overflow,
+// so that pool updates never fail, while the difference of two observations stays exact.
+pub fn accumulate_price<T: Trait>(
+    price_cumulative_last: BalanceOf<T>,
+    numerator_pool: BalanceOf<T>,
+    denominator_pool: BalanceOf<T>,
+    time_elapsed: BalanceOf<T>,
+) -> Result<BalanceOf<T>, Error<T>> {
+    let price = price::<T>(numerator_pool, denominator_pool)?;
+
+    let price_cumulative = price
+        .wrapping_mul(to_u128::<T>(time_elapsed)?)
+        .wrapping_add(to_u128::<T>(price_cumulative_last)?);
+    BalanceOf::<T>::try_from(price_cumulative).map_err(|_| Error::<T>::OverflowOccured)
+}
+
+// (price_cumulative - price_cumulative_last) / time_elapsed, scaled by PRICE_ONE
+pub fn average_price<T: Trait>(
+    price_cumulative_last: BalanceOf<T>,
+    price_cumulative: BalanceOf<T>,
+    time_elapsed: BalanceOf<T>,
+) -> Result<BalanceOf<T>, Error<T>> {
+    // Cumulative price could have wrapped around since the last observation
+    let price_delta =
+        to_u128::<T>(price_cumulative)?.wrapping_sub(to_u128::<T>(price_cumulative_last)?);
+    price_delta
+        .checked_div(to_u128::<T>(time_elapsed)?)
+        .map(BalanceOf::<T>::try_from)
+        .and_then(Result::ok)
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)
+}
+
+// Integer cumulative price, scaled by PRICE_ONE, wrapping around on overflow
+pub fn scale_price_cumulative<T: Trait>(
+    price_cumulative: BalanceOf<T>,
+) -> Result<BalanceOf<T>, Error<T>> {
+    let price_cumulative = to_u128::<T>(price_cumulative)?.wrapping_mul(PRICE_ONE);
+    BalanceOf::<T>::try_from(price_cumulative).map_err(|_| Error::<T>::OverflowOccured)
+}
+
+// Fixed point math of the weighted pools, numbers are scaled by ONE.
+
+/// Fixed point one
+pub const ONE: U256 = U256([1_000_000_000_000_000_000, 0, 0, 0]);
+
+// Precision of the fractional power approximation
+const POW_PRECISION: U256 = U256([100_000_000, 0, 0, 0]);
+
+// Fractional power approximation converges for bases within (0, 2), but slows down towards the ends,
+// so bases are bounded to [MIN_POW_BASE, MAX_POW_BASE], where it converges within MAX_POW_ITERATIONS
+const MIN_POW_BASE: U256 = U256([250_000_000_000_000_000, 0, 0, 0]);
+const MAX_POW_BASE: U256 = U256([1_750_000_000_000_000_000, 0, 0, 0]);
+
+// Binomial series terms limit
+const MAX_POW_ITERATIONS: usize = 64;
+
+// a * b, rounded to the nearest
+pub fn mul_fixed<T: Trait>(a: U256, b: U256) -> Result<U256, Error<T>> {
+    a.checked_mul(b)
+        .and_then(|result| result.checked_add(ONE / 2))
+        .map(|result| result / ONE)
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)
+}
+
+// a / b, rounded to the nearest
+pub fn div_fixed<T: Trait>(a: U256, b: U256) -> Result<U256, Error<T>> {
+    a.checked_mul(ONE)
+        .and_then(|result| result.checked_add(b / 2))
+        .and_then(|result| result.checked_div(b))
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)
+}
+
+// base ^ exp for the whole exp
+pub fn pow_whole<T: Trait>(mut base: U256, mut exp: U256) -> Result<U256, Error<T>> {
+    let mut result = ONE;
+    while !exp.is_zero() {
+        if exp.bit(0) {
+            result = mul_fixed::<T>(result, base)?;
+        }
+        exp = exp >> 1;
+        if !exp.is_zero() {
+            base = mul_fixed::<T>(base, base)?;
+        }
+    }
+    Ok(result)
+}
+
+// base ^ exp for the fractional exp, approximated by the binomial series
+fn pow_fraction<T: Trait>(base: U256, exp: U256) -> Result<U256, Error<T>> {
+    let (x, x_negative) = if base >= ONE {
+        (base - ONE, false)
+    } else {
+        (ONE - base, true)
+    };
+
+    let mut term = ONE;
+    let mut sum = ONE;
+    let mut negative = false;
+    for k in 1..=MAX_POW_ITERATIONS {
+        let big_k = U256::from(k) * ONE;
+        let (c, c_negative) = if exp >= big_k - ONE {
+            (exp - (big_k - ONE), false)
+        } else {
+            ((big_k - ONE) - exp, true)
+        };
+        term = div_fixed::<T>(mul_fixed::<T>(term, mul_fixed::<T>(c, x)?)?, big_k)?;
+        if term.is_zero() {
+            return Ok(sum);
+        }
+
+        if x_negative {
+            negative = !negative;
+        }
+        if c_negative {
+            negative = !negative;
+        }
+        sum = if negative {
+            sum.checked_sub(term)
+                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?
+        } else {
+            sum + term
+        };
+        if term < POW_PRECISION {
+            return Ok(sum);
+        }
+    }
+    Err(Error::<T>::PowNotConverged)
+}
+
+/// base ^ exp, where base is within [MIN_POW_BASE, MAX_POW_BASE]
+pub fn pow_fixed<T: Trait>(base: U256, exp: U256) -> Result<U256, Error<T>> {
+    ensure!(
+        base >= MIN_POW_BASE && base <= MAX_POW_BASE,
+        Error::<T>::UnderflowOrOverflowOccured
+    );
+
+    let whole = exp / ONE;
+    let remain = exp % ONE;
+
+    let whole_pow = pow_whole::<T>(base, whole)?;
+    if remain.is_zero() {
+        return Ok(whole_pow);
+    }
+    mul_fixed::<T>(whole_pow, pow_fraction::<T>(base, remain)?)
+}
+
+// a * b / c, rounded up or down
+pub fn mul_div_u256<T: Trait>(a: U256, b: U256, c: U256, round_up: bool) -> Result<U256, Error<T>> {
+    let product = a.checked_mul(b).ok_or(Error::<T>::OverflowOccured)?;
+    let quotient = product
+        .checked_div(c)
+        .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+    if round_up && quotient * c != product {
+        Ok(quotient + 1)
+    } else {
+        Ok(quotient)
+    }
+}
@@ -1,7 +1,11 @@
 // Creating mock runtime here
 
-use crate::{Module, Trait};
-use frame_support::{impl_outer_origin, parameter_types, weights::Weight};
+use crate as pallet_subdex;
+use crate::{DexTreasury, GenesisConfig, Module, Trait};
+use frame_support::{
+    impl_outer_dispatch, impl_outer_origin, parameter_types,
+    weights::{constants::RocksDbWeight, Weight},
+};
 use frame_system as system;
 use sp_core::H256;
 use sp_runtime::{
@@ -14,6 +18,16 @@ impl_outer_origin! {
     pub enum Origin for Test {}
 }
 
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        pallet_subdex::DexPallet,
+    }
+}
+
+pub type AccountId = u64;
+pub type Balance = u128;
+pub type AssetId = u64;
+
 // For testing the pallet, we construct most of a mock runtime. This means
 // first constructing a configuration type (`Test`) which `impl`s each of the
 // configuration traits of pallets we want to use.
@@ -33,13 +47,13 @@ impl system::Trait for Test {
     type BlockNumber = u64;
     type Hash = H256;
     type Hashing = BlakeTwo256;
-    type AccountId = u64;
+    type AccountId = AccountId;
     type Lookup = IdentityLookup<Self::AccountId>;
     type Header = Header;
     type Event = ();
     type BlockHashCount = BlockHashCount;
     type MaximumBlockWeight = MaximumBlockWeight;
-    type DbWeight = ();
+    type DbWeight = RocksDbWeight;
     type BlockExecutionWeight = ();
     type ExtrinsicBaseWeight = ();
     type MaximumExtrinsicWeight = MaximumBlockWeight;
@@ -47,20 +61,96 @@ impl system::Trait for Test {
     type AvailableBlockRatio = AvailableBlockRatio;
     type Version = ();
     type ModuleToIndex = ();
-    type AccountData = ();
+    type AccountData = balances::AccountData<Balance>;
     type OnNewAccount = ();
     type OnKilledAccount = ();
+    type SystemWeightInfo = ();
+}
+
+parameter_types! {
+    pub const MinimumPeriod: u64 = 1;
+}
+impl pallet_timestamp::Trait for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = MinimumPeriod;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const ExistentialDeposit: Balance = 1;
+}
+impl balances::Trait for Test {
+    type Balance = Balance;
+    type Event = ();
+    type DustRemoval = ();
+    type ExistentialDeposit = ExistentialDeposit;
+    type AccountStore = System;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub const FeeRateNominator: Balance = 3;
+    pub const FeeRateDenominator: Balance = 1000;
+    // Two scheduled executions per block
+    // Two scheduled executions and the cursor
+    pub const MaxScheduledWeight: Weight = 2_625_000_000;
+    pub const ScheduledOrderDeposit: Balance = 1_000;
+    pub const TwapPeriod: u64 = 60;
+    pub const MaxTwapWindow: u64 = 600;
+    pub const MaxTicksCrossed: u32 = 16;
 }
 impl Trait for Test {
     type Event = ();
+    type Currency = Balances;
+    type IMoment = u64;
+    type AssetId = AssetId;
+    type FeeRateNominator = FeeRateNominator;
+    type FeeRateDenominator = FeeRateDenominator;
+    type MaxScheduledWeight = MaxScheduledWeight;
+    type ScheduledOrderDeposit = ScheduledOrderDeposit;
+    type TwapPeriod = TwapPeriod;
+    type MaxTwapWindow = MaxTwapWindow;
+    type MaxTicksCrossed = MaxTicksCrossed;
+    type Call = Call;
 }
-pub type TemplateModule = Module<Test>;
+
+pub type System = system::Module<Test>;
+pub type Timestamp = pallet_timestamp::Module<Test>;
+pub type Balances = balances::Module<Test>;
+pub type DexPallet = Module<Test>;
+
+pub const ALICE: AccountId = 1;
+pub const BOB: AccountId = 2;
+pub const TREASURY: AccountId = 100;
+
+pub const FIRST_ASSET_ID: AssetId = 1;
+pub const SECOND_ASSET_ID: AssetId = 2;
+
+pub const INITIAL_BALANCE: Balance = 1_000_000_000_000;
 
 // This function basically just builds a genesis storage key/value store according to
 // our desired mockup.
 pub fn new_test_ext() -> sp_io::TestExternalities {
-    system::GenesisConfig::default()
+    let mut t = system::GenesisConfig::default()
         .build_storage::<Test>()
-        .unwrap()
-        .into()
+        .unwrap();
+
+    balances::GenesisConfig::<Test> {
+        balances: vec![(ALICE, INITIAL_BALANCE), (BOB, INITIAL_BALANCE)],
+    }
+    .assimilate_storage(&mut t)
+    .unwrap();
+
+    GenesisConfig::<Test> {
+        // Half of the fee goes to the treasury
+        dex_treasury: DexTreasury::new(TREASURY, 1, 2),
+        assets: vec![FIRST_ASSET_ID, SECOND_ASSET_ID],
+        initial_balance: INITIAL_BALANCE,
+        endowed_accounts: vec![ALICE, BOB],
+    }
+    .assimilate_storage(&mut t)
+    .unwrap();
+
+    t.into()
 }
@@ -0,0 +1,54 @@
+use super::*;
+
+/// Pool of any supported kind, holding liquidity of the (ordered) assets pair.
+/// Swaps and investments are routed to the pool, registered for the pair.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Pool<T: Trait> {
+    ConstantProduct(Exchange<T>),
+    Stable(StableExchange<T>),
+}
+
+impl<T: Trait> Pool<T> {
+    /// Pools, shares and cumulative prices
+    pub fn liquidity(&self) -> &Exchange<T> {
+        match self {
+            Pool::ConstantProduct(exchange) => exchange,
+            Pool::Stable(stable_exchange) => stable_exchange.liquidity(),
+        }
+    }
+
+    pub fn liquidity_mut(&mut self) -> &mut Exchange<T> {
+        match self {
+            Pool::ConstantProduct(exchange) => exchange,
+            Pool::Stable(stable_exchange) => stable_exchange.liquidity_mut(),
+        }
+    }
+
+    pub fn calculate_first_to_second_asset_swap(
+        &self,
+        first_asset_amount: BalanceOf<T>,
+    ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        match self {
+            Pool::ConstantProduct(exchange) => {
+                exchange.calculate_first_to_second_asset_swap(first_asset_amount)
+            }
+            Pool::Stable(stable_exchange) => {
+                stable_exchange.calculate_first_to_second_asset_swap(first_asset_amount)
+            }
+        }
+    }
+
+    pub fn calculate_second_to_first_asset_swap(
+        &self,
+        second_asset_amount: BalanceOf<T>,
+    ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        match self {
+            Pool::ConstantProduct(exchange) => {
+                exchange.calculate_second_to_first_asset_swap(second_asset_amount)
+            }
+            Pool::Stable(stable_exchange) => {
+                stable_exchange.calculate_second_to_first_asset_swap(second_asset_amount)
+            }
+        }
+    }
+}
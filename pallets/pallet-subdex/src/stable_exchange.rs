@@ -0,0 +1,296 @@
+use super::*;
+use sp_core::U256;
+use sp_runtime::traits::{AtLeast32BitUnsigned, One, UniqueSaturatedInto};
+
+/// Upper bound of the amplification coefficient
+pub const MAX_AMPLIFICATION: u32 = 1_000_000;
+
+/// Amplification coefficient can change at most by this factor within a single ramp
+pub const MAX_AMPLIFICATION_CHANGE: u32 = 10;
+
+// Newton's method iterations limit
+const MAX_ITERATIONS: usize = 255;
+
+/// Amplification coefficient, changing linearly from `initial` to `future` between two blocks
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Amplification<BlockNumber> {
+    pub initial: u32,
+    pub future: u32,
+    pub initial_block: BlockNumber,
+    pub future_block: BlockNumber,
+}
+
+impl<BlockNumber: AtLeast32BitUnsigned + Copy> Amplification<BlockNumber> {
+    pub fn new(amplification: u32, now: BlockNumber) -> Self {
+        Self {
+            initial: amplification,
+            future: amplification,
+            initial_block: now,
+            future_block: now,
+        }
+    }
+
+    /// Amplification coefficient at the given block
+    pub fn at(&self, now: BlockNumber) -> u32 {
+        if now >= self.future_block {
+            return self.future;
+        }
+        if now <= self.initial_block {
+            return self.initial;
+        }
+
+        let elapsed: u64 = (now - self.initial_block).unique_saturated_into();
+        let duration: u64 = (self.future_block - self.initial_block).unique_saturated_into();
+        math::interpolate(self.initial, self.future, elapsed, duration)
+    }
+}
+
+/// Structure, representing StableSwap (Curve-style amplified invariant) pool of two pegged assets.
+/// Shares are accounted the same way, as for the constant product exchange.
+/// Cumulative prices of the pools ratio are kept, but not exposed, as the ratio is not the pool price.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct StableExchange<T: Trait> {
+    pub amplification: Amplification<T::BlockNumber>,
+    // pools, shares and cumulative prices
+    liquidity: Exchange<T>,
+}
+
+impl<T: Trait> StableExchange<T> {
+    pub fn new(amplification: u32) -> Self {
+        Self {
+            amplification: Amplification::new(amplification, <system::Module<T>>::block_number()),
+            liquidity: Exchange::default(),
+        }
+    }
+
+    pub fn liquidity(&self) -> &Exchange<T> {
+        &self.liquidity
+    }
+
+    pub fn liquidity_mut(&mut self) -> &mut Exchange<T> {
+        &mut self.liquidity
+    }
+
+    /// Amplification coefficient at the current block
+    pub fn current_amplification(&self) -> u32 {
+        self.amplification.at(<system::Module<T>>::block_number())
+    }
+
+    /// StableSwap invariant D of the pools
+    pub fn invariant(&self) -> Result<U256, Error<T>> {
+        Self::calculate_invariant(
+            math::to_u256::<T>(self.liquidity.first_asset_pool())?,
+            math::to_u256::<T>(self.liquidity.second_asset_pool())?,
+            self.current_amplification(),
+        )
+    }
+
+    // Solve A * n^n * (x + y) + D = A * D * n^n + D^(n + 1) / (n^n * x * y) for D, where n = 2
+    fn calculate_invariant(x: U256, y: U256, amplification: u32) -> Result<U256, Error<T>> {
+        let sum = x + y;
+        if sum.is_zero() {
+            return Ok(U256::zero());
+        }
+        let ann = U256::from(amplification) * 4;
+
+        let mut d = sum;
+        for _ in 0..MAX_ITERATIONS {
+            // d_p = D^3 / (4 * x * y)
+            let d_p = d
+                .checked_mul(d)
+                .and_then(|result| result.checked_div(x * 2))
+                .and_then(|result| result.checked_mul(d))
+                .and_then(|result| result.checked_div(y * 2))
+                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+
+            let d_prev = d;
+            // D = (Ann * S + 2 * d_p) * D / ((Ann - 1) * D + 3 * d_p)
+            d = ann
+                .checked_mul(sum)
+                .and_then(|result| result.checked_add(d_p.checked_mul(2.into())?))
+                .and_then(|result| result.checked_mul(d))
+                .and_then(|numerator| {
+                    (ann - 1)
+                        .checked_mul(d)
+                        .and_then(|result| result.checked_add(d_p.checked_mul(3.into())?))
+                        .and_then(|denominator| numerator.checked_div(denominator))
+                })
+                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+
+            if Self::converged(d, d_prev) {
+                return Ok(d);
+            }
+        }
+        Err(Error::<T>::InvariantNotConverged)
+    }
+
+    // Solve the invariant equation for the other pool, given the new pool x and invariant D
+    fn calculate_other_pool(x: U256, d: U256, amplification: u32) -> Result<U256, Error<T>> {
+        let ann = U256::from(amplification) * 4;
+
+        // c = D^3 / (4 * x * Ann), b = x + D / Ann
+        let c = d
+            .checked_mul(d)
+            .and_then(|result| result.checked_div(x * 2))
+            .and_then(|result| result.checked_mul(d))
+            .and_then(|result| result.checked_div(ann * 2))
+            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        let b = x + d / ann;
+
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            let y_prev = y;
+            // y = (y^2 + c) / (2 * y + b - D)
+            y = y
+                .checked_mul(y)
+                .and_then(|result| result.checked_add(c))
+                .and_then(|numerator| {
+                    (y * 2 + b)
+                        .checked_sub(d)
+                        .and_then(|denominator| numerator.checked_div(denominator))
+                })
+                .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+
+            if Self::converged(y, y_prev) {
+                return Ok(y);
+            }
+        }
+        Err(Error::<T>::InvariantNotConverged)
+    }
+
+    fn converged(value: U256, prev_value: U256) -> bool {
+        if value > prev_value {
+            value - prev_value <= U256::one()
+        } else {
+            prev_value - value <= U256::one()
+        }
+    }
+
+    // Returns new pools in, out and the amount out
+    fn perform_swap_calculation(
+        &self,
+        pool_in: BalanceOf<T>,
+        pool_out: BalanceOf<T>,
+        exchange_fee: BalanceOf<T>,
+        treasury_fee: BalanceOf<T>,
+        amount_in: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let amplification = self.current_amplification();
+        let invariant = Self::calculate_invariant(
+            math::to_u256::<T>(pool_in)?,
+            math::to_u256::<T>(pool_out)?,
+            amplification,
+        )?;
+
+        // Treasury fee is paid out of the pool
+        let new_pool_in = pool_in
+            .checked_add(&amount_in)
+            .map(|result| result.checked_sub(&treasury_fee))
+            .flatten()
+            .ok_or(Error::<T>::UnderflowOrOverflowOccured)?;
+        let temp_pool_in = new_pool_in
+            .checked_sub(&exchange_fee)
+            .ok_or(Error::<T>::UnderflowOccured)?;
+
+        let other_pool = math::from_u256::<T>(Self::calculate_other_pool(
+            math::to_u256::<T>(temp_pool_in)?,
+            invariant,
+            amplification,
+        )?)?;
+        // Rounded in favor of the pool, so that invariant never decreases
+        let amount_out = pool_out
+            .checked_sub(&other_pool)
+            .map(|result| result.checked_sub(&BalanceOf::<T>::one()))
+            .flatten()
+            .ok_or(Error::<T>::UnderflowOccured)?;
+        let new_pool_out = pool_out - amount_out;
+
+        Ok((new_pool_in, new_pool_out, amount_out))
+    }
+
+    pub fn calculate_first_to_second_asset_swap(
+        &self,
+        first_asset_amount: BalanceOf<T>,
+    ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(first_asset_amount)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+
+        let (new_first_asset_pool, new_second_asset_pool, second_asset_amount) = self
+            .perform_swap_calculation(
+                self.liquidity.first_asset_pool(),
+                self.liquidity.second_asset_pool(),
+                exchange_fee,
+                treasury_fee,
+                first_asset_amount,
+            )?;
+        Ok((
+            SwapDelta::new(
+                new_first_asset_pool,
+                new_second_asset_pool,
+                second_asset_amount,
+            ),
+            treasury_fee_data,
+        ))
+    }
+
+    pub fn calculate_second_to_first_asset_swap(
+        &self,
+        second_asset_amount: BalanceOf<T>,
+    ) -> Result<(SwapDelta<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(second_asset_amount)?;
+        let treasury_fee = treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee);
+
+        let (new_second_asset_pool, new_first_asset_pool, first_asset_amount) = self
+            .perform_swap_calculation(
+                self.liquidity.second_asset_pool(),
+                self.liquidity.first_asset_pool(),
+                exchange_fee,
+                treasury_fee,
+                second_asset_amount,
+            )?;
+        Ok((
+            SwapDelta::new(
+                new_first_asset_pool,
+                new_second_asset_pool,
+                first_asset_amount,
+            ),
+            treasury_fee_data,
+        ))
+    }
+
+    /// Start linear ramp of the amplification coefficient from its current value
+    /// to `future_amplification`, reached at `future_block`.
+    pub fn ramp_amplification(
+        &mut self,
+        future_amplification: u32,
+        future_block: T::BlockNumber,
+    ) -> dispatch::DispatchResult {
+        let now = <system::Module<T>>::block_number();
+        let current_amplification = self.amplification.at(now);
+
+        ensure!(future_block > now, Error::<T>::InvalidAmplificationRamp);
+        ensure!(
+            future_amplification > 0 && future_amplification <= MAX_AMPLIFICATION,
+            Error::<T>::InvalidAmplification
+        );
+        ensure!(
+            future_amplification <= current_amplification * MAX_AMPLIFICATION_CHANGE
+                && future_amplification * MAX_AMPLIFICATION_CHANGE >= current_amplification,
+            Error::<T>::InvalidAmplificationRamp
+        );
+
+        self.amplification = Amplification {
+            initial: current_amplification,
+            future: future_amplification,
+            initial_block: now,
+            future_block,
+        };
+        Ok(())
+    }
+}
@@ -1,26 +1,3403 @@
-// Tests to be written here
-
-use crate::{mock::*, Error};
-use frame_support::{assert_noop, assert_ok};
-
-// #[test]
-// fn it_works_for_default_value() {
-//     new_test_ext().execute_with(|| {
-//         // Just a dummy test for the dummy function `do_something`
-//         // calling the `do_something` function with a value 42
-//         assert_ok!(TemplateModule::do_something(Origin::signed(1), 42));
-//         // asserting that the stored value is equal to what we stored
-//         assert_eq!(TemplateModule::something(), Some(42));
-//     });
-// }
-
-// #[test]
-// fn correct_error_for_none_value() {
-//     new_test_ext().execute_with(|| {
-//         // Ensure the correct error is thrown on None value
-//         assert_noop!(
-//             TemplateModule::cause_error(Origin::signed(1)),
-//             Error::<Test>::NoneValue
-//         );
-//     });
-// }
+use crate::{
+    concentrated_pool::{sqrt_price_at_tick, tick_at_sqrt_price, ConcentratedPool, Position, Tick},
+    exchange::Exchange,
+    liquidity_bootstrapping::WEIGHT_SCALE,
+    math,
+    mock::*,
+    stable_exchange::{Amplification, StableExchange, MAX_AMPLIFICATION},
+    weighted_pool::{WeightedPool, INITIAL_WEIGHTED_POOL_SHARES},
+    Asset, AssetBalances, Call as DexCall, Error, Exchanges, PositionId, Releases, ScheduledOrder,
+    StorageVersion, TriggerCondition, PRICE_ONE,
+};
+use codec::Encode;
+use frame_support::{
+    assert_noop, assert_ok,
+    dispatch::DispatchError,
+    traits::{Currency, OnInitialize},
+    StorageDoubleMap, StorageValue,
+};
+use proptest::prelude::*;
+use sp_core::U256;
+
+const MAIN: Asset<AssetId> = Asset::MainNetworkCurrency;
+const FIRST_ASSET: Asset<AssetId> = Asset::ParachainAsset(FIRST_ASSET_ID);
+const SECOND_ASSET: Asset<AssetId> = Asset::ParachainAsset(SECOND_ASSET_ID);
+
+fn initialize_exchange(main_amount: Balance, asset_amount: Balance) {
+    assert_ok!(DexPallet::initialize_exchange(
+        Origin::signed(ALICE),
+        MAIN,
+        main_amount,
+        FIRST_ASSET,
+        asset_amount
+    ));
+}
+
+fn exchange() -> Exchange<Test> {
+    DexPallet::exchanges(MAIN, FIRST_ASSET)
+}
+
+fn initialize_stable_exchange(amplification: u32, first_amount: Balance, second_amount: Balance) {
+    assert_ok!(DexPallet::register_stable_exchange(
+        Origin::root(),
+        FIRST_ASSET,
+        SECOND_ASSET,
+        amplification
+    ));
+    assert_ok!(DexPallet::initialize_exchange(
+        Origin::signed(ALICE),
+        FIRST_ASSET,
+        first_amount,
+        SECOND_ASSET,
+        second_amount
+    ));
+}
+
+fn stable_exchange() -> StableExchange<Test> {
+    DexPallet::stable_exchanges(FIRST_ASSET, SECOND_ASSET).unwrap()
+}
+
+// MAIN, FIRST_ASSET and SECOND_ASSET, weighted 50/25/25
+fn create_weighted_pool() {
+    assert_ok!(DexPallet::create_weighted_pool(
+        Origin::signed(ALICE),
+        vec![
+            (MAIN, 50, 2_000_000_000),
+            (FIRST_ASSET, 25, 1_000_000_000),
+            (SECOND_ASSET, 25, 1_000_000_000)
+        ]
+    ));
+}
+
+fn weighted_pool() -> WeightedPool<Test> {
+    DexPallet::weighted_pools(0).unwrap()
+}
+
+fn weighted_pools(weighted_pool: &WeightedPool<Test>) -> Vec<Balance> {
+    weighted_pool
+        .assets
+        .iter()
+        .map(|weighted_asset| weighted_asset.pool)
+        .collect()
+}
+
+// FIRST_ASSET is launched against MAIN, weights shift from 90/10 to 50/50 between blocks 10 and 20
+fn create_liquidity_bootstrapping_pool() {
+    assert_ok!(DexPallet::set_liquidity_bootstrapping_pool_creator(
+        Origin::root(),
+        ALICE,
+        true
+    ));
+    assert_ok!(DexPallet::create_liquidity_bootstrapping_pool(
+        Origin::signed(ALICE),
+        vec![
+            (FIRST_ASSET, 90, 50, 1_000_000_000),
+            (MAIN, 10, 50, 100_000_000)
+        ],
+        10,
+        20
+    ));
+}
+
+// FIRST_ASSET and SECOND_ASSET at the price of 1, with ALICE's position between ticks -1000 and 1000
+fn create_concentrated_pool() {
+    assert_ok!(DexPallet::create_concentrated_pool(
+        Origin::signed(ALICE),
+        FIRST_ASSET,
+        SECOND_ASSET,
+        10,
+        0
+    ));
+    assert_ok!(DexPallet::mint_concentrated_position(
+        Origin::signed(ALICE),
+        FIRST_ASSET,
+        SECOND_ASSET,
+        -1000,
+        1000,
+        1_000_000_000,
+        Balance::MAX,
+        Balance::MAX
+    ));
+}
+
+fn concentrated_pool() -> ConcentratedPool<Test> {
+    DexPallet::concentrated_pools(FIRST_ASSET, SECOND_ASSET).unwrap()
+}
+
+fn concentrated_position(position_id: PositionId) -> Option<Position<AccountId, Balance>> {
+    DexPallet::concentrated_positions((FIRST_ASSET, SECOND_ASSET), position_id)
+}
+
+fn concentrated_tick(tick: i32) -> Option<Tick<Balance>> {
+    DexPallet::concentrated_ticks((FIRST_ASSET, SECOND_ASSET), tick)
+}
+
+// Pallet errors are compared as dispatch errors
+fn dispatch_result<R>(result: Result<R, Error<Test>>) -> Result<R, DispatchError> {
+    result.map_err(Into::into)
+}
+
+#[test]
+fn sqrt_rounds_down() {
+    let sqrt = |y: u128| math::sqrt(U256::from(y));
+
+    assert_eq!(sqrt(0), U256::from(0));
+    assert_eq!(sqrt(1), U256::from(1));
+    assert_eq!(sqrt(3), U256::from(1));
+    assert_eq!(sqrt(4), U256::from(2));
+    assert_eq!(sqrt(99), U256::from(9));
+    assert_eq!(sqrt(100), U256::from(10));
+    assert_eq!(sqrt(4_000_000_000_000), U256::from(2_000_000));
+    assert_eq!(sqrt(u128::max_value()), U256::from(u64::max_value()));
+
+    let max_balance = U256::from(u128::max_value());
+    assert_eq!(math::sqrt(max_balance * max_balance), max_balance);
+}
+
+#[test]
+fn initialize_exchange_works() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        let exchange = exchange();
+        assert_eq!(exchange.first_asset_pool(), 1_000_000);
+        assert_eq!(exchange.second_asset_pool(), 4_000_000);
+        assert_eq!(
+            exchange.invariant().unwrap(),
+            U256::from(4_000_000_000_000u128)
+        );
+        assert_eq!(exchange.total_shares, 2_000_000);
+        assert_eq!(exchange.shares_of(&ALICE), 2_000_000);
+
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE - 1_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 4_000_000
+        );
+    });
+}
+
+#[test]
+fn initialize_exchange_orders_assets() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexPallet::initialize_exchange(
+            Origin::signed(ALICE),
+            SECOND_ASSET,
+            2_000,
+            FIRST_ASSET,
+            1_000
+        ));
+
+        let exchange = DexPallet::exchanges(FIRST_ASSET, SECOND_ASSET);
+        assert_eq!(exchange.first_asset_pool(), 1_000);
+        assert_eq!(exchange.second_asset_pool(), 2_000);
+
+        assert_noop!(
+            DexPallet::initialize_exchange(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000,
+                SECOND_ASSET,
+                1_000
+            ),
+            Error::<Test>::ExchangeAlreadyExists
+        );
+    });
+}
+
+#[test]
+fn initialize_exchange_fails_for_zero_amounts() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::initialize_exchange(Origin::signed(ALICE), MAIN, 0, FIRST_ASSET, 1_000),
+            Error::<Test>::LowFirstAssetAmount
+        );
+        assert_noop!(
+            DexPallet::initialize_exchange(Origin::signed(ALICE), MAIN, 1_000, FIRST_ASSET, 0),
+            Error::<Test>::LowSecondAssetAmount
+        );
+    });
+}
+
+#[test]
+fn initialize_exchange_fails_for_insufficient_balances() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::initialize_exchange(
+                Origin::signed(ALICE),
+                MAIN,
+                INITIAL_BALANCE + 1,
+                FIRST_ASSET,
+                1_000
+            ),
+            Error::<Test>::InsufficientKsmBalance
+        );
+        assert_noop!(
+            DexPallet::initialize_exchange(
+                Origin::signed(ALICE),
+                MAIN,
+                1_000,
+                FIRST_ASSET,
+                INITIAL_BALANCE + 1
+            ),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+    });
+}
+
+#[test]
+fn exchange_supports_pools_beyond_balance_product() {
+    new_test_ext().execute_with(|| {
+        // Two 18-decimal assets, whose pools product does not fit into a balance
+        let amount = 1_000_000_000 * 1_000_000_000_000_000_000;
+        AssetBalances::<Test>::insert(ALICE, FIRST_ASSET_ID, amount);
+        AssetBalances::<Test>::insert(ALICE, SECOND_ASSET_ID, amount);
+        AssetBalances::<Test>::insert(BOB, FIRST_ASSET_ID, amount);
+
+        assert_ok!(DexPallet::initialize_exchange(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            amount,
+            SECOND_ASSET,
+            amount
+        ));
+
+        let exchange = DexPallet::exchanges(FIRST_ASSET, SECOND_ASSET);
+        assert_eq!(exchange.total_shares, amount);
+        assert_eq!(
+            exchange.invariant().unwrap(),
+            U256::from(amount) * U256::from(amount)
+        );
+
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            amount / 1000,
+            SECOND_ASSET,
+            0,
+            BOB
+        ));
+        assert!(DexPallet::asset_balances(BOB, SECOND_ASSET_ID) > 0);
+        assert!(
+            DexPallet::exchanges(FIRST_ASSET, SECOND_ASSET)
+                .invariant()
+                .unwrap()
+                >= exchange.invariant().unwrap()
+        );
+
+        assert_ok!(DexPallet::divest_liquidity(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            amount / 2,
+            0,
+            0
+        ));
+        assert_eq!(
+            DexPallet::exchanges(FIRST_ASSET, SECOND_ASSET).total_shares,
+            amount / 2
+        );
+    });
+}
+
+#[test]
+fn swap_main_currency_to_asset_works() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            MAIN,
+            10_000,
+            FIRST_ASSET,
+            39_000,
+            BOB
+        ));
+
+        // fee is 30, half of it goes to the treasury
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 10_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 39_486
+        );
+        assert_eq!(Balances::free_balance(TREASURY), 15);
+
+        let exchange = exchange();
+        assert_eq!(exchange.first_asset_pool(), 1_009_985);
+        assert_eq!(exchange.second_asset_pool(), 3_960_514);
+    });
+}
+
+#[test]
+fn swap_asset_to_main_currency_works() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            40_000,
+            MAIN,
+            9_800,
+            BOB
+        ));
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 9_871);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 40_000
+        );
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 60);
+    });
+}
+
+#[test]
+fn swap_fails_for_invalid_or_missing_exchange() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), MAIN, 1_000, MAIN, 0, BOB),
+            Error::<Test>::InvalidExchange
+        );
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), FIRST_ASSET, 1_000, FIRST_ASSET, 0, BOB),
+            Error::<Test>::InvalidExchange
+        );
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), MAIN, 1_000, FIRST_ASSET, 0, BOB),
+            Error::<Test>::ExchangeNotExists
+        );
+    });
+}
+
+#[test]
+fn swap_fails_below_expectation() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), MAIN, 10_000, FIRST_ASSET, 39_487, BOB),
+            Error::<Test>::SecondAssetAmountBelowExpectation
+        );
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), FIRST_ASSET, 40_000, MAIN, 9_872, BOB),
+            Error::<Test>::FirstAssetAmountBelowExpectation
+        );
+    });
+}
+
+#[test]
+fn swap_fails_for_insufficient_balance_or_overflow() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_noop!(
+            DexPallet::swap_to_exact(
+                Origin::signed(BOB),
+                MAIN,
+                INITIAL_BALANCE + 1,
+                FIRST_ASSET,
+                0,
+                BOB
+            ),
+            Error::<Test>::InsufficientKsmBalance
+        );
+
+        AssetBalances::<Test>::insert(BOB, FIRST_ASSET_ID, u128::max_value() - 1);
+        assert_noop!(
+            DexPallet::swap_to_exact(Origin::signed(BOB), MAIN, 10_000, FIRST_ASSET, 0, BOB),
+            Error::<Test>::OverflowOccured
+        );
+    });
+}
+
+#[test]
+fn invest_liquidity_works() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_ok!(DexPallet::invest_liquidity(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            MAIN,
+            1_000
+        ));
+
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 500);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 2_000
+        );
+
+        let exchange = exchange();
+        assert_eq!(exchange.shares_of(&BOB), 1_000);
+        assert_eq!(exchange.total_shares, 2_001_000);
+        assert_eq!(exchange.first_asset_pool(), 1_000_500);
+        assert_eq!(exchange.second_asset_pool(), 4_002_000);
+    });
+}
+
+#[test]
+fn invest_liquidity_fails() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::invest_liquidity(Origin::signed(BOB), MAIN, FIRST_ASSET, 1_000),
+            Error::<Test>::ExchangeNotExists
+        );
+
+        initialize_exchange(1_000_000, 4_000_000);
+
+        // Single share would cost nothing of the main currency
+        assert_noop!(
+            DexPallet::invest_liquidity(Origin::signed(BOB), MAIN, FIRST_ASSET, 1),
+            Error::<Test>::LowFirstAssetAmount
+        );
+        assert_noop!(
+            DexPallet::invest_liquidity(
+                Origin::signed(BOB),
+                MAIN,
+                FIRST_ASSET,
+                4 * INITIAL_BALANCE
+            ),
+            Error::<Test>::InsufficientKsmBalance
+        );
+    });
+}
+
+#[test]
+fn divest_liquidity_works() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_ok!(DexPallet::divest_liquidity(
+            Origin::signed(ALICE),
+            MAIN,
+            FIRST_ASSET,
+            1_000_000,
+            500_000,
+            2_000_000
+        ));
+
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE - 500_000);
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 2_000_000
+        );
+
+        let exchange = exchange();
+        assert_eq!(exchange.shares_of(&ALICE), 1_000_000);
+        assert_eq!(exchange.total_shares, 1_000_000);
+
+        // Remaining shares can not be burned twice
+        assert_ok!(DexPallet::divest_liquidity(
+            Origin::signed(ALICE),
+            MAIN,
+            FIRST_ASSET,
+            1_000_000,
+            0,
+            0
+        ));
+        assert_noop!(
+            DexPallet::divest_liquidity(Origin::signed(ALICE), MAIN, FIRST_ASSET, 1, 0, 0),
+            Error::<Test>::ExchangeNotExists
+        );
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE);
+    });
+}
+
+#[test]
+fn divest_liquidity_fails() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        assert_noop!(
+            DexPallet::divest_liquidity(Origin::signed(ALICE), MAIN, FIRST_ASSET, 0, 0, 0),
+            Error::<Test>::InvalidShares
+        );
+        assert_noop!(
+            DexPallet::divest_liquidity(Origin::signed(BOB), MAIN, FIRST_ASSET, 1, 0, 0),
+            Error::<Test>::DoesNotOwnShare
+        );
+        assert_noop!(
+            DexPallet::divest_liquidity(Origin::signed(ALICE), MAIN, FIRST_ASSET, 2_000_001, 0, 0),
+            Error::<Test>::InsufficientShares
+        );
+        assert_noop!(
+            DexPallet::divest_liquidity(
+                Origin::signed(ALICE),
+                MAIN,
+                FIRST_ASSET,
+                1_000_000,
+                500_001,
+                0
+            ),
+            Error::<Test>::FirstAssetAmountBelowExpectation
+        );
+        assert_noop!(
+            DexPallet::divest_liquidity(
+                Origin::signed(ALICE),
+                MAIN,
+                FIRST_ASSET,
+                1_000_000,
+                0,
+                2_000_001
+            ),
+            Error::<Test>::SecondAssetAmountBelowExpectation
+        );
+    });
+}
+
+#[test]
+fn exchange_state_checks() {
+    new_test_ext().execute_with(|| {
+        let (mut exchange, shares) = Exchange::<Test>::initialize_new(1_000, 4_000, ALICE).unwrap();
+
+        assert_noop!(exchange.ensure_launch(), Error::<Test>::InvariantNotNull);
+        assert_eq!(
+            dispatch_result(exchange.divest(1_001, 0, shares, &ALICE)),
+            Err(Error::<Test>::UnderflowOccured.into())
+        );
+        assert_eq!(
+            dispatch_result(exchange.divest(0, 0, 1, &BOB)),
+            Err(Error::<Test>::DoesNotOwnShare.into())
+        );
+        assert_eq!(
+            exchange.ensure_second_asset_amount(4_001, 0),
+            Err(Error::<Test>::InsufficientPool.into())
+        );
+    });
+}
+
+#[test]
+fn price_data_accumulates_prices_over_time() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 4_000_000);
+
+        Timestamp::set_timestamp(10);
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            MAIN,
+            10_000,
+            FIRST_ASSET,
+            0,
+            BOB
+        ));
+
+        let price_data = DexPallet::price_data(FIRST_ASSET, MAIN).unwrap();
+        assert_eq!(price_data.asset_a_pool, 3_960_514);
+        assert_eq!(price_data.asset_b_pool, 1_009_985);
+        // Prices before the swap are accumulated, fractional prices are kept
+        assert_eq!(price_data.price_a_cumulative, 40 * PRICE_ONE);
+        assert_eq!(price_data.price_b_cumulative, 25 * PRICE_ONE / 10);
+        assert_eq!(price_data.last_timestamp, 10);
+
+        assert_eq!(
+            dispatch_result(DexPallet::price_data(MAIN, SECOND_ASSET)),
+            Err(Error::<Test>::ExchangeNotExists.into())
+        );
+    });
+}
+
+#[test]
+fn price_cumulative_wraps_around() {
+    let price_cumulative_last = u128::max_value() - PRICE_ONE + 1;
+
+    let price_cumulative = math::accumulate_price::<Test>(price_cumulative_last, 3, 1, 1).unwrap();
+    assert_eq!(price_cumulative, 2 * PRICE_ONE);
+    assert_eq!(
+        dispatch_result(math::average_price::<Test>(
+            price_cumulative_last,
+            price_cumulative,
+            1
+        )),
+        Ok(3 * PRICE_ONE)
+    );
+
+    // Price, exceeding the balance, saturates
+    assert_eq!(
+        dispatch_result(math::accumulate_price::<Test>(0, u128::max_value(), 1, 1)),
+        Ok(u128::max_value())
+    );
+    assert!(math::accumulate_price::<Test>(0, 1, 0, 1).is_err());
+}
+
+#[test]
+fn exchanges_are_migrated_from_v1() {
+    new_test_ext().execute_with(|| {
+        assert_eq!(DexPallet::storage_version(), Releases::V2);
+        assert_eq!(DexPallet::migrate_to_v2(), 0);
+
+        // Pools, invariant, total shares, last timestamp, integer cumulative prices and shares
+        StorageVersion::put(Releases::V1);
+        let exchange_v1 = (
+            1_000u128,
+            4_000u128,
+            4_000_000u128,
+            2_000u128,
+            60u64,
+            15u128,
+            240u128,
+            vec![(ALICE, 2_000u128)],
+        );
+        sp_io::storage::set(
+            &<Exchanges<Test>>::hashed_key_for(MAIN, FIRST_ASSET),
+            &exchange_v1.encode(),
+        );
+
+        assert_eq!(DexPallet::migrate_to_v2(), MaximumBlockWeight::get());
+        assert_eq!(DexPallet::storage_version(), Releases::V2);
+        let exchange = exchange();
+        assert_eq!(exchange.first_asset_pool(), 1_000);
+        assert_eq!(exchange.second_asset_pool(), 4_000);
+        assert_eq!(exchange.total_shares, 2_000);
+        assert_eq!(exchange.shares_of(&ALICE), 2_000);
+        assert_eq!(exchange.last_timestamp, 60);
+        assert_eq!(exchange.price1_cumulative_last, 15 * PRICE_ONE);
+        assert_eq!(exchange.price2_cumulative_last, 240 * PRICE_ONE);
+
+        // Migration is applied once
+        assert_eq!(DexPallet::migrate_to_v2(), 0);
+    });
+}
+
+#[test]
+fn amplification_ramps_linearly() {
+    let ramp_up = Amplification {
+        initial: 100,
+        future: 200,
+        initial_block: 10u64,
+        future_block: 20,
+    };
+    assert_eq!(ramp_up.at(5), 100);
+    assert_eq!(ramp_up.at(10), 100);
+    assert_eq!(ramp_up.at(15), 150);
+    assert_eq!(ramp_up.at(20), 200);
+    assert_eq!(ramp_up.at(30), 200);
+
+    let ramp_down = Amplification {
+        initial: 200,
+        future: 100,
+        initial_block: 10u64,
+        future_block: 20,
+    };
+    assert_eq!(ramp_down.at(12), 180);
+    assert_eq!(ramp_down.at(20), 100);
+
+    assert_eq!(Amplification::new(100, 10u64).at(0), 100);
+}
+
+#[test]
+fn register_stable_exchange_works() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexPallet::register_stable_exchange(
+            Origin::root(),
+            SECOND_ASSET,
+            FIRST_ASSET,
+            100
+        ));
+
+        // Assets are ordered, pool is not launched yet
+        let stable_exchange = stable_exchange();
+        assert_eq!(stable_exchange.current_amplification(), 100);
+        assert_eq!(stable_exchange.liquidity().total_shares, 0);
+        assert_noop!(
+            DexPallet::swap_to_exact(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000,
+                SECOND_ASSET,
+                0,
+                BOB
+            ),
+            Error::<Test>::ExchangeNotExists
+        );
+
+        assert_ok!(DexPallet::initialize_exchange(
+            Origin::signed(ALICE),
+            SECOND_ASSET,
+            4_000_000,
+            FIRST_ASSET,
+            1_000_000
+        ));
+
+        let stable_exchange = stable_exchange();
+        assert_eq!(stable_exchange.liquidity().first_asset_pool(), 1_000_000);
+        assert_eq!(stable_exchange.liquidity().second_asset_pool(), 4_000_000);
+        assert_eq!(stable_exchange.liquidity().shares_of(&ALICE), 2_000_000);
+        // Constant product exchange is not created
+        assert_eq!(
+            DexPallet::exchanges(FIRST_ASSET, SECOND_ASSET).total_shares,
+            0
+        );
+    });
+}
+
+#[test]
+fn register_stable_exchange_fails() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::register_stable_exchange(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                100
+            ),
+            DispatchError::BadOrigin
+        );
+        assert_noop!(
+            DexPallet::register_stable_exchange(Origin::root(), FIRST_ASSET, FIRST_ASSET, 100),
+            Error::<Test>::InvalidExchange
+        );
+        assert_noop!(
+            DexPallet::register_stable_exchange(Origin::root(), FIRST_ASSET, SECOND_ASSET, 0),
+            Error::<Test>::InvalidAmplification
+        );
+        assert_noop!(
+            DexPallet::register_stable_exchange(
+                Origin::root(),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                MAX_AMPLIFICATION + 1
+            ),
+            Error::<Test>::InvalidAmplification
+        );
+
+        initialize_exchange(1_000_000, 1_000_000);
+        assert_noop!(
+            DexPallet::register_stable_exchange(Origin::root(), MAIN, FIRST_ASSET, 100),
+            Error::<Test>::ExchangeAlreadyExists
+        );
+
+        assert_ok!(DexPallet::register_stable_exchange(
+            Origin::root(),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            100
+        ));
+        assert_noop!(
+            DexPallet::register_stable_exchange(Origin::root(), FIRST_ASSET, SECOND_ASSET, 200),
+            Error::<Test>::ExchangeAlreadyExists
+        );
+    });
+}
+
+#[test]
+fn stable_exchange_swaps_with_lower_slippage() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000, 1_000_000);
+        initialize_stable_exchange(100, 1_000_000, 1_000_000);
+
+        let constant_product_quote = DexPallet::quote(MAIN, 100_000, FIRST_ASSET).unwrap();
+        let stable_quote = DexPallet::quote(FIRST_ASSET, 100_000, SECOND_ASSET).unwrap();
+        assert!(stable_quote > constant_product_quote);
+        assert!(stable_quote < 100_000);
+
+        let invariant = stable_exchange().invariant().unwrap();
+
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            100_000,
+            SECOND_ASSET,
+            stable_quote,
+            BOB
+        ));
+
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + stable_quote
+        );
+        // Half of the 300 fee goes to the treasury
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 150);
+
+        let stable_exchange = stable_exchange();
+        assert_eq!(
+            stable_exchange.liquidity().first_asset_pool(),
+            1_000_000 + 100_000 - 150
+        );
+        assert_eq!(
+            stable_exchange.liquidity().second_asset_pool(),
+            1_000_000 - stable_quote
+        );
+        assert!(stable_exchange.invariant().unwrap() >= invariant);
+
+        // Swap back is routed to the same pool
+        let stable_quote = DexPallet::quote(SECOND_ASSET, 50_000, FIRST_ASSET).unwrap();
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            SECOND_ASSET,
+            50_000,
+            FIRST_ASSET,
+            stable_quote,
+            BOB
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 100_000 + stable_quote
+        );
+    });
+}
+
+#[test]
+fn stable_exchange_prices_are_not_supported() {
+    new_test_ext().execute_with(|| {
+        initialize_stable_exchange(100, 1_000_000, 4_000_000);
+
+        // Price stays near the peg, though the pools ratio is 4
+        let stable_quote = DexPallet::quote(FIRST_ASSET, 1_000, SECOND_ASSET).unwrap();
+        assert!(stable_quote > 1_000 && stable_quote < 1_100);
+
+        assert_eq!(
+            dispatch_result(DexPallet::price_data(FIRST_ASSET, SECOND_ASSET)),
+            Err(Error::<Test>::StablePriceNotSupported.into())
+        );
+        assert_eq!(
+            dispatch_result(DexPallet::spot_price(SECOND_ASSET, FIRST_ASSET)),
+            Err(Error::<Test>::StablePriceNotSupported.into())
+        );
+        assert_eq!(
+            dispatch_result(DexPallet::current_price_cumulative(
+                FIRST_ASSET,
+                SECOND_ASSET
+            )),
+            Err(Error::<Test>::StablePriceNotSupported.into())
+        );
+        assert_noop!(
+            DexPallet::place_conditional_order(
+                Origin::signed(BOB),
+                TriggerCondition::StopLoss,
+                FIRST_ASSET,
+                1_000,
+                SECOND_ASSET,
+                0,
+                PRICE_ONE,
+                100
+            ),
+            Error::<Test>::StablePriceNotSupported
+        );
+    });
+}
+
+#[test]
+fn stable_exchange_liquidity_can_be_invested_and_divested() {
+    new_test_ext().execute_with(|| {
+        initialize_stable_exchange(100, 1_000_000, 4_000_000);
+
+        assert_ok!(DexPallet::invest_liquidity(
+            Origin::signed(BOB),
+            SECOND_ASSET,
+            FIRST_ASSET,
+            1_000
+        ));
+        assert_eq!(stable_exchange().liquidity().shares_of(&BOB), 1_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 500
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE - 2_000
+        );
+
+        assert_ok!(DexPallet::divest_liquidity(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            1_000,
+            500,
+            2_000
+        ));
+        assert_eq!(stable_exchange().liquidity().shares_of(&BOB), 0);
+        assert_eq!(stable_exchange().liquidity().total_shares, 2_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE
+        );
+    });
+}
+
+#[test]
+fn ramp_amplification_works() {
+    new_test_ext().execute_with(|| {
+        initialize_stable_exchange(100, 1_000_000, 1_000_000);
+        let quote = DexPallet::quote(FIRST_ASSET, 100_000, SECOND_ASSET).unwrap();
+
+        assert_ok!(DexPallet::ramp_amplification(
+            Origin::root(),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            200,
+            100
+        ));
+
+        System::set_block_number(50);
+        assert_eq!(stable_exchange().current_amplification(), 150);
+
+        // Higher amplification gives lower slippage
+        System::set_block_number(100);
+        assert_eq!(stable_exchange().current_amplification(), 200);
+        assert!(DexPallet::quote(FIRST_ASSET, 100_000, SECOND_ASSET).unwrap() > quote);
+
+        // New ramp starts from the current amplification
+        System::set_block_number(50);
+        assert_ok!(DexPallet::ramp_amplification(
+            Origin::root(),
+            SECOND_ASSET,
+            FIRST_ASSET,
+            1_000,
+            150
+        ));
+        assert_eq!(
+            stable_exchange().amplification,
+            Amplification {
+                initial: 150,
+                future: 1_000,
+                initial_block: 50,
+                future_block: 150,
+            }
+        );
+    });
+}
+
+#[test]
+fn ramp_amplification_fails() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::ramp_amplification(Origin::root(), FIRST_ASSET, SECOND_ASSET, 200, 100),
+            Error::<Test>::StableExchangeNotExists
+        );
+
+        initialize_stable_exchange(100, 1_000_000, 1_000_000);
+        System::set_block_number(10);
+
+        assert_noop!(
+            DexPallet::ramp_amplification(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                200,
+                100
+            ),
+            DispatchError::BadOrigin
+        );
+        assert_noop!(
+            DexPallet::ramp_amplification(Origin::root(), FIRST_ASSET, SECOND_ASSET, 200, 10),
+            Error::<Test>::InvalidAmplificationRamp
+        );
+        assert_noop!(
+            DexPallet::ramp_amplification(Origin::root(), FIRST_ASSET, SECOND_ASSET, 0, 100),
+            Error::<Test>::InvalidAmplification
+        );
+        assert_noop!(
+            DexPallet::ramp_amplification(Origin::root(), FIRST_ASSET, SECOND_ASSET, 1_001, 100),
+            Error::<Test>::InvalidAmplificationRamp
+        );
+        assert_noop!(
+            DexPallet::ramp_amplification(Origin::root(), FIRST_ASSET, SECOND_ASSET, 9, 100),
+            Error::<Test>::InvalidAmplificationRamp
+        );
+    });
+}
+
+#[test]
+fn pow_fixed_approximates_powers() {
+    let pow = |base: U256, exp: U256| math::pow_fixed::<Test>(base, exp).unwrap();
+    let assert_close = |value: U256, expected: U256| {
+        let error = if value > expected {
+            value - expected
+        } else {
+            expected - value
+        };
+        assert!(
+            error <= U256::from(1_000_000_000),
+            "{} != {}",
+            value,
+            expected
+        );
+    };
+
+    let one = math::ONE;
+    let half = one / 2;
+    // Whole powers are exact
+    assert_eq!(pow(half, one * 3), one / 8);
+    assert_eq!(pow(one * 3 / 2, one), one * 3 / 2);
+    assert_eq!(pow(half, U256::zero()), one);
+
+    assert_close(pow(half, half), U256::from(707_106_781_186_547_524u128));
+    assert_close(
+        pow(one * 3 / 2, one * 5 / 2),
+        U256::from(2_755_675_960_631_075_360u128),
+    );
+    assert_close(
+        pow(one / 4, one / 4),
+        U256::from(707_106_781_186_547_524u128),
+    );
+
+    // Bases are bounded to [0.25, 1.75], where the series converges quickly
+    assert_close(
+        pow(one * 7 / 4, half),
+        U256::from(1_322_875_655_532_295_295u128),
+    );
+    assert!(math::pow_fixed::<Test>(one / 4 - 1, half).is_err());
+    assert!(math::pow_fixed::<Test>(one * 7 / 4 + 1, half).is_err());
+    assert!(math::pow_fixed::<Test>(one * 2, half).is_err());
+    assert!(math::pow_fixed::<Test>(U256::zero(), half).is_err());
+}
+
+#[test]
+fn create_weighted_pool_works() {
+    new_test_ext().execute_with(|| {
+        create_weighted_pool();
+
+        let weighted_pool = weighted_pool();
+        assert_eq!(weighted_pool.total_weight(), 100);
+        assert_eq!(
+            weighted_pools(&weighted_pool),
+            vec![2_000_000_000, 1_000_000_000, 1_000_000_000]
+        );
+        assert_eq!(weighted_pool.total_shares, INITIAL_WEIGHTED_POOL_SHARES);
+        assert_eq!(
+            weighted_pool.shares_of(&ALICE),
+            INITIAL_WEIGHTED_POOL_SHARES
+        );
+
+        assert_eq!(
+            Balances::free_balance(ALICE),
+            INITIAL_BALANCE - 2_000_000_000
+        );
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 1_000_000_000
+        );
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, SECOND_ASSET_ID),
+            INITIAL_BALANCE - 1_000_000_000
+        );
+
+        // Pools of the same assets can coexist
+        create_weighted_pool();
+        assert!(DexPallet::weighted_pools(1).is_some());
+        assert_eq!(DexPallet::next_weighted_pool_id(), 2);
+    });
+}
+
+#[test]
+fn create_weighted_pool_fails() {
+    new_test_ext().execute_with(|| {
+        let create = |assets| DexPallet::create_weighted_pool(Origin::signed(ALICE), assets);
+
+        assert_noop!(
+            create(vec![(MAIN, 50, 1_000)]),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_noop!(
+            create(
+                (1..=9)
+                    .map(|asset_id| (Asset::ParachainAsset(asset_id), 1, 1_000))
+                    .collect()
+            ),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_noop!(
+            create(vec![(FIRST_ASSET, 50, 1_000), (FIRST_ASSET, 50, 1_000)]),
+            Error::<Test>::InvalidWeightedPool
+        );
+        assert_noop!(
+            create(vec![(MAIN, 0, 1_000), (FIRST_ASSET, 50, 1_000)]),
+            Error::<Test>::InvalidWeight
+        );
+        assert_noop!(
+            create(vec![(MAIN, 51, 1_000), (FIRST_ASSET, 50, 1_000)]),
+            Error::<Test>::InvalidWeight
+        );
+        assert_noop!(
+            create(vec![(MAIN, 50, 1_000), (FIRST_ASSET, 50, 0)]),
+            Error::<Test>::LowAssetAmount
+        );
+        assert_noop!(
+            create(vec![
+                (MAIN, 50, 1_000),
+                (FIRST_ASSET, 50, INITIAL_BALANCE + 1)
+            ]),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+        assert_eq!(DexPallet::next_weighted_pool_id(), 0);
+    });
+}
+
+#[test]
+fn weighted_swap_exact_in_works() {
+    new_test_ext().execute_with(|| {
+        create_weighted_pool();
+
+        // Equal weights behave as the constant product pool
+        assert_ok!(DexPallet::swap_exact_in_weighted(
+            Origin::signed(BOB),
+            0,
+            FIRST_ASSET,
+            100_000,
+            SECOND_ASSET,
+            99_690
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 100_000
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + 99_690
+        );
+        // Half of the 300 fee goes to the treasury
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 150);
+        assert_eq!(
+            weighted_pools(&weighted_pool()),
+            vec![
+                2_000_000_000,
+                1_000_000_000 + 100_000 - 150,
+                1_000_000_000 - 99_690
+            ]
+        );
+
+        // Heavier asset in buys more of the lighter one
+        assert_ok!(DexPallet::swap_exact_in_weighted(
+            Origin::signed(BOB),
+            0,
+            MAIN,
+            100_000,
+            SECOND_ASSET,
+            99_682
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + 99_690 + 99_682
+        );
+        assert_eq!(Balances::free_balance(TREASURY), 150);
+    });
+}
+
+#[test]
+fn weighted_swap_exact_out_works() {
+    new_test_ext().execute_with(|| {
+        create_weighted_pool();
+
+        assert_noop!(
+            DexPallet::swap_exact_out_weighted(
+                Origin::signed(BOB),
+                0,
+                FIRST_ASSET,
+                99_999,
+                SECOND_ASSET,
+                99_690
+            ),
+            Error::<Test>::AssetAmountAboveLimit
+        );
+
+        // Inverse of the exact in swap
+        assert_ok!(DexPallet::swap_exact_out_weighted(
+            Origin::signed(BOB),
+            0,
+            FIRST_ASSET,
+            100_000,
+            SECOND_ASSET,
+            99_690
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 100_000
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + 99_690
+        );
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 150);
+        assert_eq!(
+            weighted_pools(&weighted_pool()),
+            vec![
+                2_000_000_000,
+                1_000_000_000 + 100_000 - 150,
+                1_000_000_000 - 99_690
+            ]
+        );
+    });
+}
+
+#[test]
+fn weighted_swap_fails() {
+    new_test_ext().execute_with(|| {
+        let swap = |asset_in, asset_in_amount, asset_out, min_asset_out_amount| {
+            DexPallet::swap_exact_in_weighted(
+                Origin::signed(BOB),
+                0,
+                asset_in,
+                asset_in_amount,
+                asset_out,
+                min_asset_out_amount,
+            )
+        };
+
+        assert_noop!(
+            swap(MAIN, 1_000, FIRST_ASSET, 0),
+            Error::<Test>::WeightedPoolNotExists
+        );
+
+        assert_ok!(DexPallet::create_weighted_pool(
+            Origin::signed(ALICE),
+            vec![(MAIN, 50, 1_000_000_000), (FIRST_ASSET, 50, 1_000_000_000)]
+        ));
+
+        assert_noop!(
+            swap(MAIN, 1_000, SECOND_ASSET, 0),
+            Error::<Test>::AssetNotInWeightedPool
+        );
+        assert_noop!(swap(MAIN, 1_000, MAIN, 0), Error::<Test>::InvalidExchange);
+        assert_noop!(
+            swap(MAIN, 100_000, FIRST_ASSET, 99_701),
+            Error::<Test>::AssetAmountBelowExpectation
+        );
+        assert_noop!(
+            swap(MAIN, 500_000_001, FIRST_ASSET, 0),
+            Error::<Test>::MaxInRatioExceeded
+        );
+        assert_noop!(
+            swap(FIRST_ASSET, INITIAL_BALANCE + 1, MAIN, 0),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+        assert_noop!(
+            DexPallet::swap_exact_out_weighted(
+                Origin::signed(BOB),
+                0,
+                MAIN,
+                INITIAL_BALANCE,
+                FIRST_ASSET,
+                333_333_334
+            ),
+            Error::<Test>::MaxOutRatioExceeded
+        );
+    });
+}
+
+#[test]
+fn weighted_pool_can_be_joined_and_exited_proportionally() {
+    new_test_ext().execute_with(|| {
+        create_weighted_pool();
+        let shares = INITIAL_WEIGHTED_POOL_SHARES / 10;
+
+        assert_noop!(
+            DexPallet::join_weighted_pool(Origin::signed(BOB), 0, shares, vec![Balance::MAX; 2]),
+            Error::<Test>::AssetAmountsMismatch
+        );
+        assert_noop!(
+            DexPallet::join_weighted_pool(
+                Origin::signed(BOB),
+                0,
+                shares,
+                vec![200_000_000, 100_000_000, 99_999_999]
+            ),
+            Error::<Test>::AssetAmountAboveLimit
+        );
+
+        assert_ok!(DexPallet::join_weighted_pool(
+            Origin::signed(BOB),
+            0,
+            shares,
+            vec![200_000_000, 100_000_000, 100_000_000]
+        ));
+        let weighted_pool = weighted_pool();
+        assert_eq!(
+            weighted_pools(&weighted_pool),
+            vec![2_200_000_000, 1_100_000_000, 1_100_000_000]
+        );
+        assert_eq!(weighted_pool.shares_of(&BOB), shares);
+        assert_eq!(
+            weighted_pool.total_shares,
+            INITIAL_WEIGHTED_POOL_SHARES + shares
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE - 100_000_000
+        );
+
+        assert_noop!(
+            DexPallet::exit_weighted_pool(
+                Origin::signed(BOB),
+                0,
+                shares,
+                vec![200_000_000, 100_000_001, 0]
+            ),
+            Error::<Test>::AssetAmountBelowExpectation
+        );
+        assert_noop!(
+            DexPallet::exit_weighted_pool(Origin::signed(BOB), 0, shares + 1, vec![0; 3]),
+            Error::<Test>::InsufficientShares
+        );
+
+        assert_ok!(DexPallet::exit_weighted_pool(
+            Origin::signed(BOB),
+            0,
+            shares,
+            vec![200_000_000, 100_000_000, 100_000_000]
+        ));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE
+        );
+        assert_eq!(weighted_pool().shares_of(&BOB), 0);
+
+        // Pool is dropped with its last shares
+        assert_ok!(DexPallet::exit_weighted_pool(
+            Origin::signed(ALICE),
+            0,
+            INITIAL_WEIGHTED_POOL_SHARES,
+            vec![0; 3]
+        ));
+        assert!(DexPallet::weighted_pools(0).is_none());
+        assert_eq!(Balances::free_balance(ALICE), INITIAL_BALANCE);
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, SECOND_ASSET_ID),
+            INITIAL_BALANCE
+        );
+    });
+}
+
+#[test]
+fn weighted_pool_can_be_joined_and_exited_with_single_asset() {
+    new_test_ext().execute_with(|| {
+        create_weighted_pool();
+
+        assert_noop!(
+            DexPallet::join_weighted_pool_single_asset(
+                Origin::signed(BOB),
+                0,
+                FIRST_ASSET,
+                100_000,
+                2_494_281_676_832_301
+            ),
+            Error::<Test>::SharesBelowExpectation
+        );
+
+        // Only three quarters of the amount are charged with the fee: 225, of which 112 go to the treasury
+        assert_ok!(DexPallet::join_weighted_pool_single_asset(
+            Origin::signed(BOB),
+            0,
+            FIRST_ASSET,
+            100_000,
+            2_494_281_676_832_300
+        ));
+        let weighted_pool = weighted_pool();
+        assert_eq!(weighted_pool.shares_of(&BOB), 2_494_281_676_832_300);
+        assert_eq!(
+            weighted_pools(&weighted_pool),
+            vec![2_000_000_000, 1_000_000_000 + 100_000 - 112, 1_000_000_000]
+        );
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 112);
+
+        // Round trip costs the fees
+        assert_ok!(DexPallet::exit_weighted_pool_single_asset(
+            Origin::signed(BOB),
+            0,
+            2_494_281_676_832_300,
+            FIRST_ASSET,
+            99_000
+        ));
+        let bob_balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+        assert!(bob_balance < INITIAL_BALANCE - 400);
+        assert!(bob_balance > INITIAL_BALANCE - 1_000);
+        assert_eq!(weighted_pool().shares_of(&BOB), 0);
+        assert!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID) > 112);
+
+        assert_noop!(
+            DexPallet::exit_weighted_pool_single_asset(
+                Origin::signed(ALICE),
+                0,
+                INITIAL_WEIGHTED_POOL_SHARES / 5,
+                FIRST_ASSET,
+                0
+            ),
+            Error::<Test>::MaxOutRatioExceeded
+        );
+        // Shares are bounded before the power is approximated
+        assert_noop!(
+            DexPallet::exit_weighted_pool_single_asset(
+                Origin::signed(ALICE),
+                0,
+                INITIAL_WEIGHTED_POOL_SHARES / 3 + 1,
+                MAIN,
+                0
+            ),
+            Error::<Test>::MaxOutRatioExceeded
+        );
+        assert_noop!(
+            DexPallet::join_weighted_pool_single_asset(
+                Origin::signed(BOB),
+                0,
+                SECOND_ASSET,
+                500_000_001,
+                0
+            ),
+            Error::<Test>::MaxInRatioExceeded
+        );
+    });
+}
+
+#[test]
+fn liquidity_bootstrapping_weights_shift_linearly() {
+    new_test_ext().execute_with(|| {
+        create_liquidity_bootstrapping_pool();
+
+        let weights = |weighted_pool: WeightedPool<Test>| {
+            weighted_pool
+                .assets
+                .iter()
+                .map(|weighted_asset| weighted_asset.weight)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            weights(weighted_pool()),
+            vec![90 * WEIGHT_SCALE, 10 * WEIGHT_SCALE]
+        );
+
+        let liquidity_bootstrapping = DexPallet::liquidity_bootstrapping_pools(0).unwrap();
+        assert_eq!(liquidity_bootstrapping.owner, ALICE);
+        assert!(!liquidity_bootstrapping.paused);
+        assert_eq!(
+            liquidity_bootstrapping.weights_at(5),
+            vec![90 * WEIGHT_SCALE, 10 * WEIGHT_SCALE]
+        );
+        assert_eq!(
+            liquidity_bootstrapping.weights_at(12),
+            vec![82 * WEIGHT_SCALE, 18 * WEIGHT_SCALE]
+        );
+        assert_eq!(
+            liquidity_bootstrapping.weights_at(20),
+            vec![50 * WEIGHT_SCALE, 50 * WEIGHT_SCALE]
+        );
+        assert_eq!(
+            liquidity_bootstrapping.weights_at(100),
+            vec![50 * WEIGHT_SCALE, 50 * WEIGHT_SCALE]
+        );
+
+        // Weights are refreshed by the pool operations
+        System::set_block_number(15);
+        assert_ok!(DexPallet::swap_exact_in_weighted(
+            Origin::signed(BOB),
+            0,
+            MAIN,
+            1_000,
+            FIRST_ASSET,
+            0
+        ));
+        assert_eq!(
+            weights(weighted_pool()),
+            vec![70 * WEIGHT_SCALE, 30 * WEIGHT_SCALE]
+        );
+    });
+}
+
+#[test]
+fn create_liquidity_bootstrapping_pool_fails() {
+    new_test_ext().execute_with(|| {
+        let create = |assets, start_block, end_block| {
+            DexPallet::create_liquidity_bootstrapping_pool(
+                Origin::signed(ALICE),
+                assets,
+                start_block,
+                end_block,
+            )
+        };
+        let assets = vec![(FIRST_ASSET, 90, 50, 1_000_000), (MAIN, 10, 50, 100_000)];
+
+        assert_noop!(
+            create(assets.clone(), 10, 20),
+            Error::<Test>::NotLiquidityBootstrappingPoolCreator
+        );
+        assert_noop!(
+            DexPallet::set_liquidity_bootstrapping_pool_creator(Origin::signed(ALICE), ALICE, true),
+            DispatchError::BadOrigin
+        );
+
+        assert_ok!(DexPallet::set_liquidity_bootstrapping_pool_creator(
+            Origin::root(),
+            ALICE,
+            true
+        ));
+        assert!(DexPallet::liquidity_bootstrapping_pool_creators(ALICE));
+
+        System::set_block_number(5);
+        assert_noop!(
+            create(assets.clone(), 10, 10),
+            Error::<Test>::InvalidLiquidityBootstrappingSchedule
+        );
+        assert_noop!(
+            create(assets.clone(), 4, 20),
+            Error::<Test>::InvalidLiquidityBootstrappingSchedule
+        );
+        assert_noop!(
+            create(
+                vec![(FIRST_ASSET, 90, 60, 1_000_000), (MAIN, 10, 50, 100_000)],
+                10,
+                20
+            ),
+            Error::<Test>::InvalidWeight
+        );
+        assert_noop!(
+            create(
+                vec![(FIRST_ASSET, 90, 100, 1_000_000), (MAIN, 10, 0, 100_000)],
+                10,
+                20
+            ),
+            Error::<Test>::InvalidWeight
+        );
+        assert_noop!(
+            create(vec![(FIRST_ASSET, 90, 50, 1_000_000)], 10, 20),
+            Error::<Test>::InvalidWeightedPool
+        );
+
+        assert_ok!(DexPallet::set_liquidity_bootstrapping_pool_creator(
+            Origin::root(),
+            ALICE,
+            false
+        ));
+        assert_noop!(
+            create(assets, 10, 20),
+            Error::<Test>::NotLiquidityBootstrappingPoolCreator
+        );
+    });
+}
+
+#[test]
+fn liquidity_bootstrapping_price_declines_over_time() {
+    new_test_ext().execute_with(|| {
+        create_liquidity_bootstrapping_pool();
+        let buy = || {
+            let balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+            assert_ok!(DexPallet::swap_exact_in_weighted(
+                Origin::signed(BOB),
+                0,
+                MAIN,
+                1_000_000,
+                FIRST_ASSET,
+                0
+            ));
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID) - balance
+        };
+
+        // Spot price is 0.9 MAIN for FIRST_ASSET at start and 0.1 at the end
+        System::set_block_number(10);
+        let start_amount = buy();
+        assert!(start_amount > 1_000_000 && start_amount < 1_112_000);
+
+        System::set_block_number(20);
+        let end_amount = buy();
+        assert!(end_amount > 9_000_000 && end_amount < 10_000_000);
+
+        // Treasury fee is charged as in the other pools
+        assert_eq!(Balances::free_balance(TREASURY), 3_000);
+    });
+}
+
+#[test]
+fn liquidity_bootstrapping_pool_can_be_paused_by_owner() {
+    new_test_ext().execute_with(|| {
+        create_liquidity_bootstrapping_pool();
+
+        assert_noop!(
+            DexPallet::pause_liquidity_bootstrapping_pool(Origin::signed(BOB), 0),
+            Error::<Test>::NotLiquidityBootstrappingPoolOwner
+        );
+        assert_noop!(
+            DexPallet::pause_liquidity_bootstrapping_pool(Origin::signed(ALICE), 1),
+            Error::<Test>::LiquidityBootstrappingPoolNotExists
+        );
+
+        assert_ok!(DexPallet::pause_liquidity_bootstrapping_pool(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert!(DexPallet::liquidity_bootstrapping_pools(0).unwrap().paused);
+
+        assert_noop!(
+            DexPallet::swap_exact_in_weighted(Origin::signed(BOB), 0, MAIN, 1_000, FIRST_ASSET, 0),
+            Error::<Test>::LiquidityBootstrappingPoolPaused
+        );
+        assert_noop!(
+            DexPallet::swap_exact_out_weighted(
+                Origin::signed(BOB),
+                0,
+                MAIN,
+                INITIAL_BALANCE,
+                FIRST_ASSET,
+                1_000
+            ),
+            Error::<Test>::LiquidityBootstrappingPoolPaused
+        );
+
+        assert_noop!(
+            DexPallet::unpause_liquidity_bootstrapping_pool(Origin::signed(BOB), 0),
+            Error::<Test>::NotLiquidityBootstrappingPoolOwner
+        );
+        assert_ok!(DexPallet::unpause_liquidity_bootstrapping_pool(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_ok!(DexPallet::swap_exact_in_weighted(
+            Origin::signed(BOB),
+            0,
+            MAIN,
+            1_000,
+            FIRST_ASSET,
+            0
+        ));
+    });
+}
+
+#[test]
+fn liquidity_bootstrapping_pool_liquidity_is_provided_by_owner() {
+    new_test_ext().execute_with(|| {
+        create_liquidity_bootstrapping_pool();
+        let shares = INITIAL_WEIGHTED_POOL_SHARES / 10;
+
+        assert_noop!(
+            DexPallet::join_weighted_pool(Origin::signed(BOB), 0, shares, vec![Balance::MAX; 2]),
+            Error::<Test>::NotLiquidityBootstrappingPoolOwner
+        );
+        assert_noop!(
+            DexPallet::join_weighted_pool_single_asset(Origin::signed(BOB), 0, MAIN, 1_000, 0),
+            Error::<Test>::NotLiquidityBootstrappingPoolOwner
+        );
+        assert_ok!(DexPallet::join_weighted_pool(
+            Origin::signed(ALICE),
+            0,
+            shares,
+            vec![Balance::MAX; 2]
+        ));
+
+        assert_noop!(
+            DexPallet::withdraw_liquidity_bootstrapping_pool(Origin::signed(BOB), 0),
+            Error::<Test>::NotLiquidityBootstrappingPoolOwner
+        );
+
+        assert_ok!(DexPallet::swap_exact_in_weighted(
+            Origin::signed(BOB),
+            0,
+            MAIN,
+            1_000_000,
+            FIRST_ASSET,
+            0
+        ));
+        let pools = weighted_pools(&weighted_pool());
+        let main_balance = Balances::free_balance(ALICE);
+        let asset_balance = DexPallet::asset_balances(ALICE, FIRST_ASSET_ID);
+
+        assert_ok!(DexPallet::withdraw_liquidity_bootstrapping_pool(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert!(DexPallet::weighted_pools(0).is_none());
+        assert!(DexPallet::liquidity_bootstrapping_pools(0).is_none());
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            asset_balance + pools[0]
+        );
+        assert_eq!(Balances::free_balance(ALICE), main_balance + pools[1]);
+    });
+}
+
+#[test]
+fn tick_price_conversion_round_trips() {
+    let sqrt_price = |tick: i32| sqrt_price_at_tick::<Test>(tick).unwrap();
+
+    assert_eq!(sqrt_price(0), math::ONE);
+    assert_eq!(sqrt_price(1), U256::from(1_000_049_998_750_062_496u128));
+    assert_eq!(sqrt_price(-1000), U256::from(951_231_802_418_721_075u128));
+
+    for tick in [-400_000, -1000, -1, 0, 1, 10, 1000, 399_999].iter() {
+        let tick = *tick;
+        assert_eq!(tick_at_sqrt_price::<Test>(sqrt_price(tick)).unwrap(), tick);
+        // Price, slightly below the tick one, belongs to the previous tick
+        assert_eq!(
+            tick_at_sqrt_price::<Test>(sqrt_price(tick + 1) - 1).unwrap(),
+            tick
+        );
+    }
+
+    assert!(sqrt_price_at_tick::<Test>(400_001).is_err());
+    assert!(sqrt_price_at_tick::<Test>(-400_001).is_err());
+}
+
+#[test]
+fn create_concentrated_pool_works() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DexPallet::create_concentrated_pool(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            10,
+            1000
+        ));
+        let concentrated_pool = concentrated_pool();
+        assert_eq!(concentrated_pool.tick_spacing, 10);
+        assert_eq!(concentrated_pool.current_tick, 1000);
+        assert_eq!(
+            concentrated_pool.sqrt_price,
+            sqrt_price_at_tick::<Test>(1000).unwrap()
+        );
+        assert_eq!(concentrated_pool.liquidity, 0);
+
+        // Price of the reversed pair is inverted
+        assert_ok!(DexPallet::create_concentrated_pool(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            MAIN,
+            10,
+            1000
+        ));
+        assert_eq!(
+            DexPallet::concentrated_pools(MAIN, FIRST_ASSET)
+                .unwrap()
+                .current_tick,
+            -1000
+        );
+    });
+}
+
+#[test]
+fn create_concentrated_pool_fails() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DexPallet::create_concentrated_pool(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                0,
+                0
+            ),
+            Error::<Test>::InvalidTickSpacing
+        );
+        assert_noop!(
+            DexPallet::create_concentrated_pool(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                10_001,
+                0
+            ),
+            Error::<Test>::InvalidTickSpacing
+        );
+        assert_noop!(
+            DexPallet::create_concentrated_pool(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                10,
+                400_001
+            ),
+            Error::<Test>::InvalidTick
+        );
+        assert_noop!(
+            DexPallet::create_concentrated_pool(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                FIRST_ASSET,
+                10,
+                0
+            ),
+            Error::<Test>::InvalidExchange
+        );
+
+        create_concentrated_pool();
+        assert_noop!(
+            DexPallet::create_concentrated_pool(
+                Origin::signed(BOB),
+                SECOND_ASSET,
+                FIRST_ASSET,
+                10,
+                0
+            ),
+            Error::<Test>::ConcentratedPoolAlreadyExists
+        );
+    });
+}
+
+#[test]
+fn concentrated_position_can_be_minted_in_and_out_of_range() {
+    new_test_ext().execute_with(|| {
+        create_concentrated_pool();
+
+        // Position in range holds both assets
+        let pool = concentrated_pool();
+        assert_eq!(pool.liquidity, 1_000_000_000);
+        assert_eq!(pool.first_asset_pool(), 48_768_198);
+        assert_eq!(pool.second_asset_pool(), 48_768_198);
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 48_768_198
+        );
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, SECOND_ASSET_ID),
+            INITIAL_BALANCE - 48_768_198
+        );
+        let position = concentrated_position(0).unwrap();
+        assert_eq!(position.owner, ALICE);
+        assert_eq!((position.lower_tick, position.upper_tick), (-1000, 1000));
+        assert_eq!(
+            concentrated_tick(-1000).unwrap().liquidity_lower,
+            1_000_000_000
+        );
+        assert_eq!(
+            concentrated_tick(1000).unwrap().liquidity_upper,
+            1_000_000_000
+        );
+
+        // Position above the price holds only the first asset, below - only the second one
+        assert_ok!(DexPallet::mint_concentrated_position(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            1000,
+            2000,
+            1_000_000_000,
+            46_389_861,
+            0
+        ));
+        // Ticks and amounts of the reversed pair are reversed
+        assert_ok!(DexPallet::mint_concentrated_position(
+            Origin::signed(BOB),
+            SECOND_ASSET,
+            FIRST_ASSET,
+            1000,
+            2000,
+            1_000_000_000,
+            46_389_861,
+            0
+        ));
+        let pool = concentrated_pool();
+        let position = concentrated_position(2).unwrap();
+        assert_eq!((position.lower_tick, position.upper_tick), (-2000, -1000));
+        assert_eq!(pool.liquidity, 1_000_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 46_389_861
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE - 46_389_861
+        );
+    });
+}
+
+#[test]
+fn mint_concentrated_position_fails() {
+    new_test_ext().execute_with(|| {
+        let mint = |lower_tick, upper_tick, liquidity, max_amount| {
+            DexPallet::mint_concentrated_position(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                lower_tick,
+                upper_tick,
+                liquidity,
+                max_amount,
+                max_amount,
+            )
+        };
+
+        assert_noop!(
+            mint(-1000, 1000, 1_000, Balance::MAX),
+            Error::<Test>::ConcentratedPoolNotExists
+        );
+
+        create_concentrated_pool();
+        assert_noop!(
+            mint(-1000, 1000, 0, Balance::MAX),
+            Error::<Test>::LowLiquidity
+        );
+        assert_noop!(
+            mint(1000, 1000, 1_000, Balance::MAX),
+            Error::<Test>::InvalidTickRange
+        );
+        assert_noop!(
+            mint(-1005, 1000, 1_000, Balance::MAX),
+            Error::<Test>::InvalidTick
+        );
+        assert_noop!(
+            mint(-400_010, 1000, 1_000, Balance::MAX),
+            Error::<Test>::InvalidTick
+        );
+        assert_noop!(
+            mint(-1000, 1000, 1_000_000_000, 48_768_197),
+            Error::<Test>::AssetAmountAboveLimit
+        );
+        assert_noop!(
+            mint(-1000, 1000, 100_000_000_000_000, Balance::MAX),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+    });
+}
+
+#[test]
+fn concentrated_swap_fees_are_collected_by_position() {
+    new_test_ext().execute_with(|| {
+        create_concentrated_pool();
+
+        assert_noop!(
+            DexPallet::swap_concentrated(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                100_000,
+                SECOND_ASSET,
+                99_691
+            ),
+            Error::<Test>::AssetAmountBelowExpectation
+        );
+
+        // Fee is 300, of which 150 go to the treasury.
+        // Swap crosses the bound of the bitmap word at the tick 0 and stops before the tick -1000.
+        let post_info = DexPallet::swap_concentrated(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            100_000,
+            SECOND_ASSET,
+            99_690,
+        )
+        .unwrap();
+        assert_eq!(
+            post_info.actual_weight,
+            Some(DexPallet::concentrated_swap_weight(2))
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + 99_690
+        );
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 150);
+        let concentrated_pool = concentrated_pool();
+        assert_eq!(concentrated_pool.current_tick, -2);
+        assert_eq!(
+            concentrated_pool.first_asset_pool(),
+            48_768_198 + 100_000 - 150
+        );
+        assert_eq!(concentrated_pool.second_asset_pool(), 48_768_198 - 99_690);
+
+        assert_noop!(
+            DexPallet::collect_fees(Origin::signed(BOB), FIRST_ASSET, SECOND_ASSET, 0),
+            Error::<Test>::NotPositionOwner
+        );
+        let first_asset_balance = DexPallet::asset_balances(ALICE, FIRST_ASSET_ID);
+        assert_ok!(DexPallet::collect_fees(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            0
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            first_asset_balance + 150
+        );
+    });
+}
+
+#[test]
+fn concentrated_position_can_be_burned() {
+    new_test_ext().execute_with(|| {
+        create_concentrated_pool();
+        assert_ok!(DexPallet::swap_concentrated(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            100_000,
+            SECOND_ASSET,
+            0
+        ));
+
+        assert_noop!(
+            DexPallet::burn_concentrated_position(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                0,
+                1_000,
+                0,
+                0
+            ),
+            Error::<Test>::NotPositionOwner
+        );
+        assert_noop!(
+            DexPallet::burn_concentrated_position(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                1,
+                1_000,
+                0,
+                0
+            ),
+            Error::<Test>::PositionNotExists
+        );
+        assert_noop!(
+            DexPallet::burn_concentrated_position(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                0,
+                1_000_000_001,
+                0,
+                0
+            ),
+            Error::<Test>::InsufficientPositionLiquidity
+        );
+        assert_noop!(
+            DexPallet::burn_concentrated_position(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                0,
+                1_000_000_000,
+                48_867_897,
+                0
+            ),
+            Error::<Test>::FirstAssetAmountBelowExpectation
+        );
+
+        let first_asset_balance = DexPallet::asset_balances(ALICE, FIRST_ASSET_ID);
+        let second_asset_balance = DexPallet::asset_balances(ALICE, SECOND_ASSET_ID);
+        assert_ok!(DexPallet::burn_concentrated_position(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            0,
+            1_000_000_000,
+            48_867_896,
+            48_668_507
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            first_asset_balance + 48_867_896
+        );
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, SECOND_ASSET_ID),
+            second_asset_balance + 48_668_507
+        );
+        assert_eq!(concentrated_pool().liquidity, 0);
+
+        // Fees stay in the position until collected, position without liquidity is closed then
+        assert_ok!(DexPallet::collect_fees(
+            Origin::signed(ALICE),
+            SECOND_ASSET,
+            FIRST_ASSET,
+            0
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            first_asset_balance + 48_867_896 + 150
+        );
+        assert!(concentrated_position(0).is_none());
+        assert!(concentrated_tick(-1000).is_none());
+        assert_eq!(
+            DexPallet::concentrated_tick_bitmaps((FIRST_ASSET, SECOND_ASSET), -1),
+            U256::zero()
+        );
+        assert_noop!(
+            DexPallet::collect_fees(Origin::signed(ALICE), FIRST_ASSET, SECOND_ASSET, 0),
+            Error::<Test>::PositionNotExists
+        );
+    });
+}
+
+#[test]
+fn concentrated_swap_crosses_initialized_ticks() {
+    new_test_ext().execute_with(|| {
+        create_concentrated_pool();
+        assert_ok!(DexPallet::mint_concentrated_position(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            -2000,
+            -1000,
+            2_000_000_000,
+            Balance::MAX,
+            Balance::MAX
+        ));
+
+        // Fee of the swap, crossing the tick -1000, is shared by both positions
+        assert_ok!(DexPallet::swap_concentrated(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            60_000_000,
+            SECOND_ASSET,
+            56_474_636
+        ));
+        let pool = concentrated_pool();
+        assert_eq!(pool.current_tick, -1082);
+        assert_eq!(pool.liquidity, 2_000_000_000);
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 90_000);
+
+        let collect = |who: AccountId, position_id| {
+            let first_asset_balance = DexPallet::asset_balances(who, FIRST_ASSET_ID);
+            let second_asset_balance = DexPallet::asset_balances(who, SECOND_ASSET_ID);
+            assert_ok!(DexPallet::collect_fees(
+                Origin::signed(who),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                position_id
+            ));
+            (
+                DexPallet::asset_balances(who, FIRST_ASSET_ID) - first_asset_balance,
+                DexPallet::asset_balances(who, SECOND_ASSET_ID) - second_asset_balance,
+            )
+        };
+        assert_eq!(collect(ALICE, 0), (77_134, 0));
+        assert_eq!(collect(BOB, 1), (12_866, 0));
+
+        // Crossing back activates the first position again
+        assert_ok!(DexPallet::swap_concentrated(
+            Origin::signed(BOB),
+            SECOND_ASSET,
+            10_000_000,
+            FIRST_ASSET,
+            11_047_198
+        ));
+        let pool = concentrated_pool();
+        assert_eq!(pool.current_tick, -953);
+        assert_eq!(pool.liquidity, 1_000_000_000);
+        assert_eq!(collect(ALICE, 0), (0, 3_406));
+        assert_eq!(collect(BOB, 1), (0, 11_594));
+
+        // Below the lowest initialized tick, the swap crosses empty bitmap words until the limit
+        assert_noop!(
+            DexPallet::swap_concentrated(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000_000_000,
+                SECOND_ASSET,
+                0
+            ),
+            Error::<Test>::TooManyTicksCrossed
+        );
+    });
+}
+
+#[test]
+fn concentrated_swap_fails_beyond_price_range() {
+    new_test_ext().execute_with(|| {
+        // Bitmap word covers the whole price range with the maximum spacing
+        assert_ok!(DexPallet::create_concentrated_pool(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            10_000,
+            0
+        ));
+        assert_ok!(DexPallet::mint_concentrated_position(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            SECOND_ASSET,
+            -10_000,
+            10_000,
+            1_000_000_000,
+            Balance::MAX,
+            Balance::MAX
+        ));
+
+        assert_noop!(
+            DexPallet::swap_concentrated(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                10_000_000_000,
+                SECOND_ASSET,
+                0
+            ),
+            Error::<Test>::InsufficientPool
+        );
+        assert_noop!(
+            DexPallet::swap_concentrated(
+                Origin::signed(BOB),
+                SECOND_ASSET,
+                10_000_000_000,
+                FIRST_ASSET,
+                0
+            ),
+            Error::<Test>::InsufficientPool
+        );
+    });
+}
+
+// ALICE sells FIRST_ASSET for MAIN at the price of at least 1.05, while the pool price is 1
+fn place_limit_order() {
+    initialize_exchange(1_000_000_000, 1_000_000_000);
+    assert_ok!(DexPallet::place_limit_order(
+        Origin::signed(ALICE),
+        FIRST_ASSET,
+        1_000_000,
+        MAIN,
+        1_050_000,
+        1_000,
+        100
+    ));
+}
+
+#[test]
+fn place_limit_order_works() {
+    new_test_ext().execute_with(|| {
+        place_limit_order();
+
+        let limit_order = DexPallet::limit_orders(0).unwrap();
+        assert_eq!(limit_order.owner, ALICE);
+        assert_eq!(limit_order.asset_in_amount, 1_000_000);
+        assert_eq!(limit_order.min_asset_out_amount, 1_050_000);
+        assert_eq!(limit_order.bounty, 1_000);
+        assert_eq!(DexPallet::next_limit_order_id(), 1);
+        // Amount and bounty are escrowed
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 1_000_000_000 - 1_001_000
+        );
+    });
+}
+
+#[test]
+fn place_limit_order_fails() {
+    new_test_ext().execute_with(|| {
+        let place = |asset_out, asset_in_amount, min_asset_out_amount, expiry| {
+            DexPallet::place_limit_order(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                asset_in_amount,
+                asset_out,
+                min_asset_out_amount,
+                1_000,
+                expiry,
+            )
+        };
+
+        assert_noop!(
+            place(FIRST_ASSET, 1_000, 1_000, 100),
+            Error::<Test>::InvalidExchange
+        );
+        assert_noop!(place(MAIN, 0, 1_000, 100), Error::<Test>::LowAssetAmount);
+        assert_noop!(place(MAIN, 1_000, 0, 100), Error::<Test>::LowAssetAmount);
+        assert_noop!(
+            place(MAIN, 1_000, 1_000, 0),
+            Error::<Test>::LimitOrderExpired
+        );
+        // Bounty is escrowed as well
+        assert_noop!(
+            place(MAIN, INITIAL_BALANCE, 1_000, 100),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+    });
+}
+
+#[test]
+fn limit_order_is_filled_by_keepers_once_price_reaches_limit() {
+    new_test_ext().execute_with(|| {
+        place_limit_order();
+
+        assert_noop!(
+            DexPallet::fill_limit_order(Origin::signed(BOB), 0, 1_000_000),
+            Error::<Test>::FirstAssetAmountBelowExpectation
+        );
+        assert_noop!(
+            DexPallet::fill_limit_order(Origin::signed(BOB), 0, 0),
+            Error::<Test>::InvalidFillAmount
+        );
+        assert_noop!(
+            DexPallet::fill_limit_order(Origin::signed(BOB), 0, 1_000_001),
+            Error::<Test>::InvalidFillAmount
+        );
+        assert_noop!(
+            DexPallet::fill_limit_order(Origin::signed(BOB), 1, 1_000),
+            Error::<Test>::LimitOrderNotExists
+        );
+
+        // Price of FIRST_ASSET rises to about 1.21
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            MAIN,
+            100_000_000,
+            FIRST_ASSET,
+            0,
+            BOB
+        ));
+
+        let main_balance = Balances::free_balance(ALICE);
+        let bob_asset_balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+        assert_ok!(DexPallet::fill_limit_order(Origin::signed(BOB), 0, 500_000));
+        assert_eq!(Balances::free_balance(ALICE), main_balance + 602_607);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            bob_asset_balance + 500
+        );
+        let limit_order = DexPallet::limit_orders(0).unwrap();
+        assert_eq!(limit_order.asset_in_amount, 500_000);
+        assert_eq!(limit_order.min_asset_out_amount, 525_000);
+        assert_eq!(limit_order.bounty, 500);
+
+        assert_ok!(DexPallet::fill_limit_order(Origin::signed(BOB), 0, 500_000));
+        assert_eq!(
+            Balances::free_balance(ALICE),
+            main_balance + 602_607 + 601_947
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            bob_asset_balance + 1_000
+        );
+        // Swaps are charged with the treasury fee as usual
+        assert_eq!(DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID), 1_500);
+        assert!(DexPallet::limit_orders(0).is_none());
+    });
+}
+
+#[test]
+fn limit_order_can_be_cancelled() {
+    new_test_ext().execute_with(|| {
+        place_limit_order();
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            MAIN,
+            100_000_000,
+            FIRST_ASSET,
+            0,
+            BOB
+        ));
+        assert_ok!(DexPallet::fill_limit_order(Origin::signed(BOB), 0, 500_000));
+
+        assert_noop!(
+            DexPallet::cancel_limit_order(Origin::signed(BOB), 0),
+            Error::<Test>::NotLimitOrderOwner
+        );
+
+        // Unfilled remainder and bounty are refunded
+        let asset_balance = DexPallet::asset_balances(ALICE, FIRST_ASSET_ID);
+        assert_ok!(DexPallet::cancel_limit_order(Origin::signed(ALICE), 0));
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            asset_balance + 500_500
+        );
+        assert!(DexPallet::limit_orders(0).is_none());
+        assert_noop!(
+            DexPallet::cancel_limit_order(Origin::signed(ALICE), 0),
+            Error::<Test>::LimitOrderNotExists
+        );
+    });
+}
+
+#[test]
+fn expired_limit_order_can_be_cancelled_by_anyone() {
+    new_test_ext().execute_with(|| {
+        place_limit_order();
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(BOB),
+            MAIN,
+            100_000_000,
+            FIRST_ASSET,
+            0,
+            BOB
+        ));
+
+        System::set_block_number(101);
+        assert_noop!(
+            DexPallet::fill_limit_order(Origin::signed(BOB), 0, 1_000_000),
+            Error::<Test>::LimitOrderExpired
+        );
+
+        let asset_balance = DexPallet::asset_balances(ALICE, FIRST_ASSET_ID);
+        assert_ok!(DexPallet::cancel_limit_order(Origin::signed(BOB), 0));
+        assert_eq!(
+            DexPallet::asset_balances(ALICE, FIRST_ASSET_ID),
+            asset_balance + 1_001_000
+        );
+    });
+}
+
+fn run_to_block(block_number: u64) {
+    while System::block_number() < block_number {
+        System::set_block_number(System::block_number() + 1);
+        DexPallet::on_initialize(System::block_number());
+    }
+}
+
+// Orders, still waiting in the queue of the block
+fn scheduled_orders(block_number: u64) -> Vec<ScheduledOrder> {
+    (0..DexPallet::scheduled_orders_count(block_number))
+        .filter_map(|position| DexPallet::scheduled_orders(block_number, position))
+        .collect()
+}
+
+// BOB buys FIRST_ASSET for 1_000_000 MAIN every 10 blocks three times, receiving at least 993_000 per execution
+fn create_dca_order() {
+    initialize_exchange(1_000_000_000, 1_000_000_000);
+    assert_ok!(DexPallet::create_dca_order(
+        Origin::signed(BOB),
+        MAIN,
+        1_000_000,
+        FIRST_ASSET,
+        993_000,
+        10,
+        3
+    ));
+}
+
+#[test]
+fn create_dca_order_fails() {
+    new_test_ext().execute_with(|| {
+        let create = |asset_in_amount, interval, executions| {
+            DexPallet::create_dca_order(
+                Origin::signed(BOB),
+                MAIN,
+                asset_in_amount,
+                FIRST_ASSET,
+                0,
+                interval,
+                executions,
+            )
+        };
+
+        assert_noop!(create(0, 10, 3), Error::<Test>::LowAssetAmount);
+        assert_noop!(create(1_000, 0, 3), Error::<Test>::InvalidDcaSchedule);
+        assert_noop!(create(1_000, 10, 0), Error::<Test>::InvalidDcaSchedule);
+        // Amounts of all executions are escrowed together with the deposit
+        assert_noop!(
+            create(INITIAL_BALANCE / 2 + 1, 10, 2),
+            Error::<Test>::InsufficientKsmBalance
+        );
+        assert_noop!(
+            create(INITIAL_BALANCE / 2 - 1, 10, 2),
+            Error::<Test>::InsufficientKsmBalance
+        );
+        assert_noop!(
+            DexPallet::create_dca_order(Origin::signed(BOB), MAIN, 1_000, MAIN, 0, 10, 3),
+            Error::<Test>::InvalidExchange
+        );
+    });
+}
+
+#[test]
+fn dca_order_is_executed_on_schedule() {
+    new_test_ext().execute_with(|| {
+        create_dca_order();
+        assert_eq!(
+            Balances::free_balance(BOB),
+            INITIAL_BALANCE - 3_000_000 - ScheduledOrderDeposit::get()
+        );
+        assert_eq!(scheduled_orders(1), vec![ScheduledOrder::Dca(0)]);
+
+        run_to_block(1);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+        assert_eq!(DexPallet::dca_orders(0).unwrap().remaining_executions, 2);
+        assert_eq!(scheduled_orders(11), vec![ScheduledOrder::Dca(0)]);
+
+        run_to_block(10);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+
+        run_to_block(11);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006 + 994_023
+        );
+
+        // Last execution would receive 992_045, so it is skipped and refunded together with the deposit
+        run_to_block(21);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006 + 994_023
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 2_000_000);
+        assert!(DexPallet::dca_orders(0).is_none());
+        assert!(scheduled_orders(31).is_empty());
+    });
+}
+
+#[test]
+fn dca_order_keeps_amounts_escrowed_until_owner_can_hold_them() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000_000, 1_000_000_000);
+        assert_ok!(DexPallet::create_dca_order(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            1_000_000,
+            MAIN,
+            u128::max_value(),
+            10,
+            1
+        ));
+        let balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+        AssetBalances::<Test>::insert(BOB, FIRST_ASSET_ID, u128::max_value() - 1);
+
+        // Refund of the skipped execution can not be held, so the execution is postponed
+        run_to_block(1);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            u128::max_value() - 1
+        );
+        assert_eq!(DexPallet::dca_orders(0).unwrap().remaining_executions, 1);
+        assert_eq!(scheduled_orders(11), vec![ScheduledOrder::Dca(0)]);
+
+        // Refund is credited, but the deposit can not be held, so the order keeps it
+        AssetBalances::<Test>::insert(BOB, FIRST_ASSET_ID, balance);
+        let main_balance = Balances::free_balance(BOB);
+        Balances::make_free_balance_be(&BOB, u128::max_value());
+        run_to_block(11);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            balance + 1_000_000
+        );
+        assert_eq!(DexPallet::dca_orders(0).unwrap().remaining_executions, 0);
+        assert_eq!(scheduled_orders(21), vec![ScheduledOrder::Dca(0)]);
+
+        Balances::make_free_balance_be(&BOB, main_balance);
+        run_to_block(21);
+        assert_eq!(
+            Balances::free_balance(BOB),
+            main_balance + ScheduledOrderDeposit::get()
+        );
+        assert!(DexPallet::dca_orders(0).is_none());
+    });
+}
+
+#[test]
+fn scheduled_orders_beyond_budget_are_deferred() {
+    new_test_ext().execute_with(|| {
+        initialize_exchange(1_000_000_000, 1_000_000_000);
+        for _ in 0..3 {
+            assert_ok!(DexPallet::create_dca_order(
+                Origin::signed(BOB),
+                MAIN,
+                1_000_000,
+                FIRST_ASSET,
+                0,
+                1,
+                1
+            ));
+        }
+
+        // Budget allows two executions per block, cursor stays at the deferred order
+        System::set_block_number(1);
+        assert_eq!(DexPallet::on_initialize(1), 2_625_000_000);
+        assert!(DexPallet::dca_orders(0).is_none());
+        assert!(DexPallet::dca_orders(1).is_none());
+        assert!(DexPallet::dca_orders(2).is_some());
+        assert_eq!(scheduled_orders(1), vec![ScheduledOrder::Dca(2)]);
+        assert_eq!(DexPallet::scheduled_orders_cursor(), Some((1, 2)));
+
+        // Deferred order is executed, then the cursor moves past the drained block
+        System::set_block_number(2);
+        assert_eq!(DexPallet::on_initialize(2), 1_500_000_000);
+        assert!(DexPallet::dca_orders(2).is_none());
+        assert_eq!(DexPallet::scheduled_orders_count(1), 0);
+        assert_eq!(DexPallet::scheduled_orders_cursor(), Some((2, 0)));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 3_000_000);
+
+        System::set_block_number(3);
+        assert_eq!(DexPallet::on_initialize(3), 375_000_000);
+        assert_eq!(DexPallet::scheduled_orders_cursor(), Some((4, 0)));
+    });
+}
+
+#[test]
+fn dca_order_can_be_cancelled() {
+    new_test_ext().execute_with(|| {
+        create_dca_order();
+        run_to_block(1);
+
+        assert_noop!(
+            DexPallet::cancel_dca_order(Origin::signed(ALICE), 0),
+            Error::<Test>::NotDcaOrderOwner
+        );
+        assert_noop!(
+            DexPallet::cancel_dca_order(Origin::signed(BOB), 1),
+            Error::<Test>::DcaOrderNotExists
+        );
+
+        // Refund and deposit are both main currency, so the balance has to hold them together
+        let balance = Balances::free_balance(BOB);
+        Balances::make_free_balance_be(
+            &BOB,
+            u128::max_value() - 2_000_000 - ScheduledOrderDeposit::get() + 1,
+        );
+        assert_noop!(
+            DexPallet::cancel_dca_order(Origin::signed(BOB), 0),
+            Error::<Test>::OverflowOccured
+        );
+        Balances::make_free_balance_be(&BOB, balance);
+
+        // Amounts of the remaining executions are refunded
+        assert_ok!(DexPallet::cancel_dca_order(Origin::signed(BOB), 0));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 1_000_000);
+        assert!(DexPallet::dca_orders(0).is_none());
+
+        run_to_block(11);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 1_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+    });
+}
+
+// BOB buys FIRST_ASSET for 3_000_001 MAIN in three slices, receiving at least the given amount in total
+fn create_twap_order(min_asset_out_amount: Balance) {
+    initialize_exchange(1_000_000_000, 1_000_000_000);
+    assert_ok!(DexPallet::create_twap_order(
+        Origin::signed(BOB),
+        MAIN,
+        3_000_001,
+        FIRST_ASSET,
+        min_asset_out_amount,
+        3
+    ));
+}
+
+#[test]
+fn create_twap_order_fails() {
+    new_test_ext().execute_with(|| {
+        let create = |asset_in_amount, slices| {
+            DexPallet::create_twap_order(
+                Origin::signed(BOB),
+                MAIN,
+                asset_in_amount,
+                FIRST_ASSET,
+                0,
+                slices,
+            )
+        };
+
+        assert_noop!(create(0, 3), Error::<Test>::LowAssetAmount);
+        assert_noop!(create(1_000, 0), Error::<Test>::InvalidTwapSchedule);
+        assert_noop!(create(2, 3), Error::<Test>::InvalidTwapSchedule);
+        assert_noop!(
+            create(INITIAL_BALANCE + 1, 3),
+            Error::<Test>::InsufficientKsmBalance
+        );
+        assert_noop!(
+            DexPallet::create_twap_order(Origin::signed(BOB), MAIN, 1_000, MAIN, 0, 3),
+            Error::<Test>::InvalidExchange
+        );
+    });
+}
+
+#[test]
+fn twap_order_is_executed_in_slices() {
+    new_test_ext().execute_with(|| {
+        create_twap_order(2_970_000);
+        assert_eq!(
+            Balances::free_balance(BOB),
+            INITIAL_BALANCE - 3_000_001 - ScheduledOrderDeposit::get()
+        );
+        assert_eq!(scheduled_orders(1), vec![ScheduledOrder::Twap(0)]);
+
+        run_to_block(1);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+        let twap_order = DexPallet::twap_orders(0).unwrap();
+        assert_eq!(twap_order.remaining_slices, 2);
+        assert_eq!(twap_order.remaining_amount, 2_000_001);
+        assert_eq!(scheduled_orders(2), vec![ScheduledOrder::Twap(0)]);
+
+        // Last slice includes the division remainder
+        run_to_block(3);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006 + 994_023 + 992_046
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 3_000_001);
+        assert!(DexPallet::twap_orders(0).is_none());
+        assert!(scheduled_orders(4).is_empty());
+    });
+}
+
+#[test]
+fn twap_slice_below_min_average_price_is_skipped() {
+    new_test_ext().execute_with(|| {
+        create_twap_order(2_980_000);
+
+        run_to_block(1);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+
+        // Second slice would receive 984_193 of required 990_661, so it is skipped and refunded
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(ALICE),
+            MAIN,
+            5_000_000,
+            FIRST_ASSET,
+            0,
+            ALICE
+        ));
+        run_to_block(2);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+        assert_eq!(
+            Balances::free_balance(BOB),
+            INITIAL_BALANCE - 2_000_001 - ScheduledOrderDeposit::get()
+        );
+
+        // Price recovers, so the last slice catches up with the average
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            10_000_000,
+            MAIN,
+            0,
+            ALICE
+        ));
+        run_to_block(3);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006 + 1_004_041
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 2_000_001);
+        assert!(DexPallet::twap_orders(0).is_none());
+    });
+}
+
+#[test]
+fn twap_and_dca_orders_share_scheduling_budget() {
+    new_test_ext().execute_with(|| {
+        create_twap_order(0);
+        for _ in 0..2 {
+            assert_ok!(DexPallet::create_dca_order(
+                Origin::signed(BOB),
+                MAIN,
+                1_000_000,
+                FIRST_ASSET,
+                0,
+                1,
+                1
+            ));
+        }
+
+        // Budget allows two executions per block
+        System::set_block_number(1);
+        assert_eq!(DexPallet::on_initialize(1), 2_625_000_000);
+        assert_eq!(DexPallet::twap_orders(0).unwrap().remaining_slices, 2);
+        assert!(DexPallet::dca_orders(0).is_none());
+        assert_eq!(scheduled_orders(1), vec![ScheduledOrder::Dca(1)]);
+        assert_eq!(scheduled_orders(2), vec![ScheduledOrder::Twap(0)]);
+
+        // Deferred orders are executed before the ones, due at the next block
+        System::set_block_number(2);
+        assert_eq!(DexPallet::on_initialize(2), 1_500_000_000);
+        assert!(DexPallet::dca_orders(1).is_none());
+        assert_eq!(DexPallet::twap_orders(0).unwrap().remaining_slices, 2);
+
+        System::set_block_number(3);
+        assert_eq!(DexPallet::on_initialize(3), 1_500_000_000);
+        assert_eq!(DexPallet::twap_orders(0).unwrap().remaining_slices, 1);
+    });
+}
+
+#[test]
+fn twap_order_can_be_cancelled() {
+    new_test_ext().execute_with(|| {
+        create_twap_order(0);
+        run_to_block(1);
+
+        assert_noop!(
+            DexPallet::cancel_twap_order(Origin::signed(ALICE), 0),
+            Error::<Test>::NotTwapOrderOwner
+        );
+        assert_noop!(
+            DexPallet::cancel_twap_order(Origin::signed(BOB), 1),
+            Error::<Test>::TwapOrderNotExists
+        );
+
+        // Refund and deposit are both main currency, so the balance has to hold them together
+        let refund = DexPallet::twap_orders(0).unwrap().remaining_amount;
+        let balance = Balances::free_balance(BOB);
+        Balances::make_free_balance_be(
+            &BOB,
+            u128::max_value() - refund - ScheduledOrderDeposit::get() + 1,
+        );
+        assert_noop!(
+            DexPallet::cancel_twap_order(Origin::signed(BOB), 0),
+            Error::<Test>::OverflowOccured
+        );
+        Balances::make_free_balance_be(&BOB, balance);
+
+        // Unswapped remainder is refunded
+        assert_ok!(DexPallet::cancel_twap_order(Origin::signed(BOB), 0));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 1_000_000);
+        assert!(DexPallet::twap_orders(0).is_none());
+
+        run_to_block(3);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE - 1_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+    });
+}
+
+// BOB sells 1_000_000 FIRST_ASSET (priced at 100 MAIN) for at least the given MAIN amount, once the condition is met
+fn place_conditional_order(
+    condition: TriggerCondition,
+    trigger_price: Balance,
+    min_asset_out_amount: Balance,
+) {
+    initialize_exchange(100_000_000_000, 1_000_000_000);
+    assert_ok!(DexPallet::place_conditional_order(
+        Origin::signed(BOB),
+        condition,
+        FIRST_ASSET,
+        1_000_000,
+        MAIN,
+        min_asset_out_amount,
+        trigger_price,
+        100
+    ));
+}
+
+#[test]
+fn place_conditional_order_fails() {
+    new_test_ext().execute_with(|| {
+        let place = |asset_in_amount, expiry| {
+            DexPallet::place_conditional_order(
+                Origin::signed(BOB),
+                TriggerCondition::StopLoss,
+                FIRST_ASSET,
+                asset_in_amount,
+                MAIN,
+                0,
+                95 * PRICE_ONE,
+                expiry,
+            )
+        };
+
+        assert_noop!(place(1_000, 100), Error::<Test>::ExchangeNotExists);
+
+        initialize_exchange(100_000_000_000, 1_000_000_000);
+        assert_noop!(place(0, 100), Error::<Test>::LowAssetAmount);
+        assert_noop!(place(1_000, 0), Error::<Test>::ConditionalOrderExpired);
+        assert_noop!(
+            place(INITIAL_BALANCE + 1, 100),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+        assert_noop!(
+            DexPallet::place_conditional_order(
+                Origin::signed(BOB),
+                TriggerCondition::StopLoss,
+                MAIN,
+                1_000,
+                MAIN,
+                0,
+                95 * PRICE_ONE,
+                100
+            ),
+            Error::<Test>::InvalidExchange
+        );
+    });
+}
+
+#[test]
+fn stop_loss_is_triggered_by_average_price() {
+    new_test_ext().execute_with(|| {
+        place_conditional_order(TriggerCondition::StopLoss, 95 * PRICE_ONE, 80_000_000);
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 1_000_000
+        );
+
+        Timestamp::set_timestamp(30);
+        assert_noop!(
+            DexPallet::trigger_conditional_order(Origin::signed(ALICE), 0),
+            Error::<Test>::TwapPeriodNotElapsed
+        );
+
+        // Average price is 100, so the new averaging window is started
+        Timestamp::set_timestamp(60);
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        let conditional_order = DexPallet::conditional_orders(0).unwrap();
+        assert_eq!(conditional_order.price_cumulative, 6_000 * PRICE_ONE);
+        assert_eq!(conditional_order.observed_at, 60);
+
+        // Spot price drops to 82 just before the check, keeping the average at 99
+        Timestamp::set_timestamp(119);
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(ALICE),
+            FIRST_ASSET,
+            100_000_000,
+            MAIN,
+            0,
+            ALICE
+        ));
+        Timestamp::set_timestamp(120);
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_eq!(DexPallet::conditional_orders(0).unwrap().observed_at, 120);
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+
+        // Price holds at 82 for the whole window
+        Timestamp::set_timestamp(180);
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 82_355_758);
+        assert!(DexPallet::conditional_orders(0).is_none());
+    });
+}
+
+#[test]
+fn conditional_order_window_beyond_maximum_is_restarted() {
+    new_test_ext().execute_with(|| {
+        // Average price of 100 triggers the order
+        place_conditional_order(TriggerCondition::StopLoss, 101 * PRICE_ONE, 80_000_000);
+
+        // Window is too long to be averaged
+        Timestamp::set_timestamp(MaxTwapWindow::get() + 1);
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_eq!(
+            DexPallet::conditional_orders(0).unwrap().observed_at,
+            u128::from(MaxTwapWindow::get() + 1)
+        );
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE);
+
+        Timestamp::set_timestamp(MaxTwapWindow::get() + 1 + TwapPeriod::get());
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert!(DexPallet::conditional_orders(0).is_none());
+    });
+}
+
+#[test]
+fn take_profit_respects_slippage_bound() {
+    new_test_ext().execute_with(|| {
+        place_conditional_order(TriggerCondition::TakeProfit, 110 * PRICE_ONE, 121_000_000);
+
+        // Price rises to 120
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(ALICE),
+            MAIN,
+            10_000_000_000,
+            FIRST_ASSET,
+            0,
+            ALICE
+        ));
+
+        // Order is triggered, but would receive 120_455_585
+        Timestamp::set_timestamp(60);
+        assert_noop!(
+            DexPallet::trigger_conditional_order(Origin::signed(ALICE), 0),
+            Error::<Test>::FirstAssetAmountBelowExpectation
+        );
+    });
+}
+
+#[test]
+fn take_profit_is_triggered_by_average_price() {
+    new_test_ext().execute_with(|| {
+        place_conditional_order(TriggerCondition::TakeProfit, 110 * PRICE_ONE, 120_000_000);
+        assert_ok!(DexPallet::swap_to_exact(
+            Origin::signed(ALICE),
+            MAIN,
+            10_000_000_000,
+            FIRST_ASSET,
+            0,
+            ALICE
+        ));
+
+        Timestamp::set_timestamp(60);
+        assert_ok!(DexPallet::trigger_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_eq!(Balances::free_balance(BOB), INITIAL_BALANCE + 120_455_585);
+        assert!(DexPallet::conditional_orders(0).is_none());
+    });
+}
+
+#[test]
+fn conditional_order_can_be_cancelled() {
+    new_test_ext().execute_with(|| {
+        place_conditional_order(TriggerCondition::StopLoss, 95 * PRICE_ONE, 80_000_000);
+
+        assert_noop!(
+            DexPallet::cancel_conditional_order(Origin::signed(ALICE), 0),
+            Error::<Test>::NotConditionalOrderOwner
+        );
+        assert_noop!(
+            DexPallet::cancel_conditional_order(Origin::signed(BOB), 1),
+            Error::<Test>::ConditionalOrderNotExists
+        );
+
+        // Expired order can not be triggered, but can be cancelled by anyone
+        System::set_block_number(101);
+        Timestamp::set_timestamp(60);
+        assert_noop!(
+            DexPallet::trigger_conditional_order(Origin::signed(ALICE), 0),
+            Error::<Test>::ConditionalOrderExpired
+        );
+        assert_ok!(DexPallet::cancel_conditional_order(
+            Origin::signed(ALICE),
+            0
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE
+        );
+        assert!(DexPallet::conditional_orders(0).is_none());
+    });
+}
+
+fn initialize_flash_swap_exchanges() {
+    initialize_exchange(1_000_000_000, 1_000_000_000);
+    assert_ok!(DexPallet::initialize_exchange(
+        Origin::signed(ALICE),
+        FIRST_ASSET,
+        1_000_000_000,
+        SECOND_ASSET,
+        1_000_000_000
+    ));
+}
+
+// Swap, dispatched by BOB as the flash swap callback
+fn swap_call(
+    asset_in: Asset<AssetId>,
+    asset_in_amount: Balance,
+    asset_out: Asset<AssetId>,
+) -> Box<Call> {
+    Box::new(Call::DexPallet(DexCall::swap_to_exact(
+        asset_in,
+        asset_in_amount,
+        asset_out,
+        0,
+        BOB,
+    )))
+}
+
+#[test]
+fn flash_swap_works() {
+    new_test_ext().execute_with(|| {
+        initialize_flash_swap_exchanges();
+
+        // BOB borrows FIRST_ASSET to swap it for SECOND_ASSET, the loan and the fee are repaid from the balance
+        assert_ok!(DexPallet::flash_swap(
+            Origin::signed(BOB),
+            FIRST_ASSET,
+            1_000_000,
+            MAIN,
+            swap_call(FIRST_ASSET, 1_000_000, SECOND_ASSET)
+        ));
+        assert_eq!(
+            DexPallet::asset_balances(BOB, FIRST_ASSET_ID),
+            INITIAL_BALANCE - 1_003_000
+        );
+        assert_eq!(
+            DexPallet::asset_balances(BOB, SECOND_ASSET_ID),
+            INITIAL_BALANCE + 996_006
+        );
+
+        // Fee is split between the pool and the treasury (which also receives the callback swap fee)
+        let exchange = exchange();
+        assert_eq!(exchange.first_asset_pool(), 1_000_000_000);
+        assert_eq!(exchange.second_asset_pool(), 1_000_001_500);
+        assert_eq!(
+            DexPallet::asset_balances(TREASURY, FIRST_ASSET_ID),
+            1_500 + 1_500
+        );
+        assert!(!DexPallet::locked_exchanges(MAIN, FIRST_ASSET));
+    });
+}
+
+#[test]
+fn flash_swap_fails() {
+    new_test_ext().execute_with(|| {
+        initialize_flash_swap_exchanges();
+        let flash_swap = |asset_amount| {
+            DexPallet::flash_swap(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                asset_amount,
+                MAIN,
+                swap_call(FIRST_ASSET, 1_000, SECOND_ASSET),
+            )
+        };
+
+        assert_noop!(flash_swap(0), Error::<Test>::LowAssetAmount);
+        assert_noop!(flash_swap(1_000_000_000), Error::<Test>::InsufficientPool);
+        assert_noop!(
+            DexPallet::flash_swap(
+                Origin::signed(BOB),
+                SECOND_ASSET,
+                1_000,
+                MAIN,
+                swap_call(FIRST_ASSET, 1_000, SECOND_ASSET)
+            ),
+            Error::<Test>::ExchangeNotExists
+        );
+    });
+}
+
+#[test]
+fn flash_swap_is_not_supported_by_stable_exchange() {
+    new_test_ext().execute_with(|| {
+        initialize_stable_exchange(100, 1_000_000_000, 1_000_000_000);
+
+        assert_noop!(
+            DexPallet::flash_swap(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000,
+                SECOND_ASSET,
+                swap_call(FIRST_ASSET, 1_000, SECOND_ASSET)
+            ),
+            Error::<Test>::FlashSwapNotSupported
+        );
+    });
+}
+
+#[test]
+fn flash_swap_locks_exchange() {
+    new_test_ext().execute_with(|| {
+        initialize_flash_swap_exchanges();
+
+        // Lent pools can not be traded against
+        assert_noop!(
+            DexPallet::flash_swap(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000_000,
+                MAIN,
+                swap_call(MAIN, 1_000, FIRST_ASSET)
+            ),
+            Error::<Test>::ExchangeLocked
+        );
+    });
+}
+
+#[test]
+fn flash_swap_is_reverted_without_repayment() {
+    new_test_ext().execute_with(|| {
+        initialize_flash_swap_exchanges();
+
+        // BOB swaps away all FIRST_ASSET, but the borrowed amount, so the fee can not be paid.
+        // Callback swap is reverted as well.
+        assert_noop!(
+            DexPallet::flash_swap(
+                Origin::signed(BOB),
+                FIRST_ASSET,
+                1_000_000,
+                MAIN,
+                swap_call(FIRST_ASSET, INITIAL_BALANCE, SECOND_ASSET)
+            ),
+            Error::<Test>::InsufficientOtherAssetBalance
+        );
+    });
+}
+
+proptest! {
+    #[test]
+    fn invariant_never_decreases_across_swaps(
+        main_pool in 1_000u128..1_000_000_000,
+        asset_pool in 1_000u128..1_000_000_000,
+        swaps in prop::collection::vec((any::<bool>(), 1u128..10_000_000), 1..20),
+    ) {
+        new_test_ext().execute_with(|| {
+            initialize_exchange(main_pool, asset_pool);
+
+            for (main_to_asset, amount) in swaps {
+                let invariant = exchange().invariant().unwrap();
+
+                let (asset_in, asset_out) = if main_to_asset {
+                    (MAIN, FIRST_ASSET)
+                } else {
+                    (FIRST_ASSET, MAIN)
+                };
+                // Swaps, which would drain the pool, are expected to fail
+                let _ = DexPallet::swap_to_exact(Origin::signed(BOB), asset_in, amount, asset_out, 0, BOB);
+
+                assert!(exchange().invariant().unwrap() >= invariant);
+            }
+        });
+    }
+
+    #[test]
+    fn stable_invariant_never_decreases_across_swaps(
+        amplification in 1u32..1_000,
+        first_pool in 1_000u128..1_000_000_000,
+        second_pool in 1_000u128..1_000_000_000,
+        swaps in prop::collection::vec((any::<bool>(), 1u128..10_000_000), 1..20),
+    ) {
+        new_test_ext().execute_with(|| {
+            initialize_stable_exchange(amplification, first_pool, second_pool);
+
+            for (first_to_second, amount) in swaps {
+                let invariant = stable_exchange().invariant().unwrap();
+
+                let (asset_in, asset_out) = if first_to_second {
+                    (FIRST_ASSET, SECOND_ASSET)
+                } else {
+                    (SECOND_ASSET, FIRST_ASSET)
+                };
+                let _ = DexPallet::swap_to_exact(Origin::signed(BOB), asset_in, amount, asset_out, 0, BOB);
+
+                // Newton's method converges within 1
+                assert!(stable_exchange().invariant().unwrap() + 1 >= invariant);
+            }
+        });
+    }
+
+    #[test]
+    fn weighted_swap_round_trip_creates_no_value(
+        main_weight in 1u32..99,
+        main_pool in 1_000u128..1_000_000_000,
+        asset_pool in 1_000u128..1_000_000_000,
+        amount in 1u128..1_000_000,
+    ) {
+        new_test_ext().execute_with(|| {
+            assert_ok!(DexPallet::create_weighted_pool(
+                Origin::signed(ALICE),
+                vec![(MAIN, main_weight, main_pool), (FIRST_ASSET, 100 - main_weight, asset_pool)]
+            ));
+
+            let main_balance = Balances::free_balance(BOB);
+            let asset_balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+
+            // Swaps beyond the ratio limits are expected to fail
+            if DexPallet::swap_exact_in_weighted(Origin::signed(BOB), 0, FIRST_ASSET, amount, MAIN, 0).is_ok() {
+                let main_amount = Balances::free_balance(BOB) - main_balance;
+                let _ = DexPallet::swap_exact_in_weighted(Origin::signed(BOB), 0, MAIN, main_amount, FIRST_ASSET, 0);
+            }
+
+            assert!(Balances::free_balance(BOB) <= main_balance);
+            assert!(DexPallet::asset_balances(BOB, FIRST_ASSET_ID) <= asset_balance);
+        });
+    }
+
+    #[test]
+    fn shares_are_conserved(
+        main_pool in 1_000u128..1_000_000_000,
+        asset_pool in 1_000u128..1_000_000_000,
+        operations in prop::collection::vec((any::<bool>(), any::<bool>(), 1u128..1_000_000), 1..20),
+    ) {
+        new_test_ext().execute_with(|| {
+            initialize_exchange(main_pool, asset_pool);
+
+            for (by_alice, invest, shares) in operations {
+                let who = if by_alice { ALICE } else { BOB };
+                let _ = if invest {
+                    DexPallet::invest_liquidity(Origin::signed(who), MAIN, FIRST_ASSET, shares)
+                } else {
+                    DexPallet::divest_liquidity(Origin::signed(who), MAIN, FIRST_ASSET, shares, 0, 0)
+                };
+
+                let exchange = exchange();
+                assert_eq!(
+                    exchange.total_shares,
+                    exchange.shares_of(&ALICE) + exchange.shares_of(&BOB)
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn invest_divest_round_trip_creates_no_value(
+        main_pool in 1_000u128..1_000_000_000,
+        asset_pool in 1_000u128..1_000_000_000,
+        swap_amount in 0u128..1_000_000,
+        shares in 1u128..1_000_000_000,
+    ) {
+        new_test_ext().execute_with(|| {
+            initialize_exchange(main_pool, asset_pool);
+            if swap_amount > 0 {
+                let _ = DexPallet::swap_to_exact(Origin::signed(ALICE), MAIN, swap_amount, FIRST_ASSET, 0, ALICE);
+            }
+
+            let main_balance = Balances::free_balance(BOB);
+            let asset_balance = DexPallet::asset_balances(BOB, FIRST_ASSET_ID);
+
+            if DexPallet::invest_liquidity(Origin::signed(BOB), MAIN, FIRST_ASSET, shares).is_ok() {
+                assert_ok!(DexPallet::divest_liquidity(
+                    Origin::signed(BOB),
+                    MAIN,
+                    FIRST_ASSET,
+                    shares,
+                    0,
+                    0
+                ));
+            }
+
+            assert!(Balances::free_balance(BOB) <= main_balance);
+            assert!(DexPallet::asset_balances(BOB, FIRST_ASSET_ID) <= asset_balance);
+        });
+    }
+
+    #[test]
+    fn concentrated_positions_can_always_be_closed(
+        initial_tick in -50i32..50,
+        positions in prop::collection::vec((-300i32..300, 1i32..300, 1_000u128..1_000_000_000_000), 1..4),
+        swaps in prop::collection::vec((any::<bool>(), 1u128..1_000_000_000), 1..20),
+    ) {
+        new_test_ext().execute_with(|| {
+            assert_ok!(DexPallet::create_concentrated_pool(
+                Origin::signed(ALICE),
+                FIRST_ASSET,
+                SECOND_ASSET,
+                10,
+                initial_tick * 10
+            ));
+            for (lower_tick, width, liquidity) in positions.iter() {
+                assert_ok!(DexPallet::mint_concentrated_position(
+                    Origin::signed(ALICE),
+                    FIRST_ASSET,
+                    SECOND_ASSET,
+                    lower_tick * 10,
+                    (lower_tick + width) * 10,
+                    *liquidity,
+                    Balance::MAX,
+                    Balance::MAX
+                ));
+            }
+
+            for (first_to_second, amount) in swaps {
+                let (asset_in, asset_out) = if first_to_second {
+                    (FIRST_ASSET, SECOND_ASSET)
+                } else {
+                    (SECOND_ASSET, FIRST_ASSET)
+                };
+                // Swaps beyond the provided liquidity are expected to fail
+                let _ = DexPallet::swap_concentrated(Origin::signed(BOB), asset_in, amount, asset_out, 0);
+            }
+
+            // Pools cover the principal and fees of all positions
+            for (position_id, (_, _, liquidity)) in positions.iter().enumerate() {
+                let position_id = position_id as u64;
+                assert_ok!(DexPallet::burn_concentrated_position(
+                    Origin::signed(ALICE),
+                    FIRST_ASSET,
+                    SECOND_ASSET,
+                    position_id,
+                    *liquidity,
+                    0,
+                    0
+                ));
+                assert_ok!(DexPallet::collect_fees(Origin::signed(ALICE), FIRST_ASSET, SECOND_ASSET, position_id));
+            }
+            assert_eq!(concentrated_pool().liquidity, 0);
+        });
+    }
+}
@@ -0,0 +1,97 @@
+use super::*;
+use sp_runtime::traits::Saturating;
+
+/// Large swap of escrowed `asset_in_amount` of `asset_in` for `asset_out`, executed in equal slices
+/// over consecutive blocks. Slice, which would bring the average price of the order below
+/// `min_asset_out_amount` / `asset_in_amount`, is skipped and refunded.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct TwapOrder<T: Trait> {
+    pub owner: T::AccountId,
+    pub asset_in: Asset<T::AssetId>,
+    pub asset_out: Asset<T::AssetId>,
+    pub asset_in_amount: BalanceOf<T>,
+    pub min_asset_out_amount: BalanceOf<T>,
+    // the last slice also includes the division remainder
+    pub slice_amount: BalanceOf<T>,
+    pub remaining_slices: u32,
+    // escrowed amount of the remaining slices
+    pub remaining_amount: BalanceOf<T>,
+    // totals of the executed slices
+    pub swapped_amount: BalanceOf<T>,
+    pub received_amount: BalanceOf<T>,
+    // main currency, returned with the last execution or on cancellation
+    pub deposit: BalanceOf<T>,
+}
+
+impl<T: Trait> TwapOrder<T> {
+    pub fn new(
+        owner: T::AccountId,
+        asset_in: Asset<T::AssetId>,
+        asset_in_amount: BalanceOf<T>,
+        asset_out: Asset<T::AssetId>,
+        min_asset_out_amount: BalanceOf<T>,
+        slices: u32,
+        deposit: BalanceOf<T>,
+    ) -> Result<Self, Error<T>> {
+        ensure!(
+            asset_in_amount > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+        let slice_amount = asset_in_amount
+            .checked_div(&BalanceOf::<T>::from(slices))
+            .unwrap_or_else(BalanceOf::<T>::zero);
+        ensure!(
+            slice_amount > BalanceOf::<T>::zero(),
+            Error::<T>::InvalidTwapSchedule
+        );
+
+        Ok(Self {
+            owner,
+            asset_in,
+            asset_out,
+            asset_in_amount,
+            min_asset_out_amount,
+            slice_amount,
+            remaining_slices: slices,
+            remaining_amount: asset_in_amount,
+            swapped_amount: BalanceOf::<T>::zero(),
+            received_amount: BalanceOf::<T>::zero(),
+            deposit,
+        })
+    }
+
+    /// Take the next slice from the escrow.
+    /// Returns its amount and the minimum `asset_out` amount, keeping the average price above the limit.
+    pub fn take_slice(&mut self) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+        let slice_amount = if self.remaining_slices == 1 {
+            self.remaining_amount
+        } else {
+            self.slice_amount
+        };
+
+        let swapped_amount = self
+            .swapped_amount
+            .checked_add(&slice_amount)
+            .ok_or(Error::<T>::OverflowOccured)?;
+        let min_received_amount = math::div_ceil::<T>(
+            math::product::<T>(swapped_amount, self.min_asset_out_amount)?,
+            math::to_u256::<T>(self.asset_in_amount)?,
+        )?;
+
+        // Can not underflow, as slices do not exceed the remaining amount
+        self.remaining_slices -= 1;
+        self.remaining_amount -= slice_amount;
+        Ok((
+            slice_amount,
+            min_received_amount.saturating_sub(self.received_amount),
+        ))
+    }
+
+    /// Record the executed slice
+    pub fn record_slice(&mut self, slice_amount: BalanceOf<T>, asset_out_amount: BalanceOf<T>) {
+        // Totals do not exceed the order amount and the received balance
+        self.swapped_amount = self.swapped_amount.saturating_add(slice_amount);
+        self.received_amount = self.received_amount.saturating_add(asset_out_amount);
+    }
+}
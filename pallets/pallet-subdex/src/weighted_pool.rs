@@ -0,0 +1,450 @@
+use super::*;
+use math::ONE;
+use sp_core::U256;
+
+/// Bounds of the number of assets in the weighted pool
+pub const MIN_WEIGHTED_POOL_ASSETS: usize = 2;
+pub const MAX_WEIGHTED_POOL_ASSETS: usize = 8;
+
+/// Weights are relative, asset share of the pool value is its weight divided by the total weight
+pub const MIN_WEIGHT: u32 = 1;
+pub const MAX_TOTAL_WEIGHT: u32 = 100;
+
+/// Shares, minted for the creator of the weighted pool (100 * 10^18)
+pub const INITIAL_WEIGHTED_POOL_SHARES: u128 = 100_000_000_000_000_000_000;
+
+// Amount in can not exceed 1 / MAX_IN_RATIO of the asset pool
+const MAX_IN_RATIO: u32 = 2;
+
+// Amount out can not exceed 1 / MAX_OUT_RATIO of the asset pool
+const MAX_OUT_RATIO: u32 = 3;
+
+/// Asset of the weighted pool
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug, Default)]
+pub struct WeightedAsset<AssetId: Default + Debug + Ord + Copy, Balance> {
+    pub asset: Asset<AssetId>,
+    pub weight: u32,
+    pub pool: Balance,
+}
+
+/// Structure, representing Balancer-style pool of two to eight assets with custom weights.
+/// Swaps keep the weighted product of the pools (product of pool ^ (weight / total weight)) from decreasing.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct WeightedPool<T: Trait> {
+    pub assets: Vec<WeightedAsset<T::AssetId, BalanceOf<T>>>,
+    // total pool shares
+    pub total_shares: BalanceOf<T>,
+    // individual shares
+    shares: BTreeMap<T::AccountId, BalanceOf<T>>,
+}
+
+impl<T: Trait> WeightedPool<T> {
+    /// Create pool of the given (asset, weight, amount) entries, all shares are minted for the sender.
+    pub fn initialize_new(
+        assets: Vec<(Asset<T::AssetId>, u32, BalanceOf<T>)>,
+        sender: T::AccountId,
+    ) -> Result<(Self, BalanceOf<T>), Error<T>> {
+        ensure!(
+            assets.len() >= MIN_WEIGHTED_POOL_ASSETS && assets.len() <= MAX_WEIGHTED_POOL_ASSETS,
+            Error::<T>::InvalidWeightedPool
+        );
+
+        for (index, (asset, _, amount)) in assets.iter().enumerate() {
+            ensure!(
+                !assets[..index].iter().any(|(other, _, _)| other == asset),
+                Error::<T>::InvalidWeightedPool
+            );
+            ensure!(*amount > BalanceOf::<T>::zero(), Error::<T>::LowAssetAmount);
+        }
+        Self::ensure_valid_weights(assets.iter().map(|(_, weight, _)| *weight))?;
+
+        let initial_shares = math::from_u256::<T>(U256::from(INITIAL_WEIGHTED_POOL_SHARES))?;
+        let mut shares = BTreeMap::new();
+        shares.insert(sender, initial_shares);
+
+        let weighted_pool = Self {
+            assets: assets
+                .into_iter()
+                .map(|(asset, weight, pool)| WeightedAsset {
+                    asset,
+                    weight,
+                    pool,
+                })
+                .collect(),
+            total_shares: initial_shares,
+            shares,
+        };
+        Ok((weighted_pool, initial_shares))
+    }
+
+    /// Every weight is at least MIN_WEIGHT, their sum is at most MAX_TOTAL_WEIGHT.
+    pub fn ensure_valid_weights(weights: impl Iterator<Item = u32>) -> Result<(), Error<T>> {
+        let mut total_weight: u32 = 0;
+        for weight in weights {
+            ensure!(weight >= MIN_WEIGHT, Error::<T>::InvalidWeight);
+            total_weight = total_weight
+                .checked_add(weight)
+                .ok_or(Error::<T>::InvalidWeight)?;
+        }
+        ensure!(total_weight <= MAX_TOTAL_WEIGHT, Error::<T>::InvalidWeight);
+        Ok(())
+    }
+
+    /// Replace weights of the pool assets (ordered as the assets). Only their ratios affect the pool math,
+    /// so the weights can be scaled for the finer precision.
+    pub fn set_weights(&mut self, weights: Vec<u32>) {
+        for (weighted_asset, weight) in self.assets.iter_mut().zip(weights) {
+            weighted_asset.weight = weight;
+        }
+    }
+
+    /// Position of the asset in the pool.
+    pub fn asset_index(&self, asset: Asset<T::AssetId>) -> Result<usize, Error<T>> {
+        self.assets
+            .iter()
+            .position(|weighted_asset| weighted_asset.asset == asset)
+            .ok_or(Error::<T>::AssetNotInWeightedPool)
+    }
+
+    pub fn total_weight(&self) -> u32 {
+        self.assets
+            .iter()
+            .map(|weighted_asset| weighted_asset.weight)
+            .sum()
+    }
+
+    /// Shares, owned by the given account.
+    pub fn shares_of(&self, who: &T::AccountId) -> BalanceOf<T> {
+        self.shares.get(who).copied().unwrap_or_default()
+    }
+
+    /// Swap exact `amount_in` of the asset at `index_in` for the asset at `index_out`.
+    /// Returns the amount out and the treasury fee data.
+    pub fn swap_exact_in(
+        &mut self,
+        index_in: usize,
+        index_out: usize,
+        amount_in: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        ensure!(index_in != index_out, Error::<T>::InvalidExchange);
+        let (weight_in, pool_in) = self.weight_and_pool(index_in)?;
+        let (weight_out, pool_out) = self.weight_and_pool(index_out)?;
+        Self::ensure_ratio(
+            amount_in,
+            pool_in,
+            MAX_IN_RATIO,
+            Error::<T>::MaxInRatioExceeded,
+        )?;
+
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(amount_in)?;
+        let treasury_fee = Self::treasury_fee(&treasury_fee_data);
+        let adjusted_amount_in =
+            math::to_u256::<T>(Self::sub(amount_in, exchange_fee + treasury_fee)?)?;
+
+        // amount_out = pool_out * (1 - (pool_in / (pool_in + adjusted_amount_in)) ^ (weight_in / weight_out))
+        let pool_in = math::to_u256::<T>(pool_in)?;
+        let weight_ratio = math::div_fixed::<T>(weight_in.into(), weight_out.into())?;
+        let base = math::div_fixed::<T>(pool_in, pool_in + adjusted_amount_in)?;
+        let power = math::pow_fixed::<T>(base, weight_ratio)?;
+        // Rounded down in favor of the pool
+        let amount_out = math::to_u256::<T>(pool_out)?
+            .checked_mul(ONE.saturating_sub(power))
+            .ok_or(Error::<T>::OverflowOccured)?
+            / ONE;
+        let amount_out = math::from_u256::<T>(amount_out)?;
+        ensure!(amount_out < pool_out, Error::<T>::InsufficientPool);
+
+        self.assets[index_in].pool = Self::add(
+            self.assets[index_in].pool,
+            Self::sub(amount_in, treasury_fee)?,
+        )?;
+        self.assets[index_out].pool = pool_out - amount_out;
+        Ok((amount_out, treasury_fee_data))
+    }
+
+    /// Swap the asset at `index_in` for exact `amount_out` of the asset at `index_out`.
+    /// Returns the amount in and the treasury fee data.
+    pub fn swap_exact_out(
+        &mut self,
+        index_in: usize,
+        index_out: usize,
+        amount_out: BalanceOf<T>,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        ensure!(index_in != index_out, Error::<T>::InvalidExchange);
+        let (weight_in, pool_in) = self.weight_and_pool(index_in)?;
+        let (weight_out, pool_out) = self.weight_and_pool(index_out)?;
+        ensure!(
+            amount_out > BalanceOf::<T>::zero(),
+            Error::<T>::LowAssetAmount
+        );
+        Self::ensure_ratio(
+            amount_out,
+            pool_out,
+            MAX_OUT_RATIO,
+            Error::<T>::MaxOutRatioExceeded,
+        )?;
+
+        // adjusted_amount_in = pool_in * ((pool_out / (pool_out - amount_out)) ^ (weight_out / weight_in) - 1)
+        let weight_ratio = math::div_fixed::<T>(weight_out.into(), weight_in.into())?;
+        let base = math::div_fixed::<T>(
+            math::to_u256::<T>(pool_out)?,
+            math::to_u256::<T>(pool_out - amount_out)?,
+        )?;
+        let power = math::pow_fixed::<T>(base, weight_ratio)?;
+        // Rounded up in favor of the pool
+        let adjusted_amount_in = math::div_ceil::<T>(
+            math::to_u256::<T>(pool_in)?
+                .checked_mul(power.saturating_sub(ONE))
+                .ok_or(Error::<T>::OverflowOccured)?,
+            ONE,
+        )?;
+
+        // Gross up by the fee rate, so that the fee is charged from the amount in
+        let fee_rate_denominator = math::to_u256::<T>(T::FeeRateDenominator::get())?;
+        let fee_rate_complement = fee_rate_denominator
+            .checked_sub(math::to_u256::<T>(T::FeeRateNominator::get())?)
+            .ok_or(Error::<T>::UnderflowOccured)?;
+        let amount_in = math::div_ceil::<T>(
+            math::to_u256::<T>(adjusted_amount_in)?
+                .checked_mul(fee_rate_denominator)
+                .ok_or(Error::<T>::OverflowOccured)?,
+            fee_rate_complement,
+        )?;
+        Self::ensure_ratio(
+            amount_in,
+            pool_in,
+            MAX_IN_RATIO,
+            Error::<T>::MaxInRatioExceeded,
+        )?;
+
+        let (_, treasury_fee_data) = <Module<T>>::swap_fees(amount_in)?;
+        let treasury_fee = Self::treasury_fee(&treasury_fee_data);
+
+        self.assets[index_in].pool = Self::add(pool_in, Self::sub(amount_in, treasury_fee)?)?;
+        self.assets[index_out].pool = pool_out - amount_out;
+        Ok((amount_in, treasury_fee_data))
+    }
+
+    /// Mint `shares` for the sender, depositing the proportional amount of every pool asset.
+    /// Returns deposited amounts, ordered as the pool assets.
+    pub fn join(
+        &mut self,
+        shares: BalanceOf<T>,
+        sender: &T::AccountId,
+    ) -> Result<Vec<BalanceOf<T>>, Error<T>> {
+        ensure!(shares > BalanceOf::<T>::zero(), Error::<T>::InvalidShares);
+
+        // Rounded up, so that join/exit round trip can not create value
+        let total_shares = math::to_u256::<T>(self.total_shares)?;
+        let amounts = self
+            .assets
+            .iter()
+            .map(|weighted_asset| {
+                math::div_ceil::<T>(
+                    math::product::<T>(shares, weighted_asset.pool)?,
+                    total_shares,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (weighted_asset, amount) in self.assets.iter_mut().zip(amounts.iter()) {
+            weighted_asset.pool = Self::add(weighted_asset.pool, *amount)?;
+        }
+        self.mint_shares(sender, shares)?;
+        Ok(amounts)
+    }
+
+    /// Burn `shares` of the sender, withdrawing the proportional amount of every pool asset.
+    /// Returns withdrawn amounts, ordered as the pool assets.
+    pub fn exit(
+        &mut self,
+        shares: BalanceOf<T>,
+        sender: &T::AccountId,
+    ) -> Result<Vec<BalanceOf<T>>, Error<T>> {
+        self.ensure_burned_shares(sender, shares)?;
+
+        // Rounded down, so that join/exit round trip can not create value
+        let amounts = self
+            .assets
+            .iter()
+            .map(|weighted_asset| {
+                math::mul_div::<T>(shares, weighted_asset.pool, self.total_shares)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (weighted_asset, amount) in self.assets.iter_mut().zip(amounts.iter()) {
+            weighted_asset.pool = Self::sub(weighted_asset.pool, *amount)?;
+        }
+        self.burn_shares(sender, shares)?;
+        Ok(amounts)
+    }
+
+    /// Deposit `amount_in` of the asset at `index` only. The fee is charged from the part of the amount,
+    /// which is implicitly swapped for the other pool assets.
+    /// Returns the minted shares and the treasury fee data.
+    pub fn join_single_asset(
+        &mut self,
+        index: usize,
+        amount_in: BalanceOf<T>,
+        sender: &T::AccountId,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        let (weight, pool_in) = self.weight_and_pool(index)?;
+        Self::ensure_ratio(
+            amount_in,
+            pool_in,
+            MAX_IN_RATIO,
+            Error::<T>::MaxInRatioExceeded,
+        )?;
+
+        let total_weight = self.total_weight();
+        let swapped_amount = math::mul_div::<T>(
+            amount_in,
+            (total_weight - weight).into(),
+            total_weight.into(),
+        )?;
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(swapped_amount)?;
+        let treasury_fee = Self::treasury_fee(&treasury_fee_data);
+        let adjusted_amount_in =
+            math::to_u256::<T>(Self::sub(amount_in, exchange_fee + treasury_fee)?)?;
+
+        // shares = total_shares * (((pool_in + adjusted_amount_in) / pool_in) ^ (weight / total_weight) - 1)
+        let pool_in_u256 = math::to_u256::<T>(pool_in)?;
+        let normalized_weight = math::div_fixed::<T>(weight.into(), total_weight.into())?;
+        let base = math::div_fixed::<T>(pool_in_u256 + adjusted_amount_in, pool_in_u256)?;
+        let power = math::pow_fixed::<T>(base, normalized_weight)?;
+        // Rounded down in favor of the pool
+        let shares = math::to_u256::<T>(self.total_shares)?
+            .checked_mul(power.saturating_sub(ONE))
+            .ok_or(Error::<T>::OverflowOccured)?
+            / ONE;
+        let shares = math::from_u256::<T>(shares)?;
+        ensure!(shares > BalanceOf::<T>::zero(), Error::<T>::InvalidShares);
+
+        self.assets[index].pool = Self::add(pool_in, Self::sub(amount_in, treasury_fee)?)?;
+        self.mint_shares(sender, shares)?;
+        Ok((shares, treasury_fee_data))
+    }
+
+    /// Burn `shares` of the sender, withdrawing the asset at `index` only. The fee is charged from the part
+    /// of the amount, which is implicitly swapped from the other pool assets.
+    /// Returns the withdrawn amount and the treasury fee data.
+    pub fn exit_single_asset(
+        &mut self,
+        index: usize,
+        shares: BalanceOf<T>,
+        sender: &T::AccountId,
+    ) -> Result<(BalanceOf<T>, Option<(BalanceOf<T>, T::AccountId)>), Error<T>> {
+        self.ensure_burned_shares(sender, shares)?;
+        let (weight, pool_out) = self.weight_and_pool(index)?;
+        // Bounds the power base, so that the approximation converges quickly.
+        // The last shares can only be burned proportionally.
+        Self::ensure_ratio(
+            shares,
+            self.total_shares,
+            MAX_OUT_RATIO,
+            Error::<T>::MaxOutRatioExceeded,
+        )?;
+
+        // amount_out = pool_out * (1 - ((total_shares - shares) / total_shares) ^ (total_weight / weight))
+        let total_weight = self.total_weight();
+        let total_shares = math::to_u256::<T>(self.total_shares)?;
+        let exponent = math::div_fixed::<T>(total_weight.into(), weight.into())?;
+        let base = math::div_fixed::<T>(total_shares - math::to_u256::<T>(shares)?, total_shares)?;
+        let power = math::pow_fixed::<T>(base, exponent)?;
+        // Rounded down in favor of the pool
+        let amount_out_before_fee = math::to_u256::<T>(pool_out)?
+            .checked_mul(ONE.saturating_sub(power))
+            .ok_or(Error::<T>::OverflowOccured)?
+            / ONE;
+        let amount_out_before_fee = math::from_u256::<T>(amount_out_before_fee)?;
+        Self::ensure_ratio(
+            amount_out_before_fee,
+            pool_out,
+            MAX_OUT_RATIO,
+            Error::<T>::MaxOutRatioExceeded,
+        )?;
+
+        let swapped_amount = math::mul_div::<T>(
+            amount_out_before_fee,
+            (total_weight - weight).into(),
+            total_weight.into(),
+        )?;
+        let (exchange_fee, treasury_fee_data) = <Module<T>>::swap_fees(swapped_amount)?;
+        let treasury_fee = Self::treasury_fee(&treasury_fee_data);
+        let amount_out = Self::sub(amount_out_before_fee, exchange_fee + treasury_fee)?;
+
+        // Exchange fee stays in the pool, treasury fee is paid out of it
+        self.assets[index].pool = Self::sub(pool_out, amount_out + treasury_fee)?;
+        self.burn_shares(sender, shares)?;
+        Ok((amount_out, treasury_fee_data))
+    }
+
+    pub fn ensure_burned_shares(
+        &self,
+        sender: &T::AccountId,
+        shares_burned: BalanceOf<T>,
+    ) -> Result<(), Error<T>> {
+        ensure!(
+            shares_burned > BalanceOf::<T>::zero(),
+            Error::<T>::InvalidShares
+        );
+        let shares = self.shares.get(sender).ok_or(Error::<T>::DoesNotOwnShare)?;
+        ensure!(*shares >= shares_burned, Error::<T>::InsufficientShares);
+        Ok(())
+    }
+
+    fn mint_shares(&mut self, who: &T::AccountId, shares: BalanceOf<T>) -> Result<(), Error<T>> {
+        let updated_shares = Self::add(self.shares_of(who), shares)?;
+        self.shares.insert(who.clone(), updated_shares);
+        self.total_shares = Self::add(self.total_shares, shares)?;
+        Ok(())
+    }
+
+    fn burn_shares(&mut self, who: &T::AccountId, shares: BalanceOf<T>) -> Result<(), Error<T>> {
+        let remaining_shares = Self::sub(self.shares_of(who), shares)?;
+        if remaining_shares == BalanceOf::<T>::zero() {
+            self.shares.remove(who);
+        } else {
+            self.shares.insert(who.clone(), remaining_shares);
+        }
+        self.total_shares = Self::sub(self.total_shares, shares)?;
+        Ok(())
+    }
+
+    fn weight_and_pool(&self, index: usize) -> Result<(u32, BalanceOf<T>), Error<T>> {
+        self.assets
+            .get(index)
+            .map(|weighted_asset| (weighted_asset.weight, weighted_asset.pool))
+            .ok_or(Error::<T>::AssetNotInWeightedPool)
+    }
+
+    // amount * ratio <= pool
+    fn ensure_ratio(
+        amount: BalanceOf<T>,
+        pool: BalanceOf<T>,
+        ratio: u32,
+        error: Error<T>,
+    ) -> Result<(), Error<T>> {
+        ensure!(
+            math::to_u256::<T>(amount)? * ratio <= math::to_u256::<T>(pool)?,
+            error
+        );
+        Ok(())
+    }
+
+    fn treasury_fee(treasury_fee_data: &Option<(BalanceOf<T>, T::AccountId)>) -> BalanceOf<T> {
+        treasury_fee_data
+            .as_ref()
+            .map_or_else(BalanceOf::<T>::zero, |(treasury_fee, _)| *treasury_fee)
+    }
+
+    fn add(a: BalanceOf<T>, b: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
+        a.checked_add(&b).ok_or(Error::<T>::OverflowOccured)
+    }
+
+    fn sub(a: BalanceOf<T>, b: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
+        a.checked_sub(&b).ok_or(Error::<T>::UnderflowOccured)
+    }
+}
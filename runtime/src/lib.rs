@@ -22,7 +22,7 @@
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
-use pallet_subdex_xcmp::XCMPMessage;
+use pallet_subdex_xcmp::VersionedXCMPMessage;
 use sp_api::impl_runtime_apis;
 use sp_core::OpaqueMetadata;
 use sp_runtime::{
@@ -41,7 +41,10 @@ use sp_version::RuntimeVersion;
 pub use frame_support::{
     construct_runtime, parameter_types,
     traits::{Get, KeyOwnerProofSystem, Randomness},
-    weights::{constants::WEIGHT_PER_SECOND, IdentityFee, Weight},
+    weights::{
+        constants::{RocksDbWeight, WEIGHT_PER_SECOND},
+        IdentityFee, Weight,
+    },
     StorageValue,
 };
 
@@ -110,10 +113,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
     spec_name: create_runtime_str!("wasm-test-parachain"),
     impl_name: create_runtime_str!("wasm-test-parachain"),
     authoring_version: 3,
-    spec_version: 4,
+    spec_version: 5,
     impl_version: 4,
     apis: RUNTIME_API_VERSIONS,
-    transaction_version: 1,
+    transaction_version: 2,
 };
 
 pub const MILLISECS_PER_BLOCK: u64 = 3000;
@@ -187,7 +190,7 @@ impl frame_system::Trait for Runtime {
     type AccountData = pallet_balances::AccountData<Balance>;
     type OnNewAccount = ();
     type OnKilledAccount = ();
-    type DbWeight = ();
+    type DbWeight = RocksDbWeight;
     type ExtrinsicBaseWeight = ExtrinsicBaseWeight;
     type BlockExecutionWeight = ();
     type MaximumExtrinsicWeight = MaximumExtrinsicWeight;
@@ -252,21 +255,44 @@ impl cumulus_message_broker::Trait for Runtime {
     type DownwardMessageHandlers = DexXCMP;
     type UpwardMessage = cumulus_upward_message::RococoUpwardMessage;
     type ParachainId = ParachainId;
-    type XCMPMessage = XCMPMessage<AccountId, Balance, AssetId>;
+    type XCMPMessage = VersionedXCMPMessage<AccountId, Balance, AssetId>;
     type XCMPMessageHandlers = DexXCMP;
 }
 
+parameter_types! {
+    pub const MaxPriceQueriesPerBlock: u32 = 10;
+    pub const TransferTimeout: BlockNumber = 10 * MINUTES;
+    // Timeout lasts at least 5 minutes of the minimum block periods, leaving a minute for the clock drift
+    pub const TransferDeliveryPeriod: u64 = 4 * 60_000;
+    pub const MaxPendingTransfersPerAccount: u32 = 16;
+    pub const MaxTrackedTransfersPerBlock: u32 = 64;
+}
+
 impl pallet_subdex_xcmp::Trait for Runtime {
     type Event = Event;
     type UpwardMessageSender = MessageBroker;
     type UpwardMessage = cumulus_upward_message::RococoUpwardMessage;
     type XCMPMessageSender = MessageBroker;
+    type MaxPriceQueriesPerBlock = MaxPriceQueriesPerBlock;
+    type TransferTimeout = TransferTimeout;
+    type TransferDeliveryPeriod = TransferDeliveryPeriod;
+    type MaxPendingTransfersPerAccount = MaxPendingTransfersPerAccount;
+    type MaxTrackedTransfersPerBlock = MaxTrackedTransfersPerBlock;
 }
 
 parameter_types! {
     // 3/1000
     pub const FeeRateNominator: Balance = 3;
     pub const FeeRateDenominator: Balance = 1000;
+    // 100 scheduled executions per block
+    pub MaxScheduledWeight: Weight = 100 * DexPallet::scheduled_execution_weight();
+    // 100 existential deposits
+    pub const ScheduledOrderDeposit: Balance = 50_000;
+    // 10 minutes, in milliseconds
+    pub const TwapPeriod: u64 = 10 * 60_000;
+    // 1 day, in milliseconds
+    pub const MaxTwapWindow: u64 = 24 * 60 * 60_000;
+    pub const MaxTicksCrossed: u32 = 64;
 }
 
 impl pallet_subdex::Trait for Runtime {
@@ -276,6 +302,12 @@ impl pallet_subdex::Trait for Runtime {
     type AssetId = AssetId;
     type FeeRateNominator = FeeRateNominator;
     type FeeRateDenominator = FeeRateDenominator;
+    type MaxScheduledWeight = MaxScheduledWeight;
+    type ScheduledOrderDeposit = ScheduledOrderDeposit;
+    type TwapPeriod = TwapPeriod;
+    type MaxTwapWindow = MaxTwapWindow;
+    type MaxTicksCrossed = MaxTicksCrossed;
+    type Call = Call;
 }
 
 construct_runtime! {
@@ -389,6 +421,25 @@ impl_runtime_apis! {
         }
     }
 
+    impl pallet_subdex_runtime_api::DexApi<Block, AssetId, Balance> for Runtime {
+        fn quote(asset_in: Option<AssetId>, asset_in_amount: Balance, asset_out: Option<AssetId>) -> Option<Balance> {
+            let asset = |asset_id: Option<AssetId>| {
+                asset_id.map_or(pallet_subdex::Asset::MainNetworkCurrency, pallet_subdex::Asset::ParachainAsset)
+            };
+            DexPallet::quote(asset(asset_in), asset_in_amount, asset(asset_out)).ok()
+        }
+    }
+
+    impl pallet_subdex_xcmp_runtime_api::DexXCMPApi<Block, AssetId, Balance> for Runtime {
+        fn backed_amount(para_id: u32, para_asset_id: Option<AssetId>) -> Balance {
+            DexXCMP::backed_amount(para_id, para_asset_id)
+        }
+
+        fn backing_ledger() -> Vec<(u32, Option<AssetId>, Balance)> {
+            DexXCMP::backing_ledger()
+        }
+    }
+
     impl sp_session::SessionKeys<Block> for Runtime {
         fn decode_session_keys(
             encoded: Vec<u8>,